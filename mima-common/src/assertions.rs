@@ -0,0 +1,198 @@
+use crate::types::{MimaAddress, MimaValue, parse_mima_number};
+use crate::runtime::Runtime;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssertionTarget {
+    Accu,
+    Memory(MimaAddress)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge
+}
+
+// a condition on the accumulator or a memory cell, checked when execution reaches
+// location or, without a location, when the machine halts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assertion {
+    pub location: Option<MimaAddress>,
+    pub target: AssertionTarget,
+    pub comparison: Comparison,
+    pub value: MimaValue,
+    // the assertion as it was written in the source, used in failure messages
+    pub description: String
+}
+
+impl Comparison {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">="
+        }
+    }
+
+    // compares two values as signed numbers
+    pub fn holds(&self, actual: i64, expected: i64) -> bool {
+        match self {
+            Comparison::Eq => actual == expected,
+            Comparison::Ne => actual != expected,
+            Comparison::Lt => actual < expected,
+            Comparison::Le => actual <= expected,
+            Comparison::Gt => actual > expected,
+            Comparison::Ge => actual >= expected
+        }
+    }
+}
+
+impl FromStr for Comparison {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [Comparison::Eq, Comparison::Ne, Comparison::Lt, Comparison::Le, Comparison::Gt, Comparison::Ge].iter()
+            .find(|c| c.symbol() == s)
+            .copied()
+            .ok_or(format!("Unknown comparison '{}'", s))
+    }
+}
+
+impl fmt::Display for AssertionTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssertionTarget::Accu => write!(f, "accu"),
+            AssertionTarget::Memory(addr) => write!(f, "{:#x}", addr)
+        }
+    }
+}
+
+impl Assertion {
+
+    // returns a failure message if the assertion does not hold for the runtime's current state
+    pub fn check(&self, runtime: &Runtime) -> Result<(), String> {
+        let isa = runtime.isa();
+        let actual = match self.target {
+            AssertionTarget::Accu => runtime.read_accu(),
+            AssertionTarget::Memory(addr) => runtime.read_mem(addr)
+        };
+        if self.comparison.holds(isa.to_signed(actual), isa.to_signed(self.value)) {
+            Ok(())
+        } else {
+            let place = self.location.map_or("at halt".to_owned(), |addr| format!("at {:#x}", addr));
+            Err(format!("Assertion `{}` failed {}: {} is {}",
+                        self.description, place, self.target, isa.to_signed(actual)))
+        }
+    }
+}
+
+// the resolved form used in symbol files:
+// `.expect <target> <op> <value> ; <description>` or `.assert_at <addr> <target> <op> <value> ; <description>`
+impl fmt::Display for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location {
+            Some(addr) => write!(f, ".assert_at {:#x} ", addr)?,
+            None => write!(f, ".expect ")?
+        }
+        write!(f, "{} {} {:#x} ; {}", self.target, self.comparison.symbol(), self.value, self.description)
+    }
+}
+
+impl FromStr for Assertion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (assertion, description) = s.split_once(';').unwrap_or((s, ""));
+        let tokens: Vec<&str> = assertion.split_whitespace().collect();
+        let parse_num = |num: &str| parse_mima_number(num)
+            .map_err(|_e| format!("Invalid number '{}' in assertion", num));
+        let (location, rest) = match tokens.as_slice() {
+            [".expect", rest @ ..] => (None, rest),
+            [".assert_at", addr, rest @ ..] => (Some(parse_num(addr)?), rest),
+            _ => return Err(format!("Invalid assertion '{}'", s))
+        };
+        match rest {
+            [target, comparison, value] => Ok(Assertion {
+                location,
+                target: if *target == "accu" {
+                    AssertionTarget::Accu
+                } else {
+                    AssertionTarget::Memory(parse_num(target)?)
+                },
+                comparison: Comparison::from_str(comparison)?,
+                value: parse_num(value)?,
+                description: description.trim().to_owned()
+            }),
+            _ => Err(format!("Invalid assertion '{}'", s))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::InstructionSet;
+
+    fn assertion(s: &str) -> Assertion {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_and_formats() {
+        let at = assertion(".assert_at 0x5 0x10 >= 0x3 ; counter >= 3");
+        assert_eq!(at.location, Some(5));
+        assert_eq!(at.target, AssertionTarget::Memory(0x10));
+        assert_eq!(at.comparison, Comparison::Ge);
+        assert_eq!(at.description, "counter >= 3");
+        assert_eq!(assertion(&at.to_string()), at);
+
+        let expect = assertion(".expect accu != 0");
+        assert_eq!((expect.location, expect.target), (None, AssertionTarget::Accu));
+        assert_eq!(assertion(&expect.to_string()), expect);
+    }
+
+    #[test]
+    fn rejects_invalid_assertions() {
+        assert!(".expect accu".parse::<Assertion>().is_err());
+        assert!(".expect accu =< 1".parse::<Assertion>().is_err());
+        assert!(".assert_at accu == 1".parse::<Assertion>().is_err());
+        assert!(".check accu == 1".parse::<Assertion>().is_err());
+    }
+
+    #[test]
+    fn compares_signed_values() {
+        let isa = InstructionSet::default();
+        let mut runtime = Runtime::new();
+        runtime.write_mem(3, isa.from_signed(-1));
+        assert!(assertion(".expect 0x3 < 0x0").check(&runtime).is_ok());
+        assert!(assertion(".expect 0x3 == 0xffffff").check(&runtime).is_ok());
+        let failure = assertion(".expect 0x3 > 0x0 ; positive").check(&runtime).unwrap_err();
+        assert_eq!(failure, "Assertion `positive` failed at halt: 0x3 is -1");
+    }
+
+    #[test]
+    fn checks_the_entry_point_before_executing() {
+        // LDC 5; HALT
+        let mut runtime = Runtime::with_memory(vec![0x000005, 0xf00000])
+            .with_assertions(vec![assertion(".assert_at 0x0 accu == 0x5"), assertion(".assert_at 0x1 accu == 0x5")]);
+        runtime.run().unwrap();
+        assert_eq!(runtime.assertion_failures(), ["Assertion `` failed at 0x0: accu is 0"]);
+    }
+
+    #[test]
+    fn checks_expectations_at_halt() {
+        let mut runtime = Runtime::with_memory(vec![0x000005, 0xf00000])
+            .with_assertions(vec![assertion(".expect accu == 0x5"), assertion(".expect accu == 0x6")]);
+        runtime.run().unwrap();
+        assert_eq!(runtime.assertion_failures(), ["Assertion `` failed at halt: accu is 5"]);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::types::{MimaAddress, MimaValue};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+// a device mapped into the address space of the runtime
+pub trait Device {
+    fn name(&self) -> &str;
+
+    // number of consecutive addresses the device occupies
+    fn size(&self) -> u32;
+
+    fn read(&self, offset: MimaAddress) -> MimaValue;
+
+    fn write(&mut self, offset: MimaAddress, val: MimaValue);
+
+    // called after every instruction; returning true raises an interrupt
+    fn tick(&mut self) -> bool {
+        false
+    }
+
+    // replaces where the device prints its output, for front-ends that draw on the terminal themselves
+    fn set_output(&mut self, _output: Box<dyn Write>) {}
+
+    // connects the device to a source of input, e.g. after it was restored from a snapshot
+    fn set_input(&mut self, _input: Receiver<u8>) {}
+
+    // internal state of the device, stored in snapshots
+    fn save_state(&self) -> Vec<MimaValue> {
+        Vec::new()
+    }
+
+    fn restore_state(&mut self, _state: &[MimaValue]) {}
+}
+
+// creates a device from its name, used to recreate devices when restoring a snapshot
+pub fn create_device(name: &str) -> Option<Box<dyn Device>> {
+    match name {
+        "console" => Some(Box::new(ConsoleDevice::default())),
+        _ => None
+    }
+}
+
+// reads the bytes of stdin in the background, so that devices can receive input without blocking
+pub fn stdin_input() -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for byte in io::stdin().lock().bytes() {
+            match byte {
+                Ok(byte) if sender.send(byte).is_ok() => {}
+                _ => break
+            }
+        }
+    });
+    receiver
+}
+
+// prints the lowest byte of every value written to its first address as a character to stdout.
+// Reading the first address returns the number of characters written so far.
+// The second address holds the oldest character of input that was not taken yet (0 if there is none),
+// writing to it takes the character. An interrupt is raised whenever a new character is available there.
+pub struct ConsoleDevice {
+    written: MimaValue,
+    output: Box<dyn Write>,
+    input: Option<Receiver<u8>>,
+    received: VecDeque<u8>,
+    // whether the interrupt for the first received character was raised already
+    signalled: bool
+}
+
+impl Default for ConsoleDevice {
+    fn default() -> Self {
+        ConsoleDevice {
+            written: 0,
+            output: Box::new(io::stdout()),
+            input: None,
+            received: VecDeque::new(),
+            signalled: false
+        }
+    }
+}
+
+impl ConsoleDevice {
+    pub fn with_input(mut self, input: Receiver<u8>) -> Self {
+        self.input = Some(input);
+        self
+    }
+}
+
+impl Device for ConsoleDevice {
+    fn name(&self) -> &str {
+        "console"
+    }
+
+    fn size(&self) -> u32 {
+        2
+    }
+
+    fn read(&self, offset: MimaAddress) -> MimaValue {
+        match offset {
+            0 => self.written,
+            _ => self.received.front().copied().unwrap_or(0) as MimaValue
+        }
+    }
+
+    fn write(&mut self, offset: MimaAddress, val: MimaValue) {
+        if offset == 0 {
+            let _ = self.output.write_all(&[(val & 0xff) as u8]);
+            let _ = self.output.flush();
+            self.written += 1;
+        } else if self.received.pop_front().is_some() {
+            self.signalled = false;
+        }
+    }
+
+    fn tick(&mut self) -> bool {
+        if let Some(input) = &self.input {
+            self.received.extend(input.try_iter());
+        }
+        if self.received.is_empty() || self.signalled {
+            false
+        } else {
+            self.signalled = true;
+            true
+        }
+    }
+
+    fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    fn set_input(&mut self, input: Receiver<u8>) {
+        self.input = Some(input);
+    }
+
+    fn save_state(&self) -> Vec<MimaValue> {
+        let mut state = vec![self.written, self.signalled as MimaValue];
+        state.extend(self.received.iter().map(|c| *c as MimaValue));
+        state
+    }
+
+    fn restore_state(&mut self, state: &[MimaValue]) {
+        self.written = state.first().copied().unwrap_or(0);
+        self.signalled = state.get(1).is_some_and(|signalled| *signalled != 0);
+        self.received = state.iter().skip(2).map(|c| *c as u8).collect();
+    }
+}
@@ -1,16 +1,17 @@
-use std::convert::TryFrom;
-use crate::types::{MimaAddress, MimaValue, ADDRESS_BITS, coerce_mima_value, MAX_ADDRESS};
-use strum_macros::{ToString, EnumString};
+use crate::types::MimaAddress;
+use strum_macros::{Display, EnumString, EnumVariantNames, EnumIter};
+use std::fmt;
 use enum_repr::EnumRepr;
+use serde::{Serialize, Deserialize};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Instruction {
     pub opcode: Opcode,
     pub arg: MimaAddress
 }
 
 #[EnumRepr(type = "u8")]
-#[derive(Debug, PartialEq, Eq, EnumString, ToString)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display, EnumIter, Serialize, Deserialize)]
 pub enum Opcode {
     LDC = 0x00,
     LDV = 0x01,
@@ -26,7 +27,27 @@ pub enum Opcode {
     STIV = 0x0b,
     HALT = 0xf0,
     NOT = 0xf1,
-    RAR = 0xf2
+    RAR = 0xf2,
+    // extended instruction set
+    SUB = 0x0c,
+    MUL = 0x0d,
+    CALL = 0x0e,
+    RET = 0xf3,
+    RAL = 0xf4,
+    LDSP = 0xf5,
+    STSP = 0xf6,
+    EI = 0xf7,
+    DI = 0xf8,
+    RETI = 0xf9
+}
+
+// the instruction set profiles supported by assembler, disassembler and runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, EnumString, Display, EnumVariantNames)]
+#[strum(serialize_all = "lowercase")]
+pub enum Isa {
+    #[default]
+    Standard,
+    Extended
 }
 
 impl Opcode {
@@ -35,21 +56,46 @@ impl Opcode {
         ((self.repr() >> 4) & 0xf) != 0xf
     }
 
-}
+    pub fn is_extended(&self) -> bool {
+        matches!(self, Self::SUB | Self::MUL | Self::CALL | Self::RET
+            | Self::RAL | Self::LDSP | Self::STSP | Self::EI | Self::DI | Self::RETI)
+    }
 
-impl Instruction {
-    pub fn opcode_bits(instr: MimaValue) -> u8 {
-        let prefix = instr >> ADDRESS_BITS;
-        (if prefix == 0x0f {
-            instr >> (ADDRESS_BITS - 4)
-        } else {
-            prefix
-        }) as u8
+    pub fn is_supported_by(&self, isa: Isa) -> bool {
+        isa == Isa::Extended || !self.is_extended()
     }
 
-    pub fn arg_bits(instr: MimaValue) -> MimaAddress {
-        instr & MAX_ADDRESS
+    // what the operation does, `a` being its argument
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::LDC => "Loads the constant a into the accumulator",
+            Self::LDV => "Loads the value at address a into the accumulator",
+            Self::STV => "Stores the accumulator at address a",
+            Self::ADD => "Adds the value at address a to the accumulator",
+            Self::AND => "Bitwise AND of the accumulator and the value at address a",
+            Self::OR => "Bitwise OR of the accumulator and the value at address a",
+            Self::XOR => "Bitwise XOR of the accumulator and the value at address a",
+            Self::EQL => "Sets the accumulator to -1 if it equals the value at address a, to 0 otherwise",
+            Self::JMP => "Continues execution at address a",
+            Self::JMN => "Continues execution at address a if the accumulator is negative",
+            Self::LDIV => "Loads the value at the address stored at address a into the accumulator",
+            Self::STIV => "Stores the accumulator at the address stored at address a",
+            Self::HALT => "Stops the machine",
+            Self::NOT => "Inverts all bits of the accumulator",
+            Self::RAR => "Rotates the accumulator one bit to the right",
+            Self::SUB => "Subtracts the value at address a from the accumulator",
+            Self::MUL => "Multiplies the accumulator with the value at address a",
+            Self::CALL => "Pushes the return address onto the stack and continues execution at address a",
+            Self::RET => "Pops the return address from the stack and continues execution there",
+            Self::RAL => "Rotates the accumulator one bit to the left",
+            Self::LDSP => "Loads the stack pointer into the accumulator",
+            Self::STSP => "Sets the stack pointer to the accumulator",
+            Self::EI => "Enables interrupts",
+            Self::DI => "Disables interrupts",
+            Self::RETI => "Returns from an interrupt handler: restores IAR and accumulator and enables interrupts"
+        }
     }
+
 }
 
 /*impl TryFrom<u8> for Opcode {
@@ -77,34 +123,12 @@ impl Instruction {
     }
 }*/
 
-impl ToString for Instruction {
-    fn to_string(&self) -> String {
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if self.opcode.has_arg() {
-            format!("{:4} {:#x}", self.opcode.to_string(), self.arg)
+            write!(f, "{:4} {:#x}", self.opcode.to_string(), self.arg)
         } else {
-            self.opcode.to_string()
+            write!(f, "{}", self.opcode)
         }
     }
 }
-
-impl TryFrom<MimaValue> for Instruction {
-    type Error = String;
-
-    fn try_from(instr: MimaValue) -> Result<Self, Self::Error> {
-        let opcode_bits = Self::opcode_bits(instr);
-        Opcode::from_repr(opcode_bits)
-            .ok_or(format!("Instruction {:#x} uses unrecognized opcode: {:#x}", instr, opcode_bits))
-            .map(|opcode| Self { opcode, arg: Self::arg_bits(instr)} )
-    }
-}
-
-impl From<&Instruction> for MimaValue {
-    fn from(instr: &Instruction) -> Self {
-        coerce_mima_value(if instr.opcode.has_arg() {
-            ((instr.opcode.repr() as u32) << ADDRESS_BITS) | instr.arg
-        } else {
-            (instr.opcode.repr() as u32) << (ADDRESS_BITS - 4)
-        })
-    }
-}
-
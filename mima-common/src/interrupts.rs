@@ -0,0 +1,111 @@
+use crate::types::MimaAddress;
+use std::collections::VecDeque;
+use std::fmt;
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InterruptSource {
+    Timer,
+    // index of the device in the order it was attached to the runtime
+    Device(usize)
+}
+
+impl InterruptSource {
+    // the number written to the cause address when the interrupt is serviced
+    pub fn cause(&self) -> u32 {
+        match self {
+            InterruptSource::Timer => 0,
+            InterruptSource::Device(i) => *i as u32 + 1
+        }
+    }
+}
+
+impl fmt::Display for InterruptSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterruptSource::Timer => write!(f, "timer"),
+            InterruptSource::Device(i) => write!(f, "device {}", i)
+        }
+    }
+}
+
+// collects interrupt requests and decides when they are serviced.
+// When an interrupt is serviced, the IAR is saved to save_addr, the accumulator to save_addr + 1
+// and the interrupt cause to save_addr + 2, interrupts are disabled and execution continues at vector.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InterruptController {
+    pub enabled: bool,
+    pub vector: MimaAddress,
+    pub save_addr: MimaAddress,
+    timer_interval: Option<u64>,
+    timer_remaining: u64,
+    pending: VecDeque<InterruptSource>
+}
+
+impl InterruptController {
+    pub fn new(vector: MimaAddress, save_addr: MimaAddress) -> Self {
+        InterruptController {
+            enabled: false,
+            vector,
+            save_addr,
+            timer_interval: None,
+            timer_remaining: 0,
+            pending: VecDeque::new()
+        }
+    }
+
+    // raises a timer interrupt every interval instructions
+    pub fn with_timer(mut self, interval: u64) -> Self {
+        self.timer_interval = Some(interval);
+        self.timer_remaining = interval;
+        self
+    }
+
+    pub fn timer_interval(&self) -> Option<u64> {
+        self.timer_interval
+    }
+
+    // instructions left until the next timer interrupt
+    pub fn timer_remaining(&self) -> Option<u64> {
+        self.timer_interval.map(|_| self.timer_remaining)
+    }
+
+    // checks settings that do not come from with_timer when restored from a snapshot
+    pub fn validate(&self) -> Result<(), String> {
+        if self.timer_interval == Some(0) {
+            return Err("Timer interval must be at least 1".to_owned());
+        }
+        Ok(())
+    }
+
+    pub fn raise(&mut self, source: InterruptSource) {
+        if !self.pending.contains(&source) {
+            self.pending.push_back(source);
+        }
+    }
+
+    pub fn pending(&self) -> impl Iterator<Item = &InterruptSource> {
+        self.pending.iter()
+    }
+
+    // advances the timer by one instruction
+    pub fn tick(&mut self) {
+        if let Some(interval) = self.timer_interval {
+            // snapshots may contain any remaining count
+            self.timer_remaining = self.timer_remaining.saturating_sub(1);
+            if self.timer_remaining == 0 {
+                self.timer_remaining = interval;
+                self.raise(InterruptSource::Timer);
+            }
+        }
+    }
+
+    // returns the next interrupt to service, if interrupts are enabled
+    pub fn take_next(&mut self) -> Option<InterruptSource> {
+        if self.enabled {
+            self.pending.pop_front()
+        } else {
+            None
+        }
+    }
+}
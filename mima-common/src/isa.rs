@@ -0,0 +1,356 @@
+use crate::instructions::{Instruction, Opcode, Isa};
+use crate::types::{MimaValue, MimaAddress, ADDRESS_BITS, VALUE_BITS, parse_mima_number};
+use serde::{Serialize, Deserialize};
+use std::fs;
+use std::num::ParseIntError;
+use std::path::Path;
+use std::str::FromStr;
+use strum::IntoEnumIterator;
+
+pub const MAX_ADDRESS_BITS: u8 = 24;
+pub const MAX_VALUE_BITS: u8 = 32;
+
+// encoding of a single operation: the opcode occupies the highest `bits` bits of a value
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpcodeSpec {
+    pub opcode: Opcode,
+    pub mnemonic: String,
+    pub code: u32,
+    pub bits: u8
+}
+
+// word widths and opcode encodings of a mima variant
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstructionSet {
+    pub name: String,
+    pub address_bits: u8,
+    pub value_bits: u8,
+    opcodes: Vec<OpcodeSpec>
+}
+
+#[derive(Deserialize)]
+struct IsaFile {
+    name: Option<String>,
+    address_bits: Option<u8>,
+    value_bits: Option<u8>,
+    opcodes: Vec<OpcodeFileEntry>
+}
+
+#[derive(Deserialize)]
+struct OpcodeFileEntry {
+    operation: String,
+    mnemonic: Option<String>,
+    code: u32,
+    bits: Option<u8>
+}
+
+impl From<Isa> for InstructionSet {
+    fn from(isa: Isa) -> Self {
+        let opcodes = Opcode::iter()
+            .filter(|opcode| opcode.is_supported_by(isa))
+            .map(|opcode| OpcodeSpec {
+                opcode,
+                mnemonic: opcode.to_string(),
+                code: opcode.repr() as u32,
+                bits: if opcode.has_arg() { 4 } else { 8 }
+            })
+            .collect();
+        InstructionSet {
+            name: isa.to_string(),
+            address_bits: ADDRESS_BITS,
+            value_bits: VALUE_BITS,
+            opcodes
+        }
+    }
+}
+
+impl Default for InstructionSet {
+    fn default() -> Self {
+        Self::from(Isa::Standard)
+    }
+}
+
+impl InstructionSet {
+
+    // reads an instruction set description from a TOML file, or a JSON file if the extension is .json
+    pub fn read_from(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read instruction set file: {}", e))?;
+        let json = path.extension().is_some_and(|ext| ext == "json");
+        Self::parse(&content, json, &path.display().to_string())
+    }
+
+    // parses the content of an instruction set file, named `default_name` unless it names itself
+    fn parse(content: &str, json: bool, default_name: &str) -> Result<Self, String> {
+        let file: IsaFile = if json {
+            serde_json::from_str(content).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(content).map_err(|e| e.to_string())
+        }.map_err(|e| format!("Invalid instruction set file: {}", e))?;
+
+        let address_bits = file.address_bits.unwrap_or(ADDRESS_BITS);
+        let value_bits = file.value_bits.unwrap_or(VALUE_BITS);
+        let mut opcodes = Vec::with_capacity(file.opcodes.len());
+        for entry in file.opcodes {
+            let opcode = Opcode::from_str(&entry.operation.to_uppercase())
+                .map_err(|_e| format!("Unknown operation '{}' in instruction set file", entry.operation))?;
+            opcodes.push(OpcodeSpec {
+                opcode,
+                mnemonic: entry.mnemonic.unwrap_or_else(|| opcode.to_string()).to_uppercase(),
+                code: entry.code,
+                // invalid widths are reported by validate
+                bits: entry.bits.unwrap_or(if opcode.has_arg() { value_bits.saturating_sub(address_bits) } else { 8 })
+            });
+        }
+        let isa = InstructionSet {
+            name: file.name.unwrap_or_else(|| default_name.to_owned()),
+            address_bits,
+            value_bits,
+            opcodes
+        };
+        isa.validate()?;
+        Ok(isa)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.address_bits == 0 || self.address_bits > MAX_ADDRESS_BITS {
+            return Err(format!("Address width must be between 1 and {} bits", MAX_ADDRESS_BITS));
+        }
+        if self.value_bits <= self.address_bits || self.value_bits > MAX_VALUE_BITS {
+            return Err(format!("Value width must be greater than the address width and at most {} bits",
+                               MAX_VALUE_BITS));
+        }
+        for (i, spec) in self.opcodes.iter().enumerate() {
+            let available = self.value_bits - if spec.opcode.has_arg() { self.address_bits } else { 0 };
+            if spec.bits == 0 || spec.bits > available {
+                return Err(format!("Opcode {} must be between 1 and {} bits wide", spec.mnemonic, available));
+            }
+            if spec.code.checked_shr(spec.bits as u32).unwrap_or(0) != 0 {
+                return Err(format!("Code {:#x} of {} does not fit into {} bits",
+                                   spec.code, spec.mnemonic, spec.bits));
+            }
+            for other in &self.opcodes[..i] {
+                if other.opcode == spec.opcode || other.mnemonic == spec.mnemonic {
+                    return Err(format!("Operation or mnemonic {} is defined more than once", spec.mnemonic));
+                }
+                // no code may be a prefix of another one, otherwise decoding is ambiguous
+                let common = spec.bits.min(other.bits);
+                if spec.code >> (spec.bits - common) == other.code >> (other.bits - common) {
+                    return Err(format!("Encodings of {} and {} overlap", other.mnemonic, spec.mnemonic));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn max_address(&self) -> MimaAddress {
+        ((1u64 << self.address_bits) - 1) as MimaAddress
+    }
+
+    pub fn max_value(&self) -> MimaValue {
+        ((1u64 << self.value_bits) - 1) as MimaValue
+    }
+
+    pub fn address_space(&self) -> u32 {
+        self.max_address() + 1
+    }
+
+    // number of bytes a value occupies in binary files
+    pub fn value_bytes(&self) -> u8 {
+        self.value_bits.div_ceil(8)
+    }
+
+    pub fn coerce_value(&self, val: MimaValue) -> MimaValue {
+        val & self.max_value()
+    }
+
+    pub fn coerce_address(&self, addr: MimaAddress) -> MimaAddress {
+        addr & self.max_address()
+    }
+
+    pub fn is_negative(&self, val: MimaValue) -> bool {
+        (val >> (self.value_bits - 1)) & 1 == 1
+    }
+
+    // interprets a value as a two's complement number of this instruction set's value width
+    pub fn to_signed(&self, val: MimaValue) -> i64 {
+        let val = self.coerce_value(val);
+        if self.is_negative(val) {
+            val as i64 - (1i64 << self.value_bits)
+        } else {
+            val as i64
+        }
+    }
+
+    pub fn from_signed(&self, num: i64) -> MimaValue {
+        self.coerce_value(num as MimaValue)
+    }
+
+    // parses a value, accepting negative numbers as two's complement
+    pub fn parse_value(&self, s: &str) -> Result<MimaValue, ParseIntError> {
+        if s.starts_with('-') {
+            s.parse::<i64>().map(|num| self.from_signed(num))
+        } else {
+            parse_mima_number(s).map(|val| self.coerce_value(val))
+        }
+    }
+
+    pub fn opcodes(&self) -> &[OpcodeSpec] {
+        &self.opcodes
+    }
+
+    pub fn supports(&self, opcode: Opcode) -> bool {
+        self.spec(opcode).is_some()
+    }
+
+    pub fn spec(&self, opcode: Opcode) -> Option<&OpcodeSpec> {
+        self.opcodes.iter().find(|spec| spec.opcode == opcode)
+    }
+
+    // looks up the operation with the given (case insensitive) mnemonic
+    pub fn lookup(&self, mnemonic: &str) -> Option<Opcode> {
+        self.opcodes.iter()
+            .find(|spec| spec.mnemonic.eq_ignore_ascii_case(mnemonic))
+            .map(|spec| spec.opcode)
+    }
+
+    pub fn mnemonic(&self, opcode: Opcode) -> String {
+        self.spec(opcode).map_or_else(|| opcode.to_string(), |spec| spec.mnemonic.clone())
+    }
+
+    pub fn decode(&self, instr: MimaValue) -> Result<Instruction, String> {
+        let instr = self.coerce_value(instr);
+        self.opcodes.iter()
+            .find(|spec| instr >> (self.value_bits - spec.bits) == spec.code)
+            .map(|spec| Instruction { opcode: spec.opcode, arg: instr & self.max_address() })
+            .ok_or(format!("Instruction {:#x} uses unrecognized opcode", instr))
+    }
+
+    pub fn encode(&self, instr: &Instruction) -> Result<MimaValue, String> {
+        let spec = self.spec(instr.opcode)
+            .ok_or(format!("Operation {} is not part of instruction set {}", instr.opcode, self.name))?;
+        let code = spec.code << (self.value_bits - spec.bits);
+        Ok(if spec.opcode.has_arg() {
+            code | self.coerce_address(instr.arg)
+        } else {
+            code
+        })
+    }
+
+    // formats an instruction using the mnemonics of this instruction set
+    pub fn format(&self, instr: &Instruction) -> String {
+        if instr.opcode.has_arg() {
+            format!("{:4} {:#x}", self.mnemonic(instr.opcode), instr.arg)
+        } else {
+            self.mnemonic(instr.opcode)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_toml_with_defaults() {
+        let isa = InstructionSet::parse(r#"
+            address_bits = 8
+            value_bits = 16
+            [[opcodes]]
+            operation = "ldc"
+            mnemonic = "load"
+            code = 0
+            [[opcodes]]
+            operation = "HALT"
+            code = 0xf0
+        "#, false, "tiny").unwrap();
+        assert_eq!(isa.name, "tiny");
+        assert_eq!(isa.spec(Opcode::LDC).unwrap().bits, 8);
+        assert_eq!(isa.spec(Opcode::HALT).unwrap().bits, 8);
+        assert_eq!(isa.lookup("LOAD"), Some(Opcode::LDC));
+        assert_eq!(isa.max_value(), 0xffff);
+    }
+
+    #[test]
+    fn parses_json() {
+        let isa = InstructionSet::parse(
+            r#"{"name": "j", "opcodes": [{"operation": "JMP", "code": 8}]}"#, true, "file.json").unwrap();
+        assert_eq!(isa.name, "j");
+        assert_eq!(isa.address_bits, ADDRESS_BITS);
+        assert!(isa.supports(Opcode::JMP));
+        assert!(!isa.supports(Opcode::LDC));
+    }
+
+    #[test]
+    fn round_trips_instructions() {
+        let isa = InstructionSet::parse(r#"
+            address_bits = 10
+            value_bits = 16
+            [[opcodes]]
+            operation = "ADD"
+            code = 0x3
+            [[opcodes]]
+            operation = "NOT"
+            code = 0xff
+        "#, false, "t").unwrap();
+        let add = Instruction { opcode: Opcode::ADD, arg: 0x2a5 };
+        assert_eq!(isa.encode(&add), Ok(0x0ea5));
+        assert_eq!(isa.decode(0x0ea5), Ok(add));
+        let not = isa.encode(&Instruction { opcode: Opcode::NOT, arg: 0 }).unwrap();
+        assert_eq!(not, 0xff00);
+        assert_eq!(isa.decode(not).map(|instr| instr.opcode), Ok(Opcode::NOT));
+        assert!(isa.decode(0).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_widths() {
+        let result = InstructionSet::parse(r#"
+            address_bits = 16
+            value_bits = 8
+            [[opcodes]]
+            operation = "LDC"
+            code = 0
+        "#, false, "t");
+        assert!(result.unwrap_err().contains("Value width"));
+    }
+
+    #[test]
+    fn rejects_codes_that_do_not_fit() {
+        let too_wide = InstructionSet::parse(r#"
+            [[opcodes]]
+            operation = "LDC"
+            code = 0x10
+            bits = 4
+        "#, false, "t");
+        assert!(too_wide.unwrap_err().contains("does not fit"));
+        let full_width = InstructionSet::parse(r#"
+            address_bits = 16
+            value_bits = 32
+            [[opcodes]]
+            operation = "HALT"
+            code = 1
+            bits = 32
+        "#, false, "t");
+        assert!(full_width.is_ok());
+    }
+
+    #[test]
+    fn rejects_overlapping_encodings() {
+        let result = InstructionSet::parse(r#"
+            [[opcodes]]
+            operation = "LDC"
+            code = 0xf
+            bits = 4
+            [[opcodes]]
+            operation = "HALT"
+            code = 0xf0
+        "#, false, "t");
+        assert!(result.unwrap_err().contains("overlap"));
+    }
+
+    #[test]
+    fn rejects_unknown_operations() {
+        let result = InstructionSet::parse("[[opcodes]]\noperation = \"FOO\"\ncode = 0\n", false, "t");
+        assert!(result.unwrap_err().contains("Unknown operation"));
+    }
+}
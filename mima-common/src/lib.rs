@@ -1,3 +1,7 @@
+pub mod assertions;
+pub mod devices;
 pub mod instructions;
+pub mod interrupts;
+pub mod isa;
 pub mod runtime;
 pub mod types;
@@ -1,24 +1,100 @@
-use crate::types::{MimaValue, MimaAddress, coerce_mima_value, is_negative, coerce_mima_address, MAX_VALUE};
+use crate::types::{MimaValue, MimaAddress};
 use crate::instructions::{Instruction, Opcode};
-use std::convert::TryFrom;
-use std::iter::repeat;
+use crate::isa::InstructionSet;
+use crate::devices::Device;
+use crate::assertions::Assertion;
+use crate::interrupts::{InterruptController, InterruptSource};
 use std::slice::Iter;
+use std::io::Write;
+use std::sync::mpsc::Receiver;
+use std::fmt;
+use std::iter::repeat_n;
+use serde::{Serialize, Deserialize};
+
+mod microcode;
+mod snapshot;
+pub use microcode::{MicroStep, Phase, MEMORY_LATENCY};
+pub use snapshot::Snapshot;
+
+// status of the last arithmetic operation, recorded if enabled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusFlags {
+    // the unsigned result did not fit into a value (for SUB: a borrow occurred)
+    pub carry: bool,
+    // the signed result did not fit into a value
+    pub overflow: bool
+}
+
+impl fmt::Display for StatusFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "C={} V={}", self.carry as u8, self.overflow as u8)
+    }
+}
 
 pub struct Runtime {
     accu: MimaValue,
     iar: MimaAddress,
     ir: MimaValue,
+    // internal registers of the textbook mima: memory address/data registers and ALU operands/result
+    sar: MimaAddress,
+    sdr: MimaValue,
+    x: MimaValue,
+    y: MimaValue,
+    z: MimaValue,
+    // stack pointer of the extended instruction set
+    sp: MimaAddress,
+    isa: InstructionSet,
+    flags: Option<StatusFlags>,
+    // progress of the current instruction when executing clock cycle by clock cycle
+    cycle: u8,
+    mem_cycles: u8,
+    decoded: Option<Instruction>,
+    // IR before the fetch of the current instruction, restored if the fetched value cannot be decoded
+    previous_ir: MimaValue,
     memory: Vec<MimaValue>,
+    // memory mapped devices and their base addresses
+    devices: Vec<(MimaAddress, Box<dyn Device>)>,
+    interrupts: Option<InterruptController>,
+    // number of instructions executed so far
+    steps: u64,
+    assertions: Vec<Assertion>,
+    assertion_failures: Vec<String>,
+    // whether the assertions at the current IAR were checked, so that they are checked once per arrival
+    location_checked: bool,
     pub halt: bool
 }
 
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Runtime {
     pub fn with_memory(initial_memory: Vec<MimaValue>) -> Self {
         Self {
             accu: 0,
             iar: 0,
             ir: 0,
+            sar: 0,
+            sdr: 0,
+            x: 0,
+            y: 0,
+            z: 0,
+            sp: 0,
+            isa: InstructionSet::default(),
+            flags: None,
+            cycle: 0,
+            mem_cycles: 0,
+            decoded: None,
+            previous_ir: 0,
             memory: initial_memory,
+            devices: Vec::new(),
+            interrupts: None,
+            steps: 0,
+            assertions: Vec::new(),
+            assertion_failures: Vec::new(),
+            location_checked: false,
             halt: false
         }
     }
@@ -33,20 +109,120 @@ impl Runtime {
         Self::with_memory(Vec::new())
     }
 
+    pub fn with_isa(mut self, isa: InstructionSet) -> Self {
+        self.isa = isa;
+        self
+    }
+
+    pub fn isa(&self) -> &InstructionSet {
+        &self.isa
+    }
+
+    // enables recording carry and overflow of arithmetic operations
+    pub fn with_status_flags(mut self, enabled: bool) -> Self {
+        self.flags = if enabled { Some(StatusFlags::default()) } else { None };
+        self
+    }
+
+    pub fn read_flags(&self) -> Option<StatusFlags> {
+        self.flags
+    }
+
+    pub fn read_steps(&self) -> u64 {
+        self.steps
+    }
+
+    // assertions are checked whenever execution arrives at their location, before the instruction
+    // there is executed (including the first one), and when the machine halts.
+    // Failures do not stop execution, they are collected instead.
+    pub fn with_assertions(mut self, assertions: Vec<Assertion>) -> Self {
+        self.assertions = assertions;
+        self
+    }
+
+    pub fn assertions(&self) -> &[Assertion] {
+        &self.assertions
+    }
+
+    pub fn assertion_failures(&self) -> &[String] {
+        &self.assertion_failures
+    }
+
+    // maps a device into memory starting at the given base address
+    pub fn with_device(mut self, base: MimaAddress, device: Box<dyn Device>) -> Self {
+        self.devices.push((self.isa.coerce_address(base), device));
+        self
+    }
+
+    // sends the output of all devices to writers created by `output`
+    pub fn set_device_output<F: Fn() -> Box<dyn Write>>(&mut self, output: F) {
+        for (_, device) in &mut self.devices {
+            device.set_output(output());
+        }
+    }
+
+    // connects all devices to sources of input created by `input`
+    pub fn set_device_input<F: Fn() -> Receiver<u8>>(&mut self, input: F) {
+        for (_, device) in &mut self.devices {
+            device.set_input(input());
+        }
+    }
+
+    pub fn devices(&self) -> impl Iterator<Item = (MimaAddress, &dyn Device)> {
+        self.devices.iter().map(|(base, device)| (*base, device.as_ref()))
+    }
+
+    pub fn with_interrupts(mut self, controller: InterruptController) -> Self {
+        self.interrupts = Some(controller);
+        self
+    }
+
+    pub fn interrupts(&self) -> Option<&InterruptController> {
+        self.interrupts.as_ref()
+    }
+
+    // requests an interrupt; it is ignored if no interrupt controller is configured
+    pub fn raise_interrupt(&mut self, source: InterruptSource) {
+        if let Some(controller) = &mut self.interrupts {
+            controller.raise(source);
+        }
+    }
+
+    // resets registers and memory to the given state, keeping instruction set, devices,
+    // interrupt setup and assertions
+    pub fn reset(&mut self, memory: Vec<MimaValue>) {
+        let isa = self.isa.clone();
+        let flags = self.flags.map(|_| StatusFlags::default());
+        let devices = std::mem::take(&mut self.devices);
+        let interrupts = self.interrupts.take()
+            .map(|c| {
+                let fresh = InterruptController::new(c.vector, c.save_addr);
+                match c.timer_interval() {
+                    Some(interval) => fresh.with_timer(interval),
+                    None => fresh
+                }
+            });
+        let assertions = std::mem::take(&mut self.assertions);
+        *self = Runtime::with_memory(memory).with_isa(isa).with_assertions(assertions);
+        self.flags = flags;
+        self.devices = devices;
+        self.interrupts = interrupts;
+    }
+
     pub fn read_accu(&self) -> MimaValue {
         self.accu
     }
 
     pub fn write_accu(&mut self, val: MimaValue) {
-        self.accu = coerce_mima_value(val);
+        self.accu = self.isa.coerce_value(val);
     }
 
-    pub fn read_ir(&self) -> MimaAddress {
+    pub fn read_ir(&self) -> MimaValue {
         self.ir
     }
 
     pub fn write_ir(&mut self, instr: MimaValue) {
-        self.ir = coerce_mima_value(instr);
+        self.ir = self.isa.coerce_value(instr);
     }
 
     pub fn read_iar(&self) -> MimaAddress {
@@ -54,11 +230,44 @@ impl Runtime {
     }
 
     pub fn write_iar(&mut self, addr: MimaAddress) {
-        self.iar = coerce_mima_address(addr);
+        self.iar = self.isa.coerce_address(addr);
+        self.location_checked = false;
+    }
+
+    pub fn read_sp(&self) -> MimaAddress {
+        self.sp
+    }
+
+    pub fn write_sp(&mut self, addr: MimaAddress) {
+        self.sp = self.isa.coerce_address(addr);
+    }
+
+    pub fn read_sar(&self) -> MimaAddress {
+        self.sar
+    }
+
+    pub fn read_sdr(&self) -> MimaValue {
+        self.sdr
+    }
+
+    pub fn read_x(&self) -> MimaValue {
+        self.x
+    }
+
+    pub fn read_y(&self) -> MimaValue {
+        self.y
+    }
+
+    pub fn read_z(&self) -> MimaValue {
+        self.z
     }
 
     pub fn read_mem(&self, addr: MimaAddress) -> MimaValue {
-        let coerced = coerce_mima_address(addr) as usize;
+        let addr = self.isa.coerce_address(addr);
+        if let Some((base, device)) = self.device_at(addr) {
+            return self.isa.coerce_value(device.read(addr - base));
+        }
+        let coerced = addr as usize;
         if coerced < self.memory.len() {
             self.memory[coerced]
         } else {
@@ -67,12 +276,25 @@ impl Runtime {
     }
 
     pub fn write_mem(&mut self, addr: MimaAddress, val: MimaValue) {
-        let coerced = coerce_mima_address(addr) as usize;
+        let addr = self.isa.coerce_address(addr);
+        let val = self.isa.coerce_value(val);
+        if let Some(index) = self.devices.iter().position(|(base, d)| addr >= *base && addr - base < d.size()) {
+            let (base, device) = &mut self.devices[index];
+            device.write(addr - *base, val);
+            return;
+        }
+        let coerced = addr as usize;
         let mem = &mut self.memory;
         if coerced >= mem.len() {
-            mem.extend(repeat(0).take(coerced + 1 - mem.len()));
+            mem.extend(repeat_n(0, coerced + 1 - mem.len()));
         }
-        mem[coerced] = coerce_mima_value(val);
+        mem[coerced] = self.isa.coerce_value(val);
+    }
+
+    fn device_at(&self, addr: MimaAddress) -> Option<(MimaAddress, &dyn Device)> {
+        self.devices.iter()
+            .find(|(base, device)| addr >= *base && addr - base < device.size())
+            .map(|(base, device)| (*base, device.as_ref()))
     }
 
     pub fn mem_iter(&self) -> Iter<'_, MimaValue> {
@@ -90,13 +312,22 @@ impl Runtime {
         if self.halt {
             return Err("MIMA is halted".to_owned())
         }
-        let instruction = self.next_instruction();
-        if instruction.is_err() {
-            return Err(format!("Decode failure - {}", instruction.err().unwrap()));
+        if self.cycle != 0 {
+            // finish the instruction that is being executed cycle by cycle
+            while self.cycle != 0 {
+                self.micro_step()?;
+            }
+            return Ok(());
+        }
+        // execution may start at a location without arriving there, e.g. at the entry point
+        if !self.location_checked {
+            self.check_assertions();
         }
-        self.write_iar(self.next_instruction_addr());
+        // decoded before fetching, so that IAR and IR still point at an instruction that cannot be decoded
+        let instr = self.isa.decode(self.read_mem(self.iar))
+            .map_err(|e| format!("Decode failure - {}", e))?;
+        self.fetch();
 
-        let instr = instruction.unwrap();
         let opcode = instr.opcode;
         let arg = instr.arg;
         match opcode {
@@ -114,47 +345,93 @@ impl Runtime {
             Opcode::STIV => self.stiv(arg),
             Opcode::HALT => self.halt(),
             Opcode::NOT => self.not(),
-            Opcode::RAR => self.rar()
+            Opcode::RAR => self.rar(),
+            Opcode::SUB => self.sub(arg),
+            Opcode::MUL => self.mul(arg),
+            Opcode::CALL => self.call(arg),
+            Opcode::RET => self.ret(),
+            Opcode::RAL => self.ral(),
+            Opcode::LDSP => self.ldsp(),
+            Opcode::STSP => self.stsp(),
+            Opcode::EI => self.set_interrupts_enabled(true),
+            Opcode::DI => self.set_interrupts_enabled(false),
+            Opcode::RETI => self.reti()
         }
+        self.finish_instruction();
         Ok(())
 
     }
 
+    // loads the next instruction into the IR and increments the IAR using the ALU
+    fn fetch(&mut self) {
+        self.sar = self.iar;
+        self.sdr = self.read_mem(self.sar);
+        self.ir = self.sdr;
+        self.alu(self.iar, 1, |x, y| x.wrapping_add(y));
+        self.write_iar(self.z);
+    }
+
+    // loads a value from memory via the SAR and SDR
+    fn load(&mut self, addr: MimaAddress) -> MimaValue {
+        self.sar = self.isa.coerce_address(addr);
+        self.sdr = self.read_mem(self.sar);
+        self.sdr
+    }
+
+    // stores a value to memory via the SAR and SDR
+    fn store(&mut self, addr: MimaAddress, val: MimaValue) {
+        self.sar = self.isa.coerce_address(addr);
+        self.sdr = self.isa.coerce_value(val);
+        self.write_mem(self.sar, self.sdr);
+    }
+
+    // performs an ALU operation on the X and Y registers, putting the result in Z
+    fn alu<F: FnOnce(MimaValue, MimaValue) -> MimaValue>(&mut self, x: MimaValue, y: MimaValue, op: F) {
+        self.x = x;
+        self.y = y;
+        self.z = self.isa.coerce_value(op(self.x, self.y));
+    }
+
+    // applies an ALU operation to the accumulator and the value at the given address
+    fn alu_mem<F: FnOnce(MimaValue, MimaValue) -> MimaValue>(&mut self, arg: MimaAddress, op: F) {
+        let operand = self.load(arg);
+        self.alu(self.accu, operand, op);
+        self.write_accu(self.z);
+    }
+
     fn ldc(&mut self, arg: MimaAddress) {
-        self.write_accu(coerce_mima_value(arg));
+        self.write_accu(self.isa.coerce_value(arg));
     }
 
     fn ldv(&mut self, arg: MimaAddress) {
-        self.write_accu(self.read_mem(arg));
+        let val = self.load(arg);
+        self.write_accu(val);
     }
 
     fn stv(&mut self, arg: MimaAddress) {
-        self.write_mem(arg, self.accu);
+        self.store(arg, self.accu);
     }
 
     fn add(&mut self, arg: MimaAddress) {
-        let result = self.read_accu() + self.read_mem(arg);
-        self.write_accu(result);
+        self.alu_mem(arg, |x, y| x.wrapping_add(y));
+        self.record_flags(Opcode::ADD);
     }
 
     fn and(&mut self, arg: MimaAddress) {
-        let result = self.read_accu() & self.read_mem(arg);
-        self.write_accu(result);
+        self.alu_mem(arg, |x, y| x & y);
     }
 
     fn or(&mut self, arg: MimaAddress) {
-        let result = self.read_accu() | self.read_mem(arg);
-        self.write_accu(result);
+        self.alu_mem(arg, |x, y| x | y);
     }
 
     fn xor(&mut self, arg: MimaAddress) {
-        let result = self.read_accu() ^ self.read_mem(arg);
-        self.write_accu(result);
+        self.alu_mem(arg, |x, y| x ^ y);
     }
 
     fn eql(&mut self, arg: MimaAddress) {
-        let result = self.read_accu() == self.read_mem(arg);
-        self.write_accu(if result { MAX_VALUE } else { 0 });
+        let max = self.isa.max_value();
+        self.alu_mem(arg, |x, y| if x == y { max } else { 0 });
     }
 
     fn jmp(&mut self, arg: MimaAddress) {
@@ -162,17 +439,19 @@ impl Runtime {
     }
 
     fn jmn(&mut self, arg: MimaAddress) {
-        if is_negative(self.read_accu()) {
+        if self.isa.is_negative(self.read_accu()) {
             self.jmp(arg);
         }
     }
 
     fn ldiv(&mut self, arg: MimaAddress) {
-        self.ldv(self.read_mem(arg));
+        let addr = self.load(arg);
+        self.ldv(addr);
     }
 
     fn stiv(&mut self, arg: MimaAddress) {
-        self.stv(self.read_mem(arg));
+        let addr = self.load(arg);
+        self.stv(addr);
     }
 
     fn halt(&mut self) {
@@ -180,18 +459,316 @@ impl Runtime {
     }
 
     fn not(&mut self) {
-        self.write_accu(self.read_accu().reverse_bits());
+        self.alu(self.accu, self.y, |x, _| !x);
+        self.write_accu(self.z);
     }
 
     fn rar(&mut self) {
-        self.write_accu(self.read_accu().rotate_right(1));
+        let bits = self.isa.value_bits;
+        self.alu(self.accu, self.y, |x, _| (x >> 1) | ((x & 1) << (bits - 1)));
+        self.write_accu(self.z);
+    }
+
+    fn sub(&mut self, arg: MimaAddress) {
+        self.alu_mem(arg, |x, y| x.wrapping_sub(y));
+        self.record_flags(Opcode::SUB);
+    }
+
+    fn mul(&mut self, arg: MimaAddress) {
+        self.alu_mem(arg, |x, y| x.wrapping_mul(y));
+        self.record_flags(Opcode::MUL);
+    }
+
+    // updates the status flags from the operands in X and Y, if the opcode is arithmetic
+    fn record_flags(&mut self, opcode: Opcode) {
+        if self.flags.is_none() {
+            return;
+        }
+        let (x, y) = (self.x as u64, self.y as u64);
+        let (sx, sy) = (self.isa.to_signed(self.x), self.isa.to_signed(self.y));
+        let max = self.isa.max_value() as u64;
+        let (min_signed, max_signed) = (-((max as i64 + 1) / 2), (max as i64) / 2);
+        let in_range = |n: i64| n >= min_signed && n <= max_signed;
+        self.flags = match opcode {
+            Opcode::ADD => Some(StatusFlags { carry: x + y > max, overflow: !in_range(sx + sy) }),
+            Opcode::SUB => Some(StatusFlags { carry: y > x, overflow: !in_range(sx - sy) }),
+            Opcode::MUL => Some(StatusFlags { carry: x * y > max, overflow: !in_range(sx * sy) }),
+            _ => self.flags
+        };
+    }
+
+    // pushes the address of the next instruction onto the stack and jumps to the given address
+    fn call(&mut self, arg: MimaAddress) {
+        self.alu(self.sp, 1, |x, y| x.wrapping_sub(y));
+        self.write_sp(self.z);
+        self.store(self.sp, self.iar);
+        self.jmp(arg);
+    }
+
+    // pops the return address off the stack and jumps to it
+    fn ret(&mut self) {
+        let addr = self.load(self.sp);
+        self.alu(self.sp, 1, |x, y| x.wrapping_add(y));
+        self.write_sp(self.z);
+        self.jmp(addr);
+    }
+
+    fn ral(&mut self) {
+        let bits = self.isa.value_bits;
+        self.alu(self.accu, self.y, |x, _| (x << 1) | (x >> (bits - 1)));
+        self.write_accu(self.z);
+    }
+
+    fn ldsp(&mut self) {
+        self.write_accu(self.sp);
+    }
+
+    fn stsp(&mut self) {
+        self.write_sp(self.accu);
+    }
+
+    fn set_interrupts_enabled(&mut self, enabled: bool) {
+        if let Some(controller) = &mut self.interrupts {
+            controller.enabled = enabled;
+        }
+    }
+
+    // restores IAR and accumulator saved on interrupt entry and enables interrupts again
+    fn reti(&mut self) {
+        if let Some(save_addr) = self.interrupts.as_ref().map(|c| c.save_addr) {
+            // in the order of the microprogram, so that SAR and SDR end up the same
+            let accu = self.load(save_addr.wrapping_add(1));
+            self.write_accu(accu);
+            let iar = self.load(save_addr);
+            self.jmp(iar);
+            self.set_interrupts_enabled(true);
+        }
+    }
+
+    // called after every instruction. Returns the source of the interrupt that was entered, if any.
+    fn finish_instruction(&mut self) -> Option<InterruptSource> {
+        let interrupt = self.advance();
+        self.check_assertions();
+        interrupt
+    }
+
+    fn check_assertions(&mut self) {
+        let location = if self.halt { None } else { Some(self.iar) };
+        let failures: Vec<String> = self.assertions.iter()
+            .filter(|assertion| assertion.location == location)
+            .filter_map(|assertion| assertion.check(self).err())
+            .collect();
+        self.assertion_failures.extend(failures);
+        self.location_checked = true;
+    }
+
+    // advances timers and devices and enters the interrupt handler if requested
+    fn advance(&mut self) -> Option<InterruptSource> {
+        self.steps += 1;
+        for (index, (_, device)) in self.devices.iter_mut().enumerate() {
+            if device.tick() {
+                if let Some(controller) = &mut self.interrupts {
+                    controller.raise(InterruptSource::Device(index));
+                }
+            }
+        }
+        if let Some(controller) = &mut self.interrupts {
+            controller.tick();
+        }
+        if self.halt {
+            return None;
+        }
+        let controller = self.interrupts.as_mut()?;
+        let source = controller.take_next()?;
+        controller.enabled = false;
+        let (save_addr, vector) = (controller.save_addr, controller.vector);
+        self.write_mem(save_addr, self.iar);
+        // write_mem keeps the addresses within memory
+        self.write_mem(save_addr.wrapping_add(1), self.accu);
+        self.write_mem(save_addr.wrapping_add(2), source.cause());
+        self.write_iar(vector);
+        Some(source)
     }
 
     pub fn next_instruction(&self) -> Result<Instruction, String> {
-        Instruction::try_from(self.read_mem(self.read_iar()))
+        self.isa.decode(self.read_mem(self.read_iar()))
     }
 
     pub fn next_instruction_addr(&self) -> MimaAddress {
-        coerce_mima_address(self.read_iar() + 1)
+        self.isa.coerce_address(self.read_iar() + 1)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::Isa;
+    use crate::devices::ConsoleDevice;
+
+    // loads the instructions followed by HALT, with the data placed after them
+    fn load(isa: Isa, program: &[(Opcode, MimaAddress)], data: &[MimaValue]) -> Runtime {
+        let isa = InstructionSet::from(isa);
+        let mut memory: Vec<MimaValue> = program.iter()
+            .chain(&[(Opcode::HALT, 0)])
+            .map(|(opcode, arg)| isa.encode(&Instruction { opcode: *opcode, arg: *arg }).unwrap())
+            .collect();
+        memory.extend(data);
+        Runtime::with_memory(memory).with_isa(isa)
     }
-}
\ No newline at end of file
+
+    fn run(isa: Isa, program: &[(Opcode, MimaAddress)], data: &[MimaValue]) -> Runtime {
+        let mut runtime = load(isa, program, data);
+        runtime.run().unwrap();
+        runtime
+    }
+
+    #[test]
+    fn not_complements_the_accumulator() {
+        let runtime = run(Isa::Standard, &[(Opcode::LDC, 0x0f00f), (Opcode::NOT, 0)], &[]);
+        assert_eq!(runtime.read_accu(), 0xff0ff0);
+        let runtime = run(Isa::Standard, &[(Opcode::LDC, 0), (Opcode::NOT, 0)], &[]);
+        assert_eq!(runtime.read_accu(), 0xffffff);
+    }
+
+    #[test]
+    fn rar_rotates_within_the_value_width() {
+        let runtime = run(Isa::Standard, &[(Opcode::LDC, 0x3), (Opcode::RAR, 0)], &[]);
+        assert_eq!(runtime.read_accu(), 0x800001);
+        let runtime = run(Isa::Standard, &[(Opcode::LDV, 3), (Opcode::RAR, 0)], &[0xfffffe]);
+        assert_eq!(runtime.read_accu(), 0x7fffff);
+    }
+
+    #[test]
+    fn sub_wraps_and_borrows() {
+        let runtime = run(Isa::Extended, &[(Opcode::LDC, 3), (Opcode::SUB, 3)], &[5]);
+        assert_eq!(runtime.read_accu(), 0xfffffe);
+        let mut runtime = load(Isa::Extended, &[(Opcode::LDC, 3), (Opcode::SUB, 3)], &[5]).with_status_flags(true);
+        runtime.run().unwrap();
+        assert_eq!(runtime.read_flags(), Some(StatusFlags { carry: true, overflow: false }));
+        assert_eq!(runtime.read_flags().unwrap().to_string(), "C=1 V=0");
+    }
+
+    #[test]
+    fn mul_keeps_the_low_bits_of_the_product() {
+        let runtime = run(Isa::Extended, &[(Opcode::LDC, 0x1001), (Opcode::MUL, 3)], &[0x1001]);
+        assert_eq!(runtime.read_accu(), 0x002001);
+        let runtime = run(Isa::Extended, &[(Opcode::LDV, 3), (Opcode::MUL, 4)], &[0xfffffd, 2]);
+        assert_eq!(runtime.isa().to_signed(runtime.read_accu()), -6);
+    }
+
+    #[test]
+    fn ral_rotates_within_the_value_width() {
+        let runtime = run(Isa::Extended, &[(Opcode::LDV, 3), (Opcode::RAL, 0)], &[0x800001]);
+        assert_eq!(runtime.read_accu(), 0x000003);
+    }
+
+    #[test]
+    fn stack_grows_downwards() {
+        let program = [(Opcode::LDC, 0x50), (Opcode::STSP, 0), (Opcode::CALL, 4), (Opcode::HALT, 0),
+                       (Opcode::LDSP, 0), (Opcode::STV, 0x40), (Opcode::RET, 0)];
+        let runtime = run(Isa::Extended, &program, &[]);
+        // the return address was pushed below the initial stack pointer, which is restored on return
+        assert_eq!(runtime.read_mem(0x4f), 3);
+        assert_eq!(runtime.read_mem(0x40), 0x4f);
+        assert_eq!(runtime.read_sp(), 0x50);
+        assert_eq!(runtime.read_iar(), 4);
+    }
+
+    #[test]
+    fn stack_pointer_wraps_around_memory() {
+        // the first CALL pushes to the last address of memory
+        let runtime = run(Isa::Extended, &[(Opcode::CALL, 1)], &[]);
+        assert_eq!((runtime.read_sp(), runtime.read_mem(0xfffff)), (0xfffff, 1));
+        // RET on an empty stack pops whatever is at address 0 and moves the stack pointer past it
+        let mut runtime = load(Isa::Extended, &[(Opcode::RET, 0)], &[]);
+        let ret = runtime.read_mem(0);
+        runtime.step().unwrap();
+        assert_eq!((runtime.read_iar(), runtime.read_sp()), (ret & 0xfffff, 1));
+    }
+
+    #[test]
+    fn stsp_keeps_the_address_bits() {
+        let runtime = run(Isa::Extended, &[(Opcode::LDC, 0xa), (Opcode::NOT, 0), (Opcode::STSP, 0),
+                                           (Opcode::LDC, 0), (Opcode::LDSP, 0)], &[]);
+        assert_eq!((runtime.read_sp(), runtime.read_accu()), (0xffff5, 0xffff5));
+    }
+
+    // the interrupt handler starts at 0x20, the save area at 0x60
+    fn with_handler(mut runtime: Runtime, handler: &[(Opcode, MimaAddress)], timer: Option<u64>) -> Runtime {
+        for (i, (opcode, arg)) in handler.iter().enumerate() {
+            let instr = runtime.isa().encode(&Instruction { opcode: *opcode, arg: *arg }).unwrap();
+            runtime.write_mem(0x20 + i as MimaAddress, instr);
+        }
+        let controller = InterruptController::new(0x20, 0x60);
+        runtime.with_interrupts(match timer {
+            Some(interval) => controller.with_timer(interval),
+            None => controller
+        })
+    }
+
+    #[test]
+    fn timer_interrupts_save_iar_accu_and_cause() {
+        let program = [(Opcode::LDC, 5), (Opcode::EI, 0), (Opcode::JMP, 2)];
+        let mut runtime = with_handler(load(Isa::Extended, &program, &[]), &[(Opcode::HALT, 0)], Some(4));
+        runtime.run().unwrap();
+        // entered after the fourth instruction, the first JMP
+        assert_eq!(runtime.read_steps(), 5);
+        assert_eq!((runtime.read_mem(0x60), runtime.read_mem(0x61), runtime.read_mem(0x62)), (2, 5, 0));
+        assert!(!runtime.interrupts().unwrap().enabled);
+    }
+
+    #[test]
+    fn interrupts_wait_while_disabled() {
+        let program = [(Opcode::EI, 0), (Opcode::DI, 0), (Opcode::JMP, 2)];
+        let mut runtime = with_handler(load(Isa::Extended, &program, &[]), &[(Opcode::HALT, 0)], Some(2));
+        for _ in 0..10 {
+            runtime.step().unwrap();
+        }
+        assert_eq!(runtime.read_iar(), 2);
+        assert_eq!(runtime.interrupts().unwrap().pending().collect::<Vec<_>>(), vec![&InterruptSource::Timer]);
+        // without EI interrupts are never serviced
+        let mut runtime = with_handler(load(Isa::Extended, &[(Opcode::JMP, 0)], &[]), &[(Opcode::HALT, 0)], Some(1));
+        for _ in 0..10 {
+            runtime.step().unwrap();
+        }
+        assert!(!runtime.halt);
+    }
+
+    #[test]
+    fn reti_restores_iar_and_accu() {
+        let program = [(Opcode::LDC, 5), (Opcode::EI, 0), (Opcode::STV, 0x40)];
+        let handler = [(Opcode::LDC, 7), (Opcode::RETI, 0)];
+        let mut runtime = with_handler(load(Isa::Extended, &program, &[]), &handler, Some(3));
+        runtime.run().unwrap();
+        // the handler ran between STV and HALT
+        assert_eq!((runtime.read_mem(0x60), runtime.read_mem(0x61)), (3, 5));
+        assert_eq!((runtime.read_accu(), runtime.read_iar(), runtime.read_mem(0x40)), (5, 4, 5));
+        assert!(runtime.interrupts().unwrap().enabled);
+    }
+
+    #[test]
+    fn save_area_wraps_within_memory() {
+        let mut runtime = load(Isa::Extended, &[(Opcode::LDC, 5), (Opcode::EI, 0), (Opcode::JMP, 2)], &[])
+            .with_interrupts(InterruptController::new(0x3, MimaAddress::MAX).with_timer(3));
+        runtime.write_mem(3, runtime.isa().encode(&Instruction { opcode: Opcode::HALT, arg: 0 }).unwrap());
+        runtime.run().unwrap();
+        assert_eq!((runtime.read_mem(0xfffff), runtime.read_mem(0), runtime.read_mem(1)), (2, 5, 0));
+    }
+
+    #[test]
+    fn console_input_raises_an_interrupt() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let runtime = load(Isa::Extended, &[(Opcode::EI, 0), (Opcode::JMP, 1)], &[])
+            .with_device(0x50, Box::new(ConsoleDevice::default().with_input(receiver)));
+        let handler = [(Opcode::LDV, 0x51), (Opcode::STV, 0x40), (Opcode::STV, 0x51), (Opcode::HALT, 0)];
+        let mut runtime = with_handler(runtime, &handler, None);
+        for _ in 0..5 {
+            runtime.step().unwrap();
+        }
+        assert_eq!(runtime.read_iar(), 1);
+        sender.send(b'a').unwrap();
+        runtime.run().unwrap();
+        // the cause of the first device is 1
+        assert_eq!((runtime.read_mem(0x40), runtime.read_mem(0x62)), (b'a' as MimaValue, 1));
+        assert_eq!(runtime.read_mem(0x51), 0);
+    }
+}
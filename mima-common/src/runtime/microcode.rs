@@ -0,0 +1,521 @@
+use crate::types::MimaValue;
+use crate::instructions::{Instruction, Opcode};
+use super::Runtime;
+use std::fmt;
+
+// number of consecutive cycles a memory read or write has to be requested for
+pub const MEMORY_LATENCY: u8 = 3;
+pub const FETCH_CYCLES: u8 = 5;
+pub const DECODE_CYCLE: u8 = FETCH_CYCLES + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Fetch,
+    Decode,
+    Execute
+}
+
+// the register transfers performed during a single clock cycle
+#[derive(Debug)]
+pub struct MicroStep {
+    pub phase: Phase,
+    pub cycle: u8,
+    pub transfers: Vec<String>
+}
+
+impl fmt::Display for MicroStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let transfers = if self.transfers.is_empty() {
+            "-".to_owned()
+        } else {
+            self.transfers.join("; ")
+        };
+        write!(f, "[{:?} {}] {}", self.phase, self.cycle, transfers)
+    }
+}
+
+impl Runtime {
+
+    // the number of clock cycles of the current instruction that have been executed.
+    // 0 means the runtime is at an instruction boundary.
+    pub fn read_cycle(&self) -> u8 {
+        self.cycle
+    }
+
+    // executes a single clock cycle of the textbook microprogram
+    pub fn micro_step(&mut self) -> Result<MicroStep, String> {
+        if self.halt {
+            return Err("MIMA is halted".to_owned())
+        }
+        if self.cycle == 0 && !self.location_checked {
+            self.check_assertions();
+        }
+        let cycle = self.cycle + 1;
+        let mut transfers = Vec::new();
+        let mut done = false;
+        let phase = match cycle {
+            1..=FETCH_CYCLES => {
+                self.fetch_cycle(cycle, &mut transfers);
+                Phase::Fetch
+            }
+            DECODE_CYCLE => {
+                match self.isa.decode(self.ir) {
+                    Ok(instr) => {
+                        transfers.push(format!("decode {}", self.isa.format(&instr)));
+                        self.decoded = Some(instr);
+                    }
+                    Err(e) => {
+                        // like step(), IAR and IR are left at the instruction that cannot be decoded.
+                        // The SAR still holds its address and its assertions were checked in the first cycle
+                        self.iar = self.sar;
+                        self.ir = self.previous_ir;
+                        self.location_checked = true;
+                        self.cycle = 0;
+                        return Err(format!("Decode failure - {}", e));
+                    }
+                }
+                Phase::Decode
+            }
+            _ => {
+                let instr = self.decoded.expect("instruction must be decoded before execution");
+                done = self.execute_cycle(instr.opcode, cycle - DECODE_CYCLE, &mut transfers);
+                Phase::Execute
+            }
+        };
+        if done {
+            self.cycle = 0;
+            self.decoded = None;
+            if let Some(source) = self.finish_instruction() {
+                transfers.push(format!("interrupt {}", source));
+            }
+        } else {
+            self.cycle = cycle;
+        }
+        Ok(MicroStep { phase, cycle, transfers })
+    }
+
+    fn fetch_cycle(&mut self, cycle: u8, transfers: &mut Vec<String>) {
+        match cycle {
+            1 => {
+                self.sar = self.iar;
+                self.x = self.iar;
+                transfers.extend(vec!["IAR -> SAR".to_owned(), "IAR -> X".to_owned()]);
+                self.mem_read_cycle(transfers);
+            }
+            2 => {
+                self.y = 1;
+                transfers.push("ONE -> Y".to_owned());
+                self.mem_read_cycle(transfers);
+            }
+            3 => {
+                self.alu_cycle("ADD", |x, y| x.wrapping_add(y), transfers);
+                self.mem_read_cycle(transfers);
+            }
+            4 => {
+                self.write_iar(self.z);
+                transfers.push("Z -> IAR".to_owned());
+            }
+            _ => {
+                self.previous_ir = self.ir;
+                self.ir = self.sdr;
+                transfers.push("SDR -> IR".to_owned());
+            }
+        }
+    }
+
+    // executes the given cycle (starting at 1) of the execute phase and
+    // returns whether it was the last one
+    fn execute_cycle(&mut self, opcode: Opcode, cycle: u8, transfers: &mut Vec<String>) -> bool {
+        let max = self.isa.max_value();
+        let bits = self.isa.value_bits;
+        match opcode {
+            Opcode::LDC => {
+                self.write_accu(self.ir & self.isa.max_address());
+                transfers.push("IR -> Akku".to_owned());
+                true
+            }
+            Opcode::LDV => self.load_cycle(cycle, transfers),
+            Opcode::STV => self.store_cycle(cycle, transfers),
+            Opcode::ADD => self.alu_mem_cycle(cycle, "ADD", |x, y| x.wrapping_add(y), transfers),
+            Opcode::AND => self.alu_mem_cycle(cycle, "AND", |x, y| x & y, transfers),
+            Opcode::OR => self.alu_mem_cycle(cycle, "OR", |x, y| x | y, transfers),
+            Opcode::XOR => self.alu_mem_cycle(cycle, "XOR", |x, y| x ^ y, transfers),
+            Opcode::EQL => self.alu_mem_cycle(cycle, "EQL",
+                                              |x, y| if x == y { max } else { 0 }, transfers),
+            Opcode::JMP => {
+                self.write_iar(self.ir);
+                transfers.push("IR -> IAR".to_owned());
+                true
+            }
+            Opcode::JMN => {
+                if self.isa.is_negative(self.accu) {
+                    self.write_iar(self.ir);
+                    transfers.push("IR -> IAR".to_owned());
+                }
+                true
+            }
+            Opcode::LDIV => match cycle {
+                1..=3 => self.indirect_cycle(cycle, transfers),
+                _ => self.load_cycle(cycle - 3, transfers)
+            },
+            Opcode::STIV => match cycle {
+                1..=3 => self.indirect_cycle(cycle, transfers),
+                _ => self.store_cycle(cycle - 3, transfers)
+            },
+            Opcode::HALT => {
+                self.halt = true;
+                transfers.push("halt".to_owned());
+                true
+            }
+            Opcode::NOT => self.alu_accu_cycle(cycle, "NOT", |x, _| !x, transfers),
+            Opcode::RAR => self.alu_accu_cycle(
+                cycle, "RAR", |x, _| (x >> 1) | ((x & 1) << (bits - 1)), transfers),
+            Opcode::SUB => self.alu_mem_cycle(cycle, "SUB", |x, y| x.wrapping_sub(y), transfers),
+            Opcode::MUL => self.alu_mem_cycle(cycle, "MUL", |x, y| x.wrapping_mul(y), transfers),
+            Opcode::CALL => self.call_cycle(cycle, transfers),
+            Opcode::RET => self.ret_cycle(cycle, transfers),
+            Opcode::RAL => self.alu_accu_cycle(
+                cycle, "RAL", |x, _| (x << 1) | (x >> (bits - 1)), transfers),
+            Opcode::LDSP => {
+                self.write_accu(self.sp);
+                transfers.push("SP -> Akku".to_owned());
+                true
+            }
+            Opcode::STSP => {
+                self.write_sp(self.accu);
+                transfers.push("Akku -> SP".to_owned());
+                true
+            }
+            Opcode::EI | Opcode::DI => {
+                let enabled = opcode == Opcode::EI;
+                self.set_interrupts_enabled(enabled);
+                transfers.push(format!("{} -> IE", enabled as u8));
+                true
+            }
+            Opcode::RETI => self.reti_cycle(cycle, transfers)
+        }
+    }
+
+    // restores the accumulator and then the IAR from the interrupt save area
+    fn reti_cycle(&mut self, cycle: u8, transfers: &mut Vec<String>) -> bool {
+        let save_addr = match self.interrupts.as_ref() {
+            Some(controller) => controller.save_addr,
+            None => return true
+        };
+        match cycle {
+            1 | 5 => {
+                self.sar = self.isa.coerce_address(if cycle == 1 { save_addr.wrapping_add(1) } else { save_addr });
+                transfers.push(format!("{:#x} -> SAR", self.sar));
+                self.mem_read_cycle(transfers);
+            }
+            2 | 3 | 6 | 7 => self.mem_read_cycle(transfers),
+            4 => {
+                self.write_accu(self.sdr);
+                transfers.push("SDR -> Akku".to_owned());
+            }
+            _ => {
+                self.write_iar(self.sdr);
+                self.set_interrupts_enabled(true);
+                transfers.extend(vec!["SDR -> IAR".to_owned(), "1 -> IE".to_owned()]);
+                return true;
+            }
+        }
+        false
+    }
+
+    // decrements the SP, writes the IAR to the new top of the stack and jumps to the address in IR
+    fn call_cycle(&mut self, cycle: u8, transfers: &mut Vec<String>) -> bool {
+        match cycle {
+            1 => {
+                self.x = self.sp;
+                transfers.push("SP -> X".to_owned());
+            }
+            2 => {
+                self.y = 1;
+                transfers.push("ONE -> Y".to_owned());
+            }
+            3 => self.alu_cycle("SUB", |x, y| x.wrapping_sub(y), transfers),
+            4 => {
+                self.write_sp(self.z);
+                self.sar = self.sp;
+                transfers.extend(vec!["Z -> SP".to_owned(), "Z -> SAR".to_owned()]);
+            }
+            5 => {
+                self.sdr = self.iar;
+                transfers.push("IAR -> SDR".to_owned());
+                self.mem_write_cycle(transfers);
+            }
+            6 | 7 => {
+                self.mem_write_cycle(transfers);
+            }
+            _ => {
+                self.write_iar(self.ir);
+                transfers.push("IR -> IAR".to_owned());
+                return true;
+            }
+        }
+        false
+    }
+
+    // reads the return address from the top of the stack, increments the SP and jumps back
+    fn ret_cycle(&mut self, cycle: u8, transfers: &mut Vec<String>) -> bool {
+        match cycle {
+            1 => {
+                self.sar = self.sp;
+                self.x = self.sp;
+                transfers.extend(vec!["SP -> SAR".to_owned(), "SP -> X".to_owned()]);
+                self.mem_read_cycle(transfers);
+            }
+            2 => {
+                self.y = 1;
+                transfers.push("ONE -> Y".to_owned());
+                self.mem_read_cycle(transfers);
+            }
+            3 => {
+                self.alu_cycle("ADD", |x, y| x.wrapping_add(y), transfers);
+                self.mem_read_cycle(transfers);
+            }
+            4 => {
+                self.write_sp(self.z);
+                transfers.push("Z -> SP".to_owned());
+            }
+            _ => {
+                self.write_iar(self.sdr);
+                transfers.push("SDR -> IAR".to_owned());
+                return true;
+            }
+        }
+        false
+    }
+
+    // reads the address in IR into the SDR
+    fn indirect_cycle(&mut self, cycle: u8, transfers: &mut Vec<String>) -> bool {
+        if cycle == 1 {
+            self.sar = self.ir & self.isa.max_address();
+            transfers.push("IR -> SAR".to_owned());
+        }
+        self.mem_read_cycle(transfers);
+        false
+    }
+
+    // loads the value at the address in IR (or in SDR for indirect instructions) into the accumulator
+    fn load_cycle(&mut self, cycle: u8, transfers: &mut Vec<String>) -> bool {
+        match cycle {
+            1 => {
+                self.address_to_sar(transfers);
+                self.mem_read_cycle(transfers);
+                false
+            }
+            2 | 3 => {
+                self.mem_read_cycle(transfers);
+                false
+            }
+            _ => {
+                self.write_accu(self.sdr);
+                transfers.push("SDR -> Akku".to_owned());
+                true
+            }
+        }
+    }
+
+    fn store_cycle(&mut self, cycle: u8, transfers: &mut Vec<String>) -> bool {
+        match cycle {
+            1 => {
+                self.address_to_sar(transfers);
+                false
+            }
+            2 => {
+                self.sdr = self.accu;
+                transfers.push("Akku -> SDR".to_owned());
+                self.mem_write_cycle(transfers)
+            }
+            _ => self.mem_write_cycle(transfers)
+        }
+    }
+
+    fn alu_mem_cycle<F: FnOnce(MimaValue, MimaValue) -> MimaValue>(
+        &mut self, cycle: u8, name: &str, op: F, transfers: &mut Vec<String>
+    ) -> bool {
+        match cycle {
+            1 => {
+                self.address_to_sar(transfers);
+                self.mem_read_cycle(transfers);
+            }
+            2 => {
+                self.x = self.accu;
+                transfers.push("Akku -> X".to_owned());
+                self.mem_read_cycle(transfers);
+            }
+            3 => self.mem_read_cycle(transfers),
+            4 => {
+                self.y = self.sdr;
+                transfers.push("SDR -> Y".to_owned());
+            }
+            5 => {
+                self.alu_cycle(name, op, transfers);
+                if let Some(instr) = self.decoded {
+                    self.record_flags(instr.opcode);
+                }
+            }
+            _ => {
+                self.write_accu(self.z);
+                transfers.push("Z -> Akku".to_owned());
+                return true;
+            }
+        }
+        false
+    }
+
+    fn alu_accu_cycle<F: FnOnce(MimaValue, MimaValue) -> MimaValue>(
+        &mut self, cycle: u8, name: &str, op: F, transfers: &mut Vec<String>
+    ) -> bool {
+        match cycle {
+            1 => {
+                self.x = self.accu;
+                transfers.push("Akku -> X".to_owned());
+                false
+            }
+            2 => {
+                self.alu_cycle(name, op, transfers);
+                false
+            }
+            _ => {
+                self.write_accu(self.z);
+                transfers.push("Z -> Akku".to_owned());
+                true
+            }
+        }
+    }
+
+    fn alu_cycle<F: FnOnce(MimaValue, MimaValue) -> MimaValue>(
+        &mut self, name: &str, op: F, transfers: &mut Vec<String>
+    ) {
+        self.z = self.isa.coerce_value(op(self.x, self.y));
+        transfers.push(format!("ALU {}", name));
+    }
+
+    // moves the operand address to the SAR: from IR for direct, from SDR for indirect instructions
+    fn address_to_sar(&mut self, transfers: &mut Vec<String>) {
+        let indirect = matches!(self.decoded, Some(Instruction { opcode: Opcode::LDIV, .. })
+            | Some(Instruction { opcode: Opcode::STIV, .. }));
+        if indirect {
+            self.sar = self.sdr & self.isa.max_address();
+            transfers.push("SDR -> SAR".to_owned());
+        } else {
+            self.sar = self.ir & self.isa.max_address();
+            transfers.push("IR -> SAR".to_owned());
+        }
+    }
+
+    // requests a memory read for one cycle; the SDR is loaded once the latency has passed
+    fn mem_read_cycle(&mut self, transfers: &mut Vec<String>) {
+        transfers.push("R = 1".to_owned());
+        self.mem_cycles += 1;
+        if self.mem_cycles == MEMORY_LATENCY {
+            self.mem_cycles = 0;
+            self.sdr = self.read_mem(self.sar);
+            transfers.push("M[SAR] -> SDR".to_owned());
+        }
+    }
+
+    // requests a memory write for one cycle and returns whether it has completed
+    fn mem_write_cycle(&mut self, transfers: &mut Vec<String>) -> bool {
+        transfers.push("W = 1".to_owned());
+        self.mem_cycles += 1;
+        if self.mem_cycles == MEMORY_LATENCY {
+            self.mem_cycles = 0;
+            self.write_mem(self.sar, self.sdr);
+            transfers.push("SDR -> M[SAR]".to_owned());
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::Isa;
+    use crate::interrupts::InterruptController;
+    use crate::isa::InstructionSet;
+    use strum::IntoEnumIterator;
+
+    // an extended runtime executing the given instruction, set up so that it has an effect:
+    // the accumulator is negative, 0x40 holds a value, 0x41 points to 0x40 and the stack holds a return address
+    fn runtime(opcode: Opcode) -> Runtime {
+        let isa = InstructionSet::from(Isa::Extended);
+        let arg = if matches!(opcode, Opcode::LDIV | Opcode::STIV) { 0x41 } else { 0x40 };
+        let mut memory = vec![0; 0x70];
+        memory[0] = isa.encode(&Instruction { opcode, arg }).unwrap();
+        memory[0x40] = 7;
+        memory[0x41] = 0x40;
+        memory[0x50] = 0x42;
+        memory[0x61] = 0x123;
+        memory[0x60] = 0x43;
+        let mut runtime = Runtime::with_memory(memory)
+            .with_isa(isa)
+            .with_status_flags(true)
+            .with_interrupts(InterruptController::new(0x30, 0x60));
+        runtime.write_accu(0x800005);
+        runtime.write_sp(0x50);
+        runtime
+    }
+
+    fn registers(runtime: &Runtime) -> Vec<u32> {
+        vec![runtime.accu, runtime.iar, runtime.ir, runtime.sar, runtime.sdr,
+             runtime.x, runtime.y, runtime.z, runtime.sp, runtime.steps as u32, runtime.halt as u32]
+    }
+
+    // clock cycles of every instruction, including fetch and decode
+    fn cycles(opcode: Opcode) -> u8 {
+        DECODE_CYCLE + match opcode {
+            Opcode::LDV => 4,
+            Opcode::STV => 4,
+            Opcode::ADD | Opcode::AND | Opcode::OR | Opcode::XOR | Opcode::EQL | Opcode::SUB | Opcode::MUL => 6,
+            Opcode::LDIV | Opcode::STIV => 7,
+            Opcode::NOT | Opcode::RAR | Opcode::RAL => 3,
+            Opcode::CALL | Opcode::RETI => 8,
+            Opcode::RET => 5,
+            _ => 1
+        }
+    }
+
+    #[test]
+    fn micro_steps_have_the_effect_of_a_step() {
+        for opcode in Opcode::iter() {
+            let mut stepped = runtime(opcode);
+            stepped.step().unwrap();
+            let mut micro_stepped = runtime(opcode);
+            let mut count = 0;
+            loop {
+                let micro_step = micro_stepped.micro_step().unwrap();
+                count += 1;
+                assert_eq!(micro_step.cycle, count, "{:?}", opcode);
+                if micro_stepped.read_cycle() == 0 {
+                    break;
+                }
+            }
+            assert_eq!(count, cycles(opcode), "{:?}", opcode);
+            assert_eq!(registers(&micro_stepped), registers(&stepped), "{:?}", opcode);
+            assert_eq!(micro_stepped.memory, stepped.memory, "{:?}", opcode);
+            assert_eq!(micro_stepped.flags, stepped.flags, "{:?}", opcode);
+            assert_eq!(micro_stepped.interrupts.unwrap().enabled, stepped.interrupts.unwrap().enabled, "{:?}", opcode);
+        }
+    }
+
+    #[test]
+    fn decode_failures_leave_iar_and_ir_at_the_instruction() {
+        let memory = || vec![0xf50000, 0x000005, 0xf00000];
+        let mut stepped = Runtime::with_memory(memory());
+        assert!(stepped.step().is_err());
+        let mut micro_stepped = Runtime::with_memory(memory());
+        for _ in 0..FETCH_CYCLES {
+            micro_stepped.micro_step().unwrap();
+        }
+        assert!(micro_stepped.micro_step().unwrap_err().starts_with("Decode failure"));
+        assert_eq!((micro_stepped.read_iar(), micro_stepped.read_ir(), micro_stepped.read_cycle()), (0, 0, 0));
+        assert_eq!((stepped.read_iar(), stepped.read_ir()), (0, 0));
+        assert!(micro_stepped.step().is_err());
+    }
+}
@@ -0,0 +1,244 @@
+use crate::types::{MimaAddress, MimaValue};
+use crate::instructions::Instruction;
+use crate::isa::InstructionSet;
+use crate::interrupts::InterruptController;
+use crate::devices::create_device;
+use super::{Runtime, StatusFlags};
+use super::microcode::{DECODE_CYCLE, MEMORY_LATENCY};
+use serde::{Serialize, Deserialize};
+use std::io::{Read, Write};
+
+const SNAPSHOT_VERSION: u32 = 1;
+
+// the complete state of a runtime: registers, memory, devices and interrupts.
+// Snapshots are stored as JSON.
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    version: u32,
+    // the program the runtime was originally started with
+    pub program: Vec<MimaValue>,
+    isa: InstructionSet,
+    registers: Registers,
+    flags: Option<StatusFlags>,
+    cycle: u8,
+    mem_cycles: u8,
+    decoded: Option<Instruction>,
+    halt: bool,
+    #[serde(default)]
+    steps: u64,
+    memory: Vec<MimaValue>,
+    interrupts: Option<InterruptController>,
+    devices: Vec<DeviceState>
+}
+
+#[derive(Serialize, Deserialize)]
+struct Registers {
+    accu: MimaValue,
+    iar: MimaAddress,
+    ir: MimaValue,
+    sar: MimaAddress,
+    sdr: MimaValue,
+    x: MimaValue,
+    y: MimaValue,
+    z: MimaValue,
+    sp: MimaAddress,
+    #[serde(default)]
+    previous_ir: MimaValue
+}
+
+#[derive(Serialize, Deserialize)]
+struct DeviceState {
+    name: String,
+    base: MimaAddress,
+    state: Vec<MimaValue>
+}
+
+impl Snapshot {
+
+    pub fn read<R: Read>(reader: R) -> Result<Self, String> {
+        let snapshot: Snapshot = serde_json::from_reader(reader)
+            .map_err(|e| format!("Invalid snapshot: {}", e))?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(format!("Unsupported snapshot version {}", snapshot.version));
+        }
+        Ok(snapshot)
+    }
+
+    pub fn write<W: Write>(&self, writer: W) -> Result<(), String> {
+        serde_json::to_writer(writer, self)
+            .map_err(|e| format!("Could not write snapshot: {}", e))
+    }
+
+    // recreates the runtime captured by this snapshot
+    pub fn restore(self) -> Result<Runtime, String> {
+        self.isa.validate()?;
+        if let Some(controller) = &self.interrupts {
+            controller.validate()?;
+        }
+        if self.memory.len() > self.isa.address_space() as usize {
+            return Err("Snapshot memory exceeds the address space of its instruction set".to_owned());
+        }
+        // the execute phase needs the decoded instruction, memory accesses complete after MEMORY_LATENCY cycles
+        if self.cycle >= DECODE_CYCLE && self.decoded.is_none() {
+            return Err(format!("Snapshot is at cycle {} of an instruction that was not decoded", self.cycle));
+        }
+        if self.mem_cycles >= MEMORY_LATENCY {
+            return Err(format!("Snapshot has a memory access at cycle {} of {}", self.mem_cycles, MEMORY_LATENCY));
+        }
+        let isa = self.isa;
+        let memory = self.memory.into_iter().map(|val| isa.coerce_value(val)).collect();
+        let mut runtime = Runtime::with_memory(memory).with_isa(isa.clone());
+        let registers = self.registers;
+        runtime.accu = isa.coerce_value(registers.accu);
+        runtime.iar = isa.coerce_address(registers.iar);
+        runtime.ir = isa.coerce_value(registers.ir);
+        runtime.sar = isa.coerce_address(registers.sar);
+        runtime.sdr = isa.coerce_value(registers.sdr);
+        runtime.x = isa.coerce_value(registers.x);
+        runtime.y = isa.coerce_value(registers.y);
+        runtime.z = isa.coerce_value(registers.z);
+        runtime.sp = isa.coerce_address(registers.sp);
+        runtime.previous_ir = isa.coerce_value(registers.previous_ir);
+        runtime.flags = self.flags;
+        runtime.cycle = self.cycle;
+        runtime.mem_cycles = self.mem_cycles;
+        runtime.decoded = self.decoded;
+        runtime.halt = self.halt;
+        runtime.steps = self.steps;
+        runtime.interrupts = self.interrupts.map(|mut controller| {
+            controller.vector = isa.coerce_address(controller.vector);
+            controller.save_addr = isa.coerce_address(controller.save_addr);
+            controller
+        });
+        for device_state in self.devices {
+            let mut device = create_device(&device_state.name)
+                .ok_or(format!("Unknown device '{}' in snapshot", device_state.name))?;
+            device.restore_state(&device_state.state);
+            runtime.devices.push((isa.coerce_address(device_state.base), device));
+        }
+        Ok(runtime)
+    }
+}
+
+impl Runtime {
+
+    // captures the current state; program is the image the runtime was started with
+    pub fn snapshot(&self, program: &[MimaValue]) -> Snapshot {
+        let used = self.memory.iter().rposition(|val| *val != 0).map_or(0, |i| i + 1);
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            program: program.to_vec(),
+            isa: self.isa.clone(),
+            registers: Registers {
+                accu: self.accu,
+                iar: self.iar,
+                ir: self.ir,
+                sar: self.sar,
+                sdr: self.sdr,
+                x: self.x,
+                y: self.y,
+                z: self.z,
+                sp: self.sp,
+                previous_ir: self.previous_ir
+            },
+            flags: self.flags,
+            cycle: self.cycle,
+            mem_cycles: self.mem_cycles,
+            decoded: self.decoded,
+            halt: self.halt,
+            steps: self.steps,
+            memory: self.memory[..used].to_vec(),
+            interrupts: self.interrupts.clone(),
+            devices: self.devices.iter()
+                .map(|(base, device)| DeviceState {
+                    name: device.name().to_owned(),
+                    base: *base,
+                    state: device.save_state()
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::ConsoleDevice;
+    use crate::instructions::{Isa, Opcode};
+    use serde_json::Value;
+    use std::io;
+
+    fn runtime() -> Runtime {
+        let isa = InstructionSet::from(Isa::Extended);
+        let program = [(Opcode::LDC, 5), (Opcode::ADD, 6), (Opcode::STV, 7), (Opcode::RAL, 0), (Opcode::STV, 0x50),
+                       (Opcode::HALT, 0)];
+        let mut memory: Vec<MimaValue> = program.iter()
+            .map(|(opcode, arg)| isa.encode(&Instruction { opcode: *opcode, arg: *arg }).unwrap())
+            .collect();
+        memory.push(3);
+        let mut runtime = Runtime::with_memory(memory)
+            .with_isa(isa)
+            .with_status_flags(true)
+            .with_interrupts(InterruptController::new(0x20, 0x60).with_timer(100))
+            .with_device(0x50, Box::new(ConsoleDevice::default()));
+        runtime.set_device_output(|| Box::new(io::sink()));
+        runtime
+    }
+
+    // the snapshot of a runtime in the middle of the ADD, as JSON
+    fn snapshot_json() -> Value {
+        let mut runtime = runtime();
+        for _ in 0..10 {
+            runtime.micro_step().unwrap();
+        }
+        let mut json = Vec::new();
+        runtime.snapshot(&[]).write(&mut json).unwrap();
+        serde_json::from_slice(&json).unwrap()
+    }
+
+    fn restore(json: &Value) -> Result<Runtime, String> {
+        Snapshot::read(json.to_string().as_bytes())?.restore()
+    }
+
+    #[test]
+    fn restores_the_state_in_the_middle_of_an_instruction() {
+        let mut original = runtime();
+        for _ in 0..10 {
+            original.micro_step().unwrap();
+        }
+        let mut restored = restore(&snapshot_json()).unwrap();
+        restored.set_device_output(|| Box::new(io::sink()));
+        assert_eq!(restored.read_cycle(), original.read_cycle());
+        original.run().unwrap();
+        restored.run().unwrap();
+        for runtime in [&original, &restored] {
+            assert_eq!((runtime.read_accu(), runtime.read_mem(7), runtime.read_steps()), (0x10, 8, 6));
+            assert_eq!(runtime.read_mem(0x50), 1);
+        }
+        assert_eq!(restored.read_flags(), original.read_flags());
+        assert_eq!(restored.interrupts().unwrap().timer_remaining(), original.interrupts().unwrap().timer_remaining());
+        assert_eq!(restored.mem_iter().collect::<Vec<_>>(), original.mem_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn rejects_execution_without_a_decoded_instruction() {
+        let mut json = snapshot_json();
+        json["cycle"] = Value::from(9);
+        json["decoded"] = Value::Null;
+        assert_eq!(restore(&json).err().unwrap(), "Snapshot is at cycle 9 of an instruction that was not decoded");
+        let mut json = snapshot_json();
+        json["mem_cycles"] = Value::from(3);
+        assert!(restore(&json).is_err());
+    }
+
+    #[test]
+    fn keeps_registers_and_devices_within_their_widths() {
+        let mut json = snapshot_json();
+        json["registers"]["accu"] = Value::from(0xffffffffu32);
+        json["registers"]["iar"] = Value::from(0x123456);
+        json["devices"][0]["base"] = Value::from(0x1000050);
+        let runtime = restore(&json).unwrap();
+        assert_eq!((runtime.read_accu(), runtime.read_iar()), (0xffffff, 0x23456));
+        assert_eq!(runtime.devices().next().map(|(base, _)| base), Some(0x50));
+    }
+}
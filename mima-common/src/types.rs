@@ -21,18 +21,23 @@ pub trait ReadMimaExt: ReadBytesExt {
     }
 
     fn read_all_mima_vals(&mut self) -> io::Result<Vec<MimaValue>> {
+        self.read_all_mima_vals_sized(VALUE_BYTES)
+    }
+
+    // reads values that are value_bytes wide, for instruction sets with non-standard widths
+    fn read_all_mima_vals_sized(&mut self, value_bytes: u8) -> io::Result<Vec<MimaValue>> {
         let mut bytes = Vec::new();
         let byte_count = self.read_to_end(&mut bytes)?;
-        if byte_count % (VALUE_BYTES as usize) != 0 {
+        if byte_count % (value_bytes as usize) != 0 {
             return io::Result::Err(
                 io::Error::new(io::ErrorKind::InvalidData,
                                "Cannot read mima values: input is malformed"));
         }
-        let value_count = byte_count / (VALUE_BYTES as usize);
+        let value_count = byte_count / (value_bytes as usize);
         let mut cursor = Cursor::new(bytes);
-        let mut values = Vec::with_capacity(byte_count / 3);
+        let mut values = Vec::with_capacity(value_count);
         for _i in 0..value_count {
-            values.push(cursor.read_mima_val().unwrap())
+            values.push(cursor.read_uint::<BigEndian>(value_bytes as usize).unwrap() as MimaValue)
         }
         Ok(values)
     }
@@ -44,8 +49,12 @@ pub trait WriteMimaExt: WriteBytesExt {
     }
 
     fn write_all_mima_vals(&mut self, vals: &[MimaValue]) -> io::Result<()> {
+        self.write_all_mima_vals_sized(vals, VALUE_BYTES)
+    }
+
+    fn write_all_mima_vals_sized(&mut self, vals: &[MimaValue], value_bytes: u8) -> io::Result<()> {
         for val in vals {
-            self.write_mima_val(*val)?;
+            self.write_uint::<BigEndian>(*val as u64, value_bytes as usize)?;
         }
         Ok(())
     }
@@ -55,20 +64,21 @@ impl<R: ReadBytesExt> ReadMimaExt for R {}
 
 impl<W: WriteBytesExt> WriteMimaExt for W {}
 
-pub fn parse_mima_addr(s: &str) -> Result<MimaAddress, ParseIntError> {
-    (if let Some(hex) = s.strip_prefix("0x") {
-        MimaAddress::from_str_radix(hex, 16)
+// parses a decimal or hex number without restricting it to the standard widths
+pub fn parse_mima_number(s: &str) -> Result<u32, ParseIntError> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
     } else {
-        MimaAddress::from_str(s)
-    }).map(|v| coerce_mima_address(v))
+        u32::from_str(s)
+    }
+}
+
+pub fn parse_mima_addr(s: &str) -> Result<MimaAddress, ParseIntError> {
+    parse_mima_number(s).map(coerce_mima_address)
 }
 
 pub fn parse_mima_value(s: &str) -> Result<MimaValue, ParseIntError> {
-    (if let Some(hex) = s.strip_prefix("0x") {
-        MimaValue::from_str_radix(hex, 16)
-    } else {
-        MimaValue::from_str(s)
-    }).map(|v| coerce_mima_value(v))
+    parse_mima_number(s).map(coerce_mima_value)
 }
 
 
@@ -86,4 +96,42 @@ pub fn signum(num: MimaValue) -> u8 {
 
 pub fn is_negative(num: MimaValue) -> bool {
     signum(num) == 1
-}
\ No newline at end of file
+}
+
+// interprets a value as a 24 bit two's complement number
+pub fn to_i32(num: MimaValue) -> i32 {
+    if is_negative(num) {
+        num as i32 - VALUE_SPACE as i32
+    } else {
+        num as i32
+    }
+}
+
+// converts a number to its 24 bit two's complement representation, wrapping if it does not fit
+pub fn from_i32(num: i32) -> MimaValue {
+    coerce_mima_value(num as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_signed_values_at_the_boundaries() {
+        let min = -(1 << 23);
+        let max = (1 << 23) - 1;
+        assert_eq!(to_i32(0x800000), min);
+        assert_eq!(to_i32(0x7fffff), max);
+        assert_eq!(to_i32(0xffffff), -1);
+        assert_eq!(to_i32(0), 0);
+        assert_eq!(from_i32(min), 0x800000);
+        assert_eq!(from_i32(max), 0x7fffff);
+        assert_eq!(from_i32(-1), 0xffffff);
+    }
+
+    #[test]
+    fn wraps_numbers_that_do_not_fit() {
+        assert_eq!(from_i32(1 << 23), 0x800000);
+        assert_eq!(to_i32(from_i32(-(1 << 23) - 1)), (1 << 23) - 1);
+    }
+}
@@ -1,7 +1,19 @@
 use mima_common::instructions::{Instruction, Opcode};
-use mima_common::types::{MimaAddress, coerce_mima_address, parse_mima_addr};
-use std::collections::{HashMap, HashSet};
+use mima_common::isa::InstructionSet;
+use mima_common::types::{MimaAddress, MimaValue, parse_mima_number};
+use mima_common::assertions::{Assertion, AssertionTarget, Comparison};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 use std::str::FromStr;
+use crate::symbols::SymbolTable;
+
+// the result of assembling a source file
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    pub symbols: SymbolTable,
+    // source line of every instruction
+    pub lines: Vec<usize>
+}
 
 // interim representation of address arguments
 #[derive(Debug)]
@@ -10,15 +22,28 @@ enum InterimAddr {
     Real(MimaAddress),
 }
 
-pub fn assemble(input: String, absolute_addresses: bool) -> Result<Vec<Instruction>, String> {
+// an assertion directive whose addresses have not been resolved yet. A target of None is the accumulator
+struct InterimAssertion {
+    line_num: usize,
+    location: Option<InterimAddr>,
+    target: Option<InterimAddr>,
+    comparison: Comparison,
+    value: MimaValue,
+    description: String
+}
+
+pub fn assemble(input: String, absolute_addresses: bool, isa: &InstructionSet) -> Result<Program, String> {
     let mut addr_labels = HashMap::<String, MimaAddress>::new();
-    let mut addr_templates = HashSet::<String>::new();
+    // in order of first use, so that template addresses are the same on every run
+    let mut addr_templates = Vec::<String>::new();
     let mut instr_templates = Vec::<(Opcode, Option<InterimAddr>)>::new();
+    let mut assertion_templates = Vec::<InterimAssertion>::new();
     let mut cur_instr_addr = 0;
     let mut highest_addr_in_use = 0;
-    let mut line_num = 1;
+    let mut lines = Vec::new();
 
-    for line in input.lines() {
+    for (index, line) in input.lines().enumerate() {
+        let line_num = index + 1;
         // get all tokens until comments, if any
         let mut tokens: Vec<&str> = line.split_whitespace()
             .take_while(|s| !s.starts_with(';'))
@@ -37,12 +62,19 @@ pub fn assemble(input: String, absolute_addresses: bool) -> Result<Vec<Instructi
                                    line_num, label));
             }
             addr_labels.insert(label.to_owned(), cur_instr_addr);
+        } else if token.starts_with('.') {
+            assertion_templates.push(parse_assertion(token, &tokens, line_num, isa)?);
         } else {
             // else parse instruction
             let opcode_token = token.to_uppercase();
-            let opcode = Opcode::from_str(&opcode_token)
-                .map_err(|_e| format!("Line {}: Unknown mnemonic opcode '{}'",
-                                     line_num, &opcode_token))?;
+            let opcode = match isa.lookup(&opcode_token) {
+                Some(opcode) => opcode,
+                None if Opcode::from_str(&opcode_token).is_ok_and(|op| op.is_extended()) =>
+                    return Err(format!("Line {}: Mnemonic '{}' requires the extended instruction set",
+                                       line_num, &opcode_token)),
+                None => return Err(format!("Line {}: Unknown mnemonic opcode '{}' for instruction set {}",
+                                           line_num, &opcode_token, isa.name))
+            };
 
             let mut possible_arg = None;
             if opcode.has_arg() {
@@ -54,7 +86,7 @@ pub fn assemble(input: String, absolute_addresses: bool) -> Result<Vec<Instructi
                 if !tokens.is_empty() {
                     return Err(format!("Line {}: Unexpected token(s) after instruction", line_num));
                 }
-                possible_arg = Some(if let Ok(val) = parse_mima_addr(arg) {
+                possible_arg = Some(if let Ok(val) = parse_mima_number(arg).map(|v| isa.coerce_address(v)) {
                     // keep track of the highest explicit address in use for address templating
                     if opcode != Opcode::LDC && val > highest_addr_in_use {
                         highest_addr_in_use = val;
@@ -62,43 +94,107 @@ pub fn assemble(input: String, absolute_addresses: bool) -> Result<Vec<Instructi
                     InterimAddr::Real(val)
                 } else {
                     // if address is not a number, add it as a template
-                    addr_templates.insert(arg.to_owned());
+                    if !addr_templates.iter().any(|t| t == arg) {
+                        addr_templates.push(arg.to_owned());
+                    }
                     InterimAddr::Template(arg.to_owned())
                 });
             }
             instr_templates.push((opcode, possible_arg));
+            lines.push(line_num);
             cur_instr_addr += 1;
         }
-        line_num += 1;
-
     }
 
     let instr_count = instr_templates.len();
     assign_template_addresses(
-        instr_count, absolute_addresses,
+        isa, instr_count, absolute_addresses,
         highest_addr_in_use,
         addr_templates, &mut addr_labels
     );
+    let assertions = assertion_templates.into_iter()
+        .map(|assertion| resolve_assertion(isa, assertion, &addr_labels, instr_count, absolute_addresses))
+        .collect::<Result<Vec<Assertion>, String>>()?;
     let instructions = construct_instructions(
-        instr_templates, addr_labels, absolute_addresses
+        isa, instr_templates, &addr_labels, absolute_addresses
     );
-    Ok(instructions)
+    let symbols = SymbolTable::from(addr_labels).with_assertions(assertions);
+    Ok(Program { instructions, symbols, lines })
+}
+
+// parses `.expect <target> <op> <value>` and `.assert_at <location> <target> <op> <value>`,
+// where the target is `accu` or a memory address
+fn parse_assertion(directive: &str, tokens: &[&str], line_num: usize, isa: &InstructionSet)
+    -> Result<InterimAssertion, String> {
+    let (location, rest) = match (directive, tokens) {
+        (".expect", rest) => (None, rest),
+        (".assert_at", [location, rest @ ..]) => (Some(interim_addr(location, isa)), rest),
+        (".assert_at", []) => return Err(format!("Line {}: Expected location after .assert_at", line_num)),
+        _ => return Err(format!("Line {}: Unknown directive '{}'", line_num, directive))
+    };
+    match rest {
+        [target, comparison, value] => Ok(InterimAssertion {
+            line_num,
+            location,
+            target: if *target == "accu" { None } else { Some(interim_addr(target, isa)) },
+            comparison: Comparison::from_str(comparison)
+                .map_err(|e| format!("Line {}: {}", line_num, e))?,
+            value: isa.parse_value(value)
+                .map_err(|_e| format!("Line {}: Invalid value '{}' in assertion", line_num, value))?,
+            description: rest.join(" ")
+        }),
+        _ => Err(format!("Line {}: Expected '<target> <comparison> <value>' after {}", line_num, directive))
+    }
+}
+
+fn interim_addr(arg: &str, isa: &InstructionSet) -> InterimAddr {
+    match parse_mima_number(arg) {
+        Ok(addr) => InterimAddr::Real(isa.coerce_address(addr)),
+        Err(_) => InterimAddr::Template(arg.to_owned())
+    }
+}
+
+// resolves the names in an assertion. Explicit target addresses are adjusted like data addresses,
+// explicit locations are used as they are
+fn resolve_assertion(
+    isa: &InstructionSet,
+    assertion: InterimAssertion,
+    labels: &HashMap<String, MimaAddress>,
+    instr_count: usize,
+    absolute_addresses: bool
+) -> Result<Assertion, String> {
+    let line_num = assertion.line_num;
+    let resolve = |addr: InterimAddr, is_data: bool| match addr {
+        InterimAddr::Template(name) => labels.get(&name).copied()
+            .ok_or(format!("Line {}: Unknown label or variable '{}' in assertion", line_num, name)),
+        InterimAddr::Real(addr) => Ok(add_offset(isa, addr, instr_count, absolute_addresses || !is_data))
+    };
+    Ok(Assertion {
+        location: assertion.location.map(|location| resolve(location, false)).transpose()?,
+        target: match assertion.target {
+            Some(target) => AssertionTarget::Memory(resolve(target, true)?),
+            None => AssertionTarget::Accu
+        },
+        comparison: assertion.comparison,
+        value: assertion.value,
+        description: assertion.description
+    })
 }
 
 // assigns all uninitialised template addresses an address in the address space,
 // avoiding conflicts with other addresses as much as possible.
 fn assign_template_addresses(
+    isa: &InstructionSet,
     instr_count: usize,
     absolute_addresses: bool,
     max_address: MimaAddress,
-    templates: HashSet<String>,
+    templates: Vec<String>,
     labels: &mut HashMap<String, MimaAddress>
 ) {
     let mut next_addr = max_address + 1;
     for template in templates {
-        if !labels.contains_key(&template) {
-            labels.insert(template,
-                          add_offset(next_addr, instr_count, absolute_addresses));
+        if let Entry::Vacant(e) = labels.entry(template) {
+            e.insert(add_offset(isa, next_addr, instr_count, absolute_addresses));
             next_addr += 1;
         }
 
@@ -106,8 +202,9 @@ fn assign_template_addresses(
 }
 
 fn construct_instructions(
+    isa: &InstructionSet,
     templates: Vec<(Opcode, Option<InterimAddr>)>,
-    labels: HashMap<String, MimaAddress>,
+    labels: &HashMap<String, MimaAddress>,
     absolute_addresses: bool
 ) -> Vec<Instruction> {
     let count = templates.len();
@@ -117,7 +214,7 @@ fn construct_instructions(
             Some(InterimAddr::Template(s)) => labels[&s],
             // add offset only if it's an unadjusted explicit address used in a non-ldc opcode
             Some(InterimAddr::Real(addr)) =>
-                add_offset(addr, count,
+                add_offset(isa, addr, count,
                            absolute_addresses || !op.has_arg() || op == Opcode::LDC),
             None => 0
         };
@@ -131,6 +228,38 @@ fn construct_instructions(
 }
 
 // adds an appropriate offset to the given address or does nothing when unchanged is true
-fn add_offset(addr: MimaAddress, instr_count: usize, unchanged: bool) -> MimaAddress {
-    coerce_mima_address(addr + (if unchanged { 0 } else { instr_count as u32 + 1 }))
+fn add_offset(isa: &InstructionSet, addr: MimaAddress, instr_count: usize, unchanged: bool) -> MimaAddress {
+    isa.coerce_address(addr + (if unchanged { 0 } else { instr_count as u32 + 1 }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mima_common::assertions::AssertionTarget;
+
+    #[test]
+    fn resolves_assertion_names() {
+        let source = "LDC 3\nSTV counter\nend:\nHALT\n.expect counter == 3\n.assert_at end accu == -1\n";
+        let program = assemble(source.to_owned(), false, &InstructionSet::default()).unwrap();
+        let counter = program.symbols.resolve("counter").unwrap();
+        let assertions = program.symbols.assertions();
+        assert_eq!(assertions[0].location, None);
+        assert_eq!(assertions[0].target, AssertionTarget::Memory(counter));
+        assert_eq!(assertions[1].location, program.symbols.resolve("end"));
+        assert_eq!((assertions[1].target, assertions[1].value), (AssertionTarget::Accu, 0xffffff));
+        assert_eq!(assertions[1].description, "accu == -1");
+    }
+
+    #[test]
+    fn rejects_unknown_assertion_names() {
+        let result = assemble("HALT\n.expect missing == 1\n".to_owned(), false, &InstructionSet::default());
+        assert_eq!(result.err().unwrap(), "Line 2: Unknown label or variable 'missing' in assertion");
+    }
+
+    #[test]
+    fn numbers_template_variables_in_order_of_first_use() {
+        let program = assemble("LDV b\nADD a\nSTV b\nHALT\n".to_owned(), true, &InstructionSet::default()).unwrap();
+        assert_eq!(program.symbols.resolve("b"), Some(1));
+        assert_eq!(program.symbols.resolve("a"), Some(2));
+    }
 }
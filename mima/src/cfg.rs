@@ -0,0 +1,245 @@
+use crate::profile::Profile;
+use crate::symbols::SymbolTable;
+use mima_common::instructions::{Instruction, Opcode};
+use mima_common::isa::InstructionSet;
+use mima_common::types::{MimaAddress, MimaValue};
+use std::collections::{BTreeSet, HashMap};
+use strum_macros::{EnumString, EnumVariantNames};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, EnumVariantNames)]
+#[strum(serialize_all = "lowercase")]
+pub enum GraphFormat {
+    Dot,
+    Mermaid
+}
+
+// a run of instructions that is only entered at its first and left at its last instruction
+struct Block {
+    start: MimaAddress,
+    instructions: Vec<Instruction>
+}
+
+struct Edge {
+    from: MimaAddress,
+    to: MimaAddress,
+    label: &'static str
+}
+
+// builds the control flow graph of a program and renders it. Blocks start at the first instruction,
+// at labels, at jump targets and after jumps; values that are no instructions end a block
+pub fn render_cfg(program: &[MimaValue], isa: &InstructionSet, symbols: &SymbolTable,
+                  profile: Option<&Profile>, format: GraphFormat) -> String {
+    let decoded: Vec<Option<Instruction>> = program.iter().map(|val| isa.decode(*val).ok()).collect();
+    let count = decoded.len() as MimaAddress;
+    let mut leaders: BTreeSet<MimaAddress> = symbols.sorted().iter()
+        .map(|(_, addr)| *addr)
+        .filter(|addr| *addr < count)
+        .collect();
+    leaders.insert(0);
+    for (addr, instr) in decoded.iter().enumerate() {
+        let addr = addr as MimaAddress;
+        match instr {
+            Some(instr) if is_jump(instr.opcode) => {
+                leaders.insert(addr + 1);
+                if instr.opcode != Opcode::HALT && instr.opcode != Opcode::RET && instr.opcode != Opcode::RETI {
+                    leaders.insert(instr.arg);
+                }
+            }
+            Some(_) => {}
+            None => {
+                leaders.insert(addr + 1);
+            }
+        }
+    }
+
+    let mut blocks: Vec<Block> = Vec::new();
+    for (addr, instr) in decoded.iter().enumerate() {
+        let addr = addr as MimaAddress;
+        match (instr, blocks.last_mut()) {
+            (None, _) => {}
+            (Some(instr), Some(block)) if !leaders.contains(&addr)
+                && block.start + block.instructions.len() as MimaAddress == addr => block.instructions.push(*instr),
+            (Some(instr), _) => blocks.push(Block { start: addr, instructions: vec![*instr] })
+        }
+    }
+
+    let mut edges = Vec::new();
+    for block in &blocks {
+        let last = block.instructions.last().expect("blocks are never empty");
+        let last_addr = block.start + block.instructions.len() as MimaAddress - 1;
+        let next = last_addr + 1;
+        let targets: Vec<(MimaAddress, &'static str)> = match last.opcode {
+            Opcode::HALT | Opcode::RET | Opcode::RETI => Vec::new(),
+            Opcode::JMP => vec![(last.arg, "")],
+            Opcode::JMN => vec![(last.arg, "negative"), (next, "")],
+            Opcode::CALL => vec![(last.arg, "call"), (next, "return")],
+            _ => vec![(next, "")]
+        };
+        edges.extend(targets.into_iter().map(|(to, label)| Edge { from: block.start, to, label }));
+    }
+    // jumps out of the blocks end in nodes of their own
+    let outside: BTreeSet<MimaAddress> = edges.iter()
+        .map(|edge| edge.to)
+        .filter(|to| !blocks.iter().any(|block| block.start == *to))
+        .collect();
+
+    let names = symbol_names(symbols);
+    let block_lines = |block: &Block| -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(profile) = profile {
+            lines.push(format!("executed {}x", profile.get(&block.start).copied().unwrap_or(0)));
+        }
+        for (i, instr) in block.instructions.iter().enumerate() {
+            let addr = block.start + i as MimaAddress;
+            if let Some(name) = names.get(&addr) {
+                lines.push(format!("{}:", name));
+            }
+            lines.push(format!("{:#07x}  {}", addr, format_instr(instr, isa, &names)));
+        }
+        lines
+    };
+    let executed = |block: &Block| profile.is_none_or(|profile| profile.get(&block.start).is_some_and(|count| *count > 0));
+
+    let mut output = String::new();
+    match format {
+        GraphFormat::Dot => {
+            output.push_str("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+            for block in &blocks {
+                let label: String = block_lines(block).iter().map(|line| format!("{}\\l", escape_dot(line))).collect();
+                let style = if executed(block) { "" } else { ", style=dashed" };
+                output.push_str(&format!("    {} [label=\"{}\"{}];\n", node_id(block.start), label, style));
+            }
+            for addr in &outside {
+                output.push_str(&format!("    {} [label=\"{}\", shape=ellipse];\n", node_id(*addr), outside_label(*addr, count, &names)));
+            }
+            for edge in &edges {
+                let label = if edge.label.is_empty() { String::new() } else { format!(" [label=\"{}\"]", edge.label) };
+                output.push_str(&format!("    {} -> {}{};\n", node_id(edge.from), node_id(edge.to), label));
+            }
+            output.push_str("}\n");
+        }
+        GraphFormat::Mermaid => {
+            output.push_str("flowchart TD\n");
+            for block in &blocks {
+                let label: Vec<String> = block_lines(block).iter().map(|line| escape_mermaid(line)).collect();
+                output.push_str(&format!("    {}[\"{}\"]\n", node_id(block.start), label.join("<br/>")));
+            }
+            for addr in &outside {
+                output.push_str(&format!("    {}([\"{}\"])\n", node_id(*addr), outside_label(*addr, count, &names)));
+            }
+            for edge in &edges {
+                let arrow = if edge.label.is_empty() { "-->".to_owned() } else { format!("-->|{}|", edge.label) };
+                output.push_str(&format!("    {} {} {}\n", node_id(edge.from), arrow, node_id(edge.to)));
+            }
+            let unexecuted: Vec<String> = blocks.iter()
+                .filter(|block| !executed(block))
+                .map(|block| node_id(block.start))
+                .collect();
+            if !unexecuted.is_empty() {
+                output.push_str("    classDef unexecuted stroke-dasharray: 5 5\n");
+                output.push_str(&format!("    class {} unexecuted\n", unexecuted.join(",")));
+            }
+        }
+    }
+    output
+}
+
+fn is_jump(opcode: Opcode) -> bool {
+    matches!(opcode, Opcode::JMP | Opcode::JMN | Opcode::CALL | Opcode::HALT | Opcode::RET | Opcode::RETI)
+}
+
+// the first name of every address
+fn symbol_names(symbols: &SymbolTable) -> HashMap<MimaAddress, String> {
+    let mut names = HashMap::new();
+    for (name, addr) in symbols.sorted() {
+        names.entry(addr).or_insert_with(|| name.to_owned());
+    }
+    names
+}
+
+// arguments are shown by name, except for the constants of LDC
+fn format_instr(instr: &Instruction, isa: &InstructionSet, names: &HashMap<MimaAddress, String>) -> String {
+    match names.get(&instr.arg) {
+        Some(name) if instr.opcode.has_arg() && instr.opcode != Opcode::LDC =>
+            format!("{:4} {}", isa.mnemonic(instr.opcode), name),
+        _ => isa.format(instr)
+    }
+}
+
+fn node_id(addr: MimaAddress) -> String {
+    format!("block_{:x}", addr)
+}
+
+fn outside_label(addr: MimaAddress, count: MimaAddress, names: &HashMap<MimaAddress, String>) -> String {
+    match names.get(&addr) {
+        _ if addr == count => "end of program".to_owned(),
+        Some(name) => format!("{} ({:#07x})", name, addr),
+        None => format!("{:#07x}", addr)
+    }
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_mermaid(text: &str) -> String {
+    text.replace('"', "#quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::assemble;
+
+    const LOOP: &str = "LDC 3\nSTV counter\nloop:\nLDV counter\nADD minus\nSTV counter\nJMN end\nJMP loop\nend:\nHALT\n";
+
+    fn render(source: &str, profile: Option<&Profile>, format: GraphFormat) -> String {
+        let isa = InstructionSet::default();
+        let program = assemble(source.to_owned(), false, &isa).unwrap();
+        let values: Vec<MimaValue> = program.instructions.iter().map(|i| isa.encode(i).unwrap()).collect();
+        render_cfg(&values, &isa, &program.symbols, profile, format)
+    }
+
+    #[test]
+    fn splits_blocks_at_labels_and_after_jumps() {
+        let dot = render(LOOP, None, GraphFormat::Dot);
+        let nodes: Vec<&str> = dot.lines()
+            .map(|line| line.trim())
+            .filter(|line| line.starts_with("block_") && !line.contains("->"))
+            .map(|line| line.split(' ').next().unwrap())
+            .collect();
+        assert_eq!(nodes, vec!["block_0", "block_2", "block_6", "block_7"]);
+        assert!(dot.contains("block_2 [label=\"loop:\\l0x00002  LDV  counter\\l0x00003  ADD  minus\\l"));
+        assert!(dot.contains("block_7 [label=\"end:\\l0x00007  HALT\\l\"];"));
+    }
+
+    #[test]
+    fn connects_blocks() {
+        let edges: Vec<String> = render(LOOP, None, GraphFormat::Dot).lines()
+            .filter(|line| line.contains("->"))
+            .map(|line| line.trim().to_owned())
+            .collect();
+        assert_eq!(edges, vec![
+            "block_0 -> block_2;",
+            "block_2 -> block_7 [label=\"negative\"];",
+            "block_2 -> block_6;",
+            "block_6 -> block_2;"
+        ]);
+        let mermaid = render("LDC 1\n", None, GraphFormat::Mermaid);
+        assert!(mermaid.contains("block_1([\"end of program\"])"));
+        assert!(mermaid.contains("block_0 --> block_1"));
+    }
+
+    #[test]
+    fn marks_blocks_that_never_ran() {
+        let source = "LDC 3\nJMP loop\nLDC 1\nloop:\nHALT\n";
+        let profile = Profile::from([(0, 1), (1, 1), (3, 1)]);
+        let dot = render(source, Some(&profile), GraphFormat::Dot);
+        assert!(dot.contains("block_0 [label=\"executed 1x\\l0x00000  LDC  0x3\\l0x00001  JMP  loop\\l\"];"));
+        assert!(dot.contains("block_2 [label=\"executed 0x\\l0x00002  LDC  0x1\\l\", style=dashed];"));
+        assert!(dot.contains("block_3 [label=\"executed 1x\\lloop:\\l0x00003  HALT\\l\"];"));
+        let mermaid = render(source, Some(&profile), GraphFormat::Mermaid);
+        assert!(mermaid.ends_with("    class block_2 unexecuted\n"));
+        assert!(!render(source, None, GraphFormat::Mermaid).contains("unexecuted"));
+    }
+}
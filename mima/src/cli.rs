@@ -1,6 +1,12 @@
 use std::path::PathBuf;
-use mima_common::types::MimaAddress;
+use mima_common::types::{MimaAddress, parse_mima_number};
+use mima_common::instructions::Isa;
+use strum::VariantNames;
+use crate::memdump::{DumpFormat, AddressRange};
+use crate::testing::ReportFormat;
+use crate::cfg::GraphFormat;
 use clap::Clap;
+use strum_macros::{EnumString, EnumVariantNames};
 
 
 /// mimavm is an emulator of the "minimal machine" (mima) used in various
@@ -17,20 +23,41 @@ pub struct MainOpts {
 }
 
 impl MainOpts {
-    pub fn file(&self) -> &PathBuf {
+    // the input file of the subcommand, if it has one
+    pub fn file(&self) -> Option<&PathBuf> {
         match &self.cmd {
-            SubCommand::Asm(opts) => &opts.file,
-            SubCommand::Run(opts) => &opts.file
+            SubCommand::Asm(opts) => Some(&opts.file),
+            SubCommand::Run(opts) => opts.resume.as_ref().or(opts.file.as_ref()),
+            SubCommand::Test(opts) => Some(&opts.file),
+            SubCommand::Lint(opts) => Some(&opts.file),
+            SubCommand::Cfg(opts) => Some(&opts.file),
+            SubCommand::Repl(_) | SubCommand::Dap | SubCommand::Lsp(_) | SubCommand::Fmt(_) => None
         }
     }
 }
 
+// parsed once at startup, so the size of the variants does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Clap)]
 pub enum SubCommand {
     /// Assemble/Disassemble mima instructions
     Asm(AsmOpts),
     /// Run/Debug mima instructions
-    Run(RunOpts)
+    Run(RunOpts),
+    /// Run a program against the test cases of a test specification
+    Test(TestOpts),
+    /// Interactively assemble and execute instructions one at a time
+    Repl(ReplOpts),
+    /// Serve the Debug Adapter Protocol on stdin and stdout
+    Dap,
+    /// Serve the Language Server Protocol for assembly sources on stdin and stdout
+    Lsp(LspOpts),
+    /// Format assembly sources
+    Fmt(FmtOpts),
+    /// Check an assembly source for common mistakes
+    Lint(LintOpts),
+    /// Export the control flow graph of a program as Graphviz DOT or Mermaid
+    Cfg(CfgOpts)
 }
 
 #[derive(Clap)]
@@ -41,11 +68,21 @@ pub struct AsmOpts {
     /// Do not relativize addresses used in assembly
     #[clap(short, long)]
     pub absolute: bool,
+    /// The instruction set to use
+    #[clap(long, value_name = "ISA", default_value = "standard", possible_values = Isa::VARIANTS)]
+    pub isa: Isa,
+    /// File describing a custom instruction set (TOML or JSON), overrides --isa
+    #[clap(long, value_name = "FILE")]
+    pub isa_file: Option<PathBuf>,
 
     /// File to output the result of the operation to.
     #[clap(short, long, value_name = "FILE", required_unless_present = "disassemble")]
     pub output: Option<PathBuf>,
 
+    /// File to write the addresses of all labels and template variables to.
+    #[clap(short, long, value_name = "FILE", conflicts_with = "disassemble")]
+    pub symbols: Option<PathBuf>,
+
     /// The file to assemble/disassemble
     file: PathBuf
 }
@@ -55,6 +92,15 @@ pub struct RunOpts {
     /// Enables debug mode
     #[clap(short, long)]
     pub debug: bool,
+    /// Shows the debugger as a full screen terminal interface
+    #[clap(long, requires = "debug")]
+    pub tui: bool,
+    /// The instruction set to use
+    #[clap(long, value_name = "ISA", default_value = "standard", possible_values = Isa::VARIANTS)]
+    pub isa: Isa,
+    /// File describing a custom instruction set (TOML or JSON), overrides --isa
+    #[clap(long, value_name = "FILE")]
+    pub isa_file: Option<PathBuf>,
     /// Outputs the values at the given addresses in decimal format
     /// to the console upon termination
     #[clap(short, long = "--print-absolute-addresses", value_name = "ADDR")]
@@ -64,11 +110,184 @@ pub struct RunOpts {
     /// These addresses will be printed after the absolute ones, if any
     #[clap(short, long = "--print-relative-addresses", value_name = "ADDR")]
     pub rel_output: Option<Vec<MimaAddress>>,
+    /// Comma separated labels or template variables whose values are printed
+    /// with their names upon termination. Requires the symbol file (-s)
+    #[clap(long, value_name = "NAMES", require_delimiter = true)]
+    pub print: Vec<String>,
+
+    /// Format of the results printed upon termination. json also reports errors,
+    /// the number of executed instructions and the final registers
+    #[clap(long, value_name = "FORMAT", default_value = "text", possible_values = OutputFormat::VARIANTS,
+           conflicts_with = "debug")]
+    pub output_format: OutputFormat,
+
+    /// Records carry and overflow of arithmetic operations
+    /// and shows them in the debugger and in traces
+    #[clap(short, long)]
+    pub flags: bool,
+    /// Prints every executed instruction and the resulting accumulator to stderr
+    #[clap(short, long, conflicts_with = "debug")]
+    pub trace: bool,
+    /// Writes how often each instruction was executed to FILE (CSV), to be shown by `mima cfg`
+    #[clap(long, value_name = "FILE", conflicts_with_all = &["debug", "trace", "gdb-port", "gdb-stdio"])]
+    pub profile: Option<PathBuf>,
+    /// Fails the profiled run if the program does not halt within N instructions (default 100000)
+    #[clap(long, value_name = "N", requires = "profile")]
+    pub max_steps: Option<u64>,
+
+    /// Enables interrupts: execution continues at this address when an interrupt is serviced.
+    /// Requires the extended instruction set
+    #[clap(long, value_name = "ADDR", parse(try_from_str = parse_mima_number))]
+    pub interrupt_vector: Option<MimaAddress>,
+    /// Address IAR, accumulator and interrupt cause are saved to on interrupt entry
+    /// (defaults to the last three addresses of memory)
+    #[clap(long, value_name = "ADDR", requires = "interrupt-vector", parse(try_from_str = parse_mima_number))]
+    pub interrupt_save: Option<MimaAddress>,
+    /// Raises a timer interrupt every N executed instructions
+    #[clap(long, value_name = "N", requires = "interrupt-vector")]
+    pub timer: Option<u64>,
+    /// Maps a console device to the given address. Values written to it are printed as characters,
+    /// the following address holds the next character of input
+    #[clap(long, value_name = "ADDR", parse(try_from_str = parse_mima_number))]
+    pub console: Option<MimaAddress>,
+
+    /// Format of the binary to run. Memory dumps in any format can be run
+    #[clap(long, value_name = "FORMAT", default_value = "raw", possible_values = DumpFormat::VARIANTS)]
+    pub input_format: DumpFormat,
+
+    /// Writes a value to memory before execution. The address can be a label
+    /// or template variable from the symbol file given with -s
+    #[clap(long, value_name = "ADDR=VALUE", number_of_values = 1)]
+    pub set: Vec<String>,
+    /// Loads the values of a binary file into memory starting at ADDR before execution
+    #[clap(long, value_name = "FILE@ADDR", number_of_values = 1)]
+    pub load: Vec<String>,
 
     /// Dumps the VM's memory to the specified file upon termination
     #[clap(short, long, value_name = "FILE")]
     pub memdump: Option<PathBuf>,
+    /// Format of the memory dump
+    #[clap(long, value_name = "FORMAT", default_value = "raw", possible_values = DumpFormat::VARIANTS)]
+    pub memdump_format: DumpFormat,
+    /// Only dumps the addresses from START up to (excluding) END
+    #[clap(long, value_name = "START..END", requires = "memdump")]
+    pub memdump_range: Option<AddressRange>,
+
+    /// Symbol file created by the assembler, used to resolve labels in the debugger and in --set
+    #[clap(short, long, value_name = "FILE")]
+    pub symbols: Option<PathBuf>,
+    /// File to load debugger display expressions from and save them to
+    #[clap(long, value_name = "FILE", requires = "debug")]
+    pub displays: Option<PathBuf>,
+
+    /// Waits for GDB to connect on this port and lets it control execution
+    #[clap(long, value_name = "PORT", conflicts_with_all = &["debug", "trace", "gdb-stdio"])]
+    pub gdb_port: Option<u16>,
+    /// Lets GDB control execution through stdin and stdout (`target remote | mima run --gdb-stdio FILE`)
+    #[clap(long, conflicts_with_all = &["debug", "trace"])]
+    pub gdb_stdio: bool,
+
+    /// Continues execution from a snapshot saved in the debugger instead of running a binary.
+    /// Instruction set, devices and interrupt settings are taken from the snapshot
+    #[clap(long, value_name = "SNAPSHOT",
+           conflicts_with_all = &["file", "isa-file", "input-format", "flags", "interrupt-vector", "console"])]
+    pub resume: Option<PathBuf>,
 
     /// The binary to run
+    #[clap(required_unless_present = "resume")]
+    file: Option<PathBuf>
+}
+#[derive(Clap)]
+pub struct ReplOpts {
+    /// The instruction set to use
+    #[clap(long, value_name = "ISA", default_value = "standard", possible_values = Isa::VARIANTS)]
+    pub isa: Isa,
+    /// File describing a custom instruction set (TOML or JSON), overrides --isa
+    #[clap(long, value_name = "FILE")]
+    pub isa_file: Option<PathBuf>,
+    /// Records carry and overflow of arithmetic operations
+    #[clap(short, long)]
+    pub flags: bool
+}
+
+#[derive(Clap)]
+pub struct LspOpts {
+    /// The instruction set to use, unless the client sets one in its initialization options
+    #[clap(long, value_name = "ISA", default_value = "standard", possible_values = Isa::VARIANTS)]
+    pub isa: Isa,
+    /// File describing a custom instruction set (TOML or JSON), overrides --isa
+    #[clap(long, value_name = "FILE")]
+    pub isa_file: Option<PathBuf>
+}
+
+#[derive(Clap)]
+pub struct FmtOpts {
+    /// Only reports files that are not formatted and fails if there are any, instead of formatting them
+    #[clap(long)]
+    pub check: bool,
+
+    /// The assembly sources to format in place
+    #[clap(required = true)]
+    pub files: Vec<PathBuf>
+}
+
+#[derive(Clap)]
+pub struct LintOpts {
+    /// The instruction set to use
+    #[clap(long, value_name = "ISA", default_value = "standard", possible_values = Isa::VARIANTS)]
+    pub isa: Isa,
+    /// File describing a custom instruction set (TOML or JSON), overrides --isa
+    #[clap(long, value_name = "FILE")]
+    pub isa_file: Option<PathBuf>,
+    /// Do not relativize addresses used in assembly
+    #[clap(short, long)]
+    pub absolute: bool,
+
+    /// The assembly source to check
+    file: PathBuf
+}
+
+#[derive(Clap)]
+pub struct CfgOpts {
+    /// Format of the graph
+    #[clap(long, value_name = "FORMAT", default_value = "dot", possible_values = GraphFormat::VARIANTS)]
+    pub format: GraphFormat,
+    /// The instruction set to use
+    #[clap(long, value_name = "ISA", default_value = "standard", possible_values = Isa::VARIANTS)]
+    pub isa: Isa,
+    /// File describing a custom instruction set (TOML or JSON), overrides --isa
+    #[clap(long, value_name = "FILE")]
+    pub isa_file: Option<PathBuf>,
+    /// Do not relativize addresses used in assembly
+    #[clap(short, long)]
+    pub absolute: bool,
+    /// Symbol file created by the assembler, used to name blocks of a binary
+    #[clap(short, long, value_name = "FILE")]
+    pub symbols: Option<PathBuf>,
+    /// Profile written by `mima run --profile`. Shows how often each block was executed
+    #[clap(long, value_name = "FILE")]
+    pub profile: Option<PathBuf>,
+    /// Format of a binary. Memory dumps in any format can be read
+    #[clap(long, value_name = "FORMAT", default_value = "raw", possible_values = DumpFormat::VARIANTS)]
+    pub input_format: DumpFormat,
+
+    /// The binary or assembly source (.asm) to build the graph of
     file: PathBuf
-}
\ No newline at end of file
+}
+
+#[derive(Clap)]
+pub struct TestOpts {
+    /// Format of the test report
+    #[clap(long, value_name = "FORMAT", default_value = "text", possible_values = ReportFormat::VARIANTS)]
+    pub format: ReportFormat,
+
+    /// The test specification (TOML)
+    file: PathBuf
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, EnumVariantNames)]
+#[strum(serialize_all = "lowercase")]
+pub enum OutputFormat {
+    Text,
+    Json
+}
@@ -0,0 +1,95 @@
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+use crate::debugger::COMMANDS;
+
+// commands whose arguments are addresses
+const ADDRESS_COMMANDS: &[&str] = &[
+    "break", "b", "read", "disas", "mem", "write", "display", "jump"
+];
+
+// completes debugger commands, labels from the symbol table and file names
+#[derive(Default)]
+pub struct DebuggerHelper {
+    pub labels: Vec<String>,
+    filenames: FilenameCompleter
+}
+
+impl Completer for DebuggerHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>)
+        -> rustyline::Result<(usize, Vec<Pair>)> {
+        match self.complete_words(&line[..pos]) {
+            Some(completions) => Ok(completions),
+            None => self.filenames.complete(line, pos, ctx)
+        }
+    }
+}
+
+impl DebuggerHelper {
+    // the start of the word before the cursor and its completions, None if it is a file name
+    fn complete_words(&self, before: &str) -> Option<(usize, Vec<Pair>)> {
+        let start = before.rfind(' ').map_or(0, |i| i + 1);
+        let word = &before[start..];
+        let previous: Vec<&str> = before[..start].split_whitespace().collect();
+
+        let candidates: Vec<&str> = match previous.as_slice() {
+            [] => COMMANDS.iter().map(|c| c.name).collect(),
+            ["help"] | ["?"] => COMMANDS.iter().map(|c| c.name).collect(),
+            ["set"] => vec!["accu", "iar"],
+            ["set", "iar"] => self.labels(),
+            ["interrupts"] => vec!["raise"],
+            ["interrupts", "raise"] => vec!["timer"],
+            ["dump"] | ["save"] | ["load"] => return None,
+            [command] if ADDRESS_COMMANDS.contains(command) || command.starts_with("x/") =>
+                self.labels(),
+            _ => Vec::new()
+        };
+        let mut matches: Vec<Pair> = candidates.into_iter()
+            .filter(|c| c.starts_with(word))
+            .map(|c| Pair { display: c.to_owned(), replacement: c.to_owned() })
+            .collect();
+        matches.sort_by(|a, b| a.display.cmp(&b.display));
+        Some((start, matches))
+    }
+
+    fn labels(&self) -> Vec<&str> {
+        self.labels.iter().map(|s| s.as_str()).collect()
+    }
+}
+
+impl Hinter for DebuggerHelper {
+    type Hint = String;
+}
+
+impl Highlighter for DebuggerHelper {}
+
+impl Validator for DebuggerHelper {}
+
+impl Helper for DebuggerHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complete(before: &str) -> Option<(usize, Vec<String>)> {
+        let helper = DebuggerHelper { labels: vec!["start".to_owned(), "sum".to_owned()], ..Default::default() };
+        helper.complete_words(before)
+            .map(|(start, pairs)| (start, pairs.into_iter().map(|pair| pair.replacement).collect()))
+    }
+
+    #[test]
+    fn completes_commands_and_their_arguments() {
+        assert_eq!(complete("st"), Some((0, vec!["state".to_owned(), "step".to_owned()])));
+        assert_eq!(complete("help di"), Some((5, vec!["disas".to_owned(), "display".to_owned()])));
+        assert_eq!(complete("set "), Some((4, vec!["accu".to_owned(), "iar".to_owned()])));
+        assert_eq!(complete("set iar s"), Some((8, vec!["start".to_owned(), "sum".to_owned()])));
+        assert_eq!(complete("x/4d su"), Some((5, vec!["sum".to_owned()])));
+        assert_eq!(complete("b st"), Some((2, vec!["start".to_owned()])));
+        assert_eq!(complete("write 1 s"), Some((8, Vec::new())));
+        assert_eq!(complete("save sn"), None);
+    }
+}
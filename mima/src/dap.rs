@@ -0,0 +1,604 @@
+use crate::assembly::assemble;
+use crate::messages::{read_message, write_message};
+use crate::symbols::SymbolTable;
+use mima_common::instructions::{Isa, Opcode};
+use mima_common::isa::InstructionSet;
+use mima_common::runtime::Runtime;
+use mima_common::types::{MimaAddress, MimaValue, ReadMimaExt, parse_mima_number};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+// instructions executed between two checks for new requests while the program is running
+const STEPS_PER_CHECK: u32 = 10_000;
+const THREAD_ID: u64 = 1;
+const REGISTERS_REFERENCE: u64 = 1;
+const LABELS_REFERENCE: u64 = 2;
+
+// serves the Debug Adapter Protocol on stdin and stdout until the client disconnects
+pub fn serve() -> Result<(), String> {
+    let (sender, receiver) = mpsc::channel();
+    // requests are read on a separate thread so that a running program can be paused
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    DapServer::new(io::stdout()).serve(receiver)
+}
+
+// a launched program and the debugging state around it
+struct Session {
+    runtime: Runtime,
+    symbols: SymbolTable,
+    // source file and the source line of every instruction, if the program was assembled from source
+    source: Option<PathBuf>,
+    lines: Vec<usize>,
+    line_breakpoints: HashSet<MimaAddress>,
+    instruction_breakpoints: HashSet<MimaAddress>,
+    stop_on_entry: bool
+}
+
+struct DapServer<W: Write> {
+    output: W,
+    seq: u64,
+    session: Option<Session>,
+    // events that are sent after the response to the current request
+    events: Vec<(&'static str, Value)>,
+    running: bool,
+    // set when continuing, so that the breakpoint execution currently stands at is not hit again
+    leaving_breakpoint: bool,
+    done: bool
+}
+
+impl<W: Write> DapServer<W> {
+    fn new(output: W) -> Self {
+        DapServer {
+            output,
+            seq: 1,
+            session: None,
+            events: Vec::new(),
+            running: false,
+            leaving_breakpoint: false,
+            done: false
+        }
+    }
+
+    fn serve(mut self, requests: Receiver<Result<Value, String>>) -> Result<(), String> {
+        while !self.done {
+            let request = if self.running {
+                match requests.try_recv() {
+                    Ok(request) => request,
+                    Err(TryRecvError::Empty) => {
+                        self.run_slice();
+                        self.flush_events()?;
+                        continue;
+                    }
+                    Err(TryRecvError::Disconnected) => break
+                }
+            } else {
+                match requests.recv() {
+                    Ok(request) => request,
+                    Err(_) => break
+                }
+            };
+            match request {
+                Ok(request) => self.handle(request)?,
+                // the request can not be told apart, so the error answers no particular one
+                Err(message) => self.send(json!({
+                    "type": "response",
+                    "request_seq": 0,
+                    "command": "",
+                    "success": false,
+                    "message": message
+                }))?
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, request: Value) -> Result<(), String> {
+        if request["type"] != "request" {
+            return Ok(());
+        }
+        let command = request["command"].as_str().unwrap_or_default().to_owned();
+        let args = &request["arguments"];
+        let result = match command.as_str() {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsDisassembleRequest": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsEvaluateForHovers": true
+            })),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "configurationDone" => self.configuration_done(),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "mima" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false },
+                { "name": "Labels", "variablesReference": LABELS_REFERENCE, "expensive": false }
+            ]})),
+            "variables" => self.variables(args),
+            "evaluate" => self.evaluate(args),
+            "continue" => self.continue_run(),
+            "next" | "stepIn" | "stepOut" => self.step(),
+            "pause" => self.pause(),
+            "disassemble" => self.disassemble(args),
+            "disconnect" | "terminate" => {
+                self.done = true;
+                Ok(Value::Null)
+            }
+            _ => Err(format!("Unsupported request '{}'", command))
+        };
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": result.is_ok()
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message)
+        }
+        self.send(response)?;
+        self.flush_events()
+    }
+
+    fn send(&mut self, mut message: Value) -> Result<(), String> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        write_message(&mut self.output, &message)
+    }
+
+    fn event(&mut self, event: &'static str, body: Value) {
+        self.events.push((event, body));
+    }
+
+    fn flush_events(&mut self) -> Result<(), String> {
+        for (event, body) in std::mem::take(&mut self.events) {
+            let mut message = json!({ "type": "event", "event": event });
+            if !body.is_null() {
+                message["body"] = body;
+            }
+            self.send(message)?;
+        }
+        Ok(())
+    }
+
+    fn session(&self) -> Result<&Session, String> {
+        self.session.as_ref().ok_or_else(|| "No program has been launched".to_owned())
+    }
+
+    fn session_mut(&mut self) -> Result<&mut Session, String> {
+        self.session.as_mut().ok_or_else(|| "No program has been launched".to_owned())
+    }
+
+    // launch arguments: program (source or binary), symbols, isa, isaFile, absolute, flags, stopOnEntry
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program = args["program"].as_str()
+            .map(PathBuf::from)
+            .ok_or("Launch configuration requires 'program'")?;
+        let isa = match args["isaFile"].as_str() {
+            Some(path) => InstructionSet::read_from(Path::new(path))?,
+            None => InstructionSet::from(Isa::from_str(args["isa"].as_str().unwrap_or("standard"))
+                .map_err(|_e| "Unknown instruction set".to_owned())?)
+        };
+
+        let (values, symbols, source, lines) = if program.extension().is_some_and(|ext| ext == "asm") {
+            let content = fs::read_to_string(&program)
+                .map_err(|e| format!("Could not read program {}: {}", program.display(), e))?;
+            let assembled = assemble(content, args["absolute"].as_bool().unwrap_or(false), &isa)?;
+            let values = assembled.instructions.iter()
+                .map(|instr| isa.encode(instr))
+                .collect::<Result<Vec<MimaValue>, String>>()?;
+            let source = program.canonicalize().unwrap_or(program);
+            (values, assembled.symbols, Some(source), assembled.lines)
+        } else {
+            let mut file = File::open(&program)
+                .map_err(|e| format!("Could not open program {}: {}", program.display(), e))?;
+            let values = file.read_all_mima_vals_sized(isa.value_bytes())
+                .map_err(|e| format!("Failed to parse mima file: {}", e))?;
+            let symbols = match args["symbols"].as_str() {
+                Some(path) => SymbolTable::read_from(Path::new(path), &isa)?,
+                None => SymbolTable::default()
+            };
+            (values, symbols, None, Vec::new())
+        };
+
+        let runtime = Runtime::with_instructions(&values)
+            .with_isa(isa)
+            .with_status_flags(args["flags"].as_bool().unwrap_or(false))
+            .with_assertions(symbols.assertions().to_vec());
+        self.session = Some(Session {
+            runtime,
+            symbols,
+            source,
+            lines,
+            line_breakpoints: HashSet::new(),
+            instruction_breakpoints: HashSet::new(),
+            stop_on_entry: args["stopOnEntry"].as_bool().unwrap_or(false)
+        });
+        self.event("initialized", Value::Null);
+        Ok(Value::Null)
+    }
+
+    // source breakpoints are placed at the first instruction at or after the requested line
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let session = self.session_mut()?;
+        let path = args["source"]["path"].as_str().map(PathBuf::from);
+        let matches_source = match (&path, &session.source) {
+            (Some(path), Some(source)) => path.canonicalize().is_ok_and(|path| &path == source),
+            _ => false
+        };
+        session.line_breakpoints.clear();
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let breakpoints: Vec<Value> = requested.iter().map(|breakpoint| {
+            let line = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+            let found = session.lines.iter().enumerate()
+                .filter(|(_, instr_line)| matches_source && **instr_line >= line)
+                .min_by_key(|(_, instr_line)| **instr_line);
+            match found {
+                Some((addr, instr_line)) => {
+                    session.line_breakpoints.insert(addr as MimaAddress);
+                    json!({ "verified": true, "line": instr_line, "instructionReference": format!("{:#x}", addr) })
+                }
+                None => json!({ "verified": false, "line": line,
+                                "message": "No instruction for this line. Line information requires an assembly source" })
+            }
+        }).collect();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let session = self.session_mut()?;
+        session.instruction_breakpoints.clear();
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let breakpoints: Vec<Value> = requested.iter().map(|breakpoint| {
+            let reference = breakpoint["instructionReference"].as_str().unwrap_or_default();
+            match parse_mima_number(reference) {
+                Ok(addr) => {
+                    let addr = session.runtime.isa().coerce_address(
+                        (addr as i64 + breakpoint["offset"].as_i64().unwrap_or(0)) as MimaAddress);
+                    session.instruction_breakpoints.insert(addr);
+                    json!({ "verified": true, "instructionReference": format!("{:#x}", addr) })
+                }
+                Err(_) => json!({ "verified": false, "message": format!("Invalid address '{}'", reference) })
+            }
+        }).collect();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn configuration_done(&mut self) -> Result<Value, String> {
+        if self.session()?.stop_on_entry {
+            self.stopped("entry", None);
+        } else {
+            self.running = true;
+        }
+        Ok(Value::Null)
+    }
+
+    fn stack_trace(&self) -> Result<Value, String> {
+        let session = self.session()?;
+        let iar = session.runtime.read_iar();
+        // the frame is named after the closest label before the current instruction
+        let name = session.symbols.sorted().into_iter()
+            .rev()
+            .find(|(_, addr)| *addr <= iar)
+            .map_or_else(|| format!("{:#07x}", iar), |(name, addr)| if addr == iar {
+                name.to_owned()
+            } else {
+                format!("{} + {:#x}", name, iar - addr)
+            });
+        let mut frame = json!({
+            "id": 1,
+            "name": name,
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": format!("{:#x}", iar)
+        });
+        if let (Some(source), Some(line)) = (&session.source, session.lines.get(iar as usize)) {
+            frame["source"] = source_json(source);
+            frame["line"] = json!(line);
+            frame["column"] = json!(1);
+        }
+        Ok(json!({ "stackFrames": [frame], "totalFrames": 1 }))
+    }
+
+    fn variables(&self, args: &Value) -> Result<Value, String> {
+        let session = self.session()?;
+        let runtime = &session.runtime;
+        let isa = runtime.isa();
+        let variables: Vec<Value> = match args["variablesReference"].as_u64() {
+            Some(REGISTERS_REFERENCE) => {
+                let mut registers = vec![
+                    variable("Accu", format_value(isa, runtime.read_accu())),
+                    variable("IAR", format!("{:#07x}", runtime.read_iar())),
+                    variable("IR", format!("{:#08x} {}", runtime.read_ir(), isa.decode(runtime.read_ir())
+                        .map_or("???".to_owned(), |i| isa.format(&i)))),
+                    variable("SAR", format!("{:#07x}", runtime.read_sar())),
+                    variable("SDR", format!("{:#08x}", runtime.read_sdr())),
+                    variable("X", format!("{:#08x}", runtime.read_x())),
+                    variable("Y", format!("{:#08x}", runtime.read_y())),
+                    variable("Z", format!("{:#08x}", runtime.read_z()))
+                ];
+                if isa.supports(Opcode::CALL) || isa.supports(Opcode::LDSP) {
+                    registers.push(variable("SP", format!("{:#07x}", runtime.read_sp())));
+                }
+                if let Some(flags) = runtime.read_flags() {
+                    registers.push(variable("Flags", flags.to_string()));
+                }
+                registers.push(variable("Steps", runtime.read_steps().to_string()));
+                registers
+            }
+            Some(LABELS_REFERENCE) => session.symbols.sorted().into_iter()
+                .map(|(name, addr)| {
+                    let mut var = variable(name, format_value(isa, runtime.read_mem(addr)));
+                    var["memoryReference"] = json!(format!("{:#x}", addr));
+                    var["evaluateName"] = json!(name);
+                    var
+                })
+                .collect(),
+            _ => Vec::new()
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    // expressions are `accu`, `iar`, labels or addresses
+    fn evaluate(&self, args: &Value) -> Result<Value, String> {
+        let session = self.session()?;
+        let runtime = &session.runtime;
+        let expression = args["expression"].as_str().unwrap_or_default().trim();
+        let result = match expression.to_lowercase().as_str() {
+            "accu" => format_value(runtime.isa(), runtime.read_accu()),
+            "iar" => format!("{:#07x}", runtime.read_iar()),
+            _ => session.symbols.parse_addr(expression, runtime.isa())
+                .map(|addr| format_value(runtime.isa(), runtime.read_mem(addr)))
+                .map_err(|_e| format!("Unknown label or address '{}'", expression))?
+        };
+        Ok(json!({ "result": result, "variablesReference": 0 }))
+    }
+
+    fn continue_run(&mut self) -> Result<Value, String> {
+        if self.session()?.runtime.halt {
+            return Err("The program has halted".to_owned());
+        }
+        self.running = true;
+        self.leaving_breakpoint = true;
+        Ok(json!({ "allThreadsContinued": true }))
+    }
+
+    fn step(&mut self) -> Result<Value, String> {
+        if self.session()?.runtime.halt {
+            return Err("The program has halted".to_owned());
+        }
+        if self.execute_instruction() {
+            self.stopped("step", None);
+        }
+        Ok(Value::Null)
+    }
+
+    fn pause(&mut self) -> Result<Value, String> {
+        self.session()?;
+        if self.running {
+            self.running = false;
+            self.stopped("pause", None);
+        }
+        Ok(Value::Null)
+    }
+
+    fn disassemble(&self, args: &Value) -> Result<Value, String> {
+        let session = self.session()?;
+        let runtime = &session.runtime;
+        let isa = runtime.isa();
+        let reference = args["memoryReference"].as_str().unwrap_or_default();
+        let base = parse_mima_number(reference)
+            .map_err(|_e| format!("Invalid memory reference '{}'", reference))?;
+        // memory is addressed in words, so the byte offset is interpreted as a word offset
+        let start = base as i64 + args["offset"].as_i64().unwrap_or(0) + args["instructionOffset"].as_i64().unwrap_or(0);
+        let count = args["instructionCount"].as_i64().unwrap_or(0);
+        let instructions: Vec<Value> = (start..start + count).map(|addr| {
+            if addr < 0 || addr >= isa.address_space() as i64 {
+                let address = format!("{}{:#x}", if addr < 0 { "-" } else { "" }, addr.abs());
+                return json!({ "address": address, "instruction": "??", "presentationHint": "invalid" });
+            }
+            let addr = addr as MimaAddress;
+            let val = runtime.read_mem(addr);
+            let mut instruction = json!({
+                "address": format!("{:#x}", addr),
+                "instructionBytes": format!("{:0digits$x}", val, digits = isa.value_bytes() as usize * 2),
+                "instruction": isa.decode(val).map_or("???".to_owned(), |i| isa.format(&i))
+            });
+            if let Some((name, _)) = session.symbols.sorted().into_iter().find(|(_, a)| *a == addr) {
+                instruction["symbol"] = json!(name);
+            }
+            if let (Some(source), Some(line)) = (&session.source, session.lines.get(addr as usize)) {
+                instruction["location"] = source_json(source);
+                instruction["line"] = json!(line);
+            }
+            instruction
+        }).collect();
+        Ok(json!({ "instructions": instructions }))
+    }
+
+    // executes instructions until a breakpoint is reached, the program halts or the slice is over
+    fn run_slice(&mut self) {
+        for _ in 0..STEPS_PER_CHECK {
+            let session = match &self.session {
+                Some(session) => session,
+                None => return
+            };
+            let iar = session.runtime.read_iar();
+            if !self.leaving_breakpoint
+                && (session.line_breakpoints.contains(&iar) || session.instruction_breakpoints.contains(&iar)) {
+                self.running = false;
+                self.stopped("breakpoint", None);
+                return;
+            }
+            self.leaving_breakpoint = false;
+            if !self.execute_instruction() {
+                return;
+            }
+            if !self.running {
+                return;
+            }
+        }
+    }
+
+    // executes one instruction, returns whether the program can continue. Failed assertions stop execution
+    fn execute_instruction(&mut self) -> bool {
+        let session = match &mut self.session {
+            Some(session) => session,
+            None => return false
+        };
+        let failures = session.runtime.assertion_failures().len();
+        let result = session.runtime.step();
+        let new_failures = session.runtime.assertion_failures()[failures..].to_vec();
+        let halted = session.runtime.halt;
+        let failed = !session.runtime.assertion_failures().is_empty();
+        for failure in &new_failures {
+            self.event("output", json!({ "category": "stderr", "output": format!("{}\n", failure) }));
+        }
+        if let Err(error) = result {
+            self.event("output", json!({ "category": "stderr", "output": format!("{}\n", error) }));
+            self.terminate(1);
+            return false;
+        }
+        if halted {
+            self.terminate(if failed { 1 } else { 0 });
+            return false;
+        }
+        if let Some(failure) = new_failures.first() {
+            self.running = false;
+            self.stopped("exception", Some(failure.clone()));
+            return false;
+        }
+        true
+    }
+
+    fn stopped(&mut self, reason: &'static str, text: Option<String>) {
+        let mut body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+        if let Some(text) = text {
+            body["text"] = json!(text);
+        }
+        self.event("stopped", body);
+    }
+
+    fn terminate(&mut self, exit_code: i32) {
+        self.running = false;
+        self.event("exited", json!({ "exitCode": exit_code }));
+        self.event("terminated", Value::Null);
+    }
+}
+
+fn variable(name: &str, value: String) -> Value {
+    json!({ "name": name, "value": value, "variablesReference": 0 })
+}
+
+fn format_value(isa: &InstructionSet, val: MimaValue) -> String {
+    format!("{} ({:#08x})", isa.to_signed(val), val)
+}
+
+fn source_json(path: &Path) -> Value {
+    json!({
+        "name": path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned()),
+        "path": path
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str = "LDC 1\nADD 0x10\nADD 0x10\nHALT\n";
+
+    fn request(server: &mut DapServer<Vec<u8>>, command: &str, arguments: Value) {
+        server.handle(json!({ "type": "request", "seq": server.seq, "command": command, "arguments": arguments }))
+            .unwrap();
+    }
+
+    // what a client would see while the program runs
+    fn run(server: &mut DapServer<Vec<u8>>) {
+        while server.running {
+            server.run_slice();
+            server.flush_events().unwrap();
+        }
+    }
+
+    // the messages written since the last call, responses as `response <command>`, events as `event <event>`
+    fn received(server: &mut DapServer<Vec<u8>>) -> Vec<(String, Value)> {
+        let output = std::mem::take(&mut server.output);
+        let mut input = output.as_slice();
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut input).unwrap() {
+            let message = message.unwrap();
+            let kind = match message["type"].as_str() {
+                Some("response") => format!("response {}", message["command"].as_str().unwrap()),
+                _ => format!("event {}", message["event"].as_str().unwrap())
+            };
+            messages.push((kind, message));
+        }
+        messages
+    }
+
+    fn kinds(messages: &[(String, Value)]) -> Vec<&str> {
+        messages.iter().map(|(kind, _)| kind.as_str()).collect()
+    }
+
+    #[test]
+    fn stops_at_breakpoints_until_the_program_halts() {
+        let path = std::env::temp_dir().join(format!("mima-dap-{}.asm", std::process::id()));
+        fs::write(&path, PROGRAM).unwrap();
+        let mut server = DapServer::new(Vec::new());
+
+        request(&mut server, "initialize", json!({}));
+        request(&mut server, "launch", json!({ "program": path, "absolute": true }));
+        let messages = received(&mut server);
+        assert_eq!(kinds(&messages), ["response initialize", "response launch", "event initialized"]);
+        assert_eq!(messages[0].1["body"]["supportsInstructionBreakpoints"], true);
+
+        request(&mut server, "setBreakpoints", json!({ "source": { "path": path }, "breakpoints": [{ "line": 3 }] }));
+        request(&mut server, "configurationDone", json!({}));
+        run(&mut server);
+        let messages = received(&mut server);
+        assert_eq!(kinds(&messages), ["response setBreakpoints", "response configurationDone", "event stopped"]);
+        assert_eq!(messages[0].1["body"]["breakpoints"], json!([{ "verified": true, "line": 3, "instructionReference": "0x2" }]));
+        assert_eq!(messages[2].1["body"]["reason"], "breakpoint");
+        assert_eq!(server.session().unwrap().runtime.read_iar(), 2);
+
+        request(&mut server, "continue", json!({}));
+        run(&mut server);
+        let messages = received(&mut server);
+        assert_eq!(kinds(&messages), ["response continue", "event exited", "event terminated"]);
+        assert_eq!(messages[1].1["body"]["exitCode"], 0);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn answers_invalid_messages_with_an_error() {
+        let (sender, receiver) = mpsc::channel();
+        sender.send(Err("Invalid message: EOF while parsing a value".to_owned())).unwrap();
+        sender.send(Ok(json!({ "type": "request", "seq": 2, "command": "threads" }))).unwrap();
+        drop(sender);
+        let mut output = Vec::new();
+        DapServer::new(&mut output).serve(receiver).unwrap();
+
+        let mut input = output.as_slice();
+        let error = read_message(&mut input).unwrap().unwrap().unwrap();
+        assert_eq!((&error["success"], &error["message"]), (&json!(false), &json!("Invalid message: EOF while parsing a value")));
+        let threads = read_message(&mut input).unwrap().unwrap().unwrap();
+        assert_eq!((&threads["command"], &threads["success"]), (&json!("threads"), &json!(true)));
+    }
+}
@@ -1,150 +1,741 @@
-use mima_common::runtime::Runtime;
+use mima_common::runtime::{Runtime, Snapshot};
 use rustyline::Editor;
+use crate::completion::DebuggerHelper;
 use std::collections::HashSet;
-use mima_common::types::{MimaAddress, parse_mima_addr, parse_mima_value, ADDRESS_SPACE};
-use mima_common::instructions::Instruction;
+use mima_common::types::{MimaAddress, MimaValue};
+use mima_common::instructions::Opcode;
+use mima_common::interrupts::InterruptSource;
+use mima_common::isa::InstructionSet;
 use rustyline::config::Configurer;
 use std::path::PathBuf;
 use std::str::FromStr;
 use crate::{create_memdump};
-use std::convert::TryFrom;
-
-const HELP_MESSAGE: &'static str =
-    "<ENTER> - run the next instruction and immediately break again
-`state` - print the current state of the machine
-`continue` - continue execution until the next breakpoint
-`break <addr>` - toggle breakpoint at the specified address
-`read <addr>` - print value at the given address
-`write <addr> <val>` - write value to the given address
-`dump <file>` - dump the machine's memory to the specified file
-`halt` - stop execution
-`?` - display this help message";
+use crate::symbols::SymbolTable;
+use crate::memdump::{DumpFormat, AddressRange, hexdump_rows, as_char};
+use strum::VariantNames;
+use std::num::ParseIntError;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::fmt;
+use std::cell::RefCell;
+
+pub struct CommandInfo {
+    pub name: &'static str,
+    pub alias: Option<&'static str>,
+    pub usage: &'static str,
+    pub summary: &'static str,
+    pub details: &'static str
+}
+
+pub const COMMANDS: &[CommandInfo] = &[
+    CommandInfo {
+        name: "step", alias: Some("s"), usage: "step",
+        summary: "run the next instruction and immediately break again",
+        details: "Executes exactly one instruction. Entering an empty line does the same."
+    },
+    CommandInfo {
+        name: "ustep", alias: Some("u"), usage: "ustep",
+        summary: "run a single clock cycle of the current instruction",
+        details: "Executes one clock cycle of the textbook microprogram and prints its register transfers \
+                  and the internal registers. Fetching takes five cycles, decoding one, \
+                  and the execution phase depends on the instruction. \
+                  Memory accesses take three cycles. `step` finishes a partially executed instruction."
+    },
+    CommandInfo {
+        name: "state", alias: None, usage: "state",
+        summary: "print the current state of the machine",
+        details: "Prints the accumulator, the instruction address and instruction registers, \
+                  the instructions around the instruction address, all display expressions \
+                  and the internal registers SAR, SDR, X, Y and Z."
+    },
+    CommandInfo {
+        name: "continue", alias: Some("c"), usage: "continue",
+        summary: "continue execution until the next breakpoint",
+        details: "Runs the program until a breakpoint is hit or the machine halts."
+    },
+    CommandInfo {
+        name: "break", alias: Some("b"), usage: "break <addr>",
+        summary: "toggle breakpoint at the specified address",
+        details: "Sets a breakpoint at the given address or removes it if one is already set there. \
+                  Execution stops before the instruction at that address is run."
+    },
+    CommandInfo {
+        name: "read", alias: None, usage: "read <addr>",
+        summary: "print value at the given address",
+        details: "Prints the value at the given address as unsigned and signed decimal, hex and binary."
+    },
+    CommandInfo {
+        name: "x", alias: None, usage: "x/<n><fmt> <addr>",
+        summary: "examine n values starting at the given address",
+        details: "Prints n values (default 1) starting at the given address in the format fmt (default x).\n\
+                  fmt is one of x (hex), d (signed), u (unsigned), b (binary), c (char), i (instruction).\n\
+                  Example: `x/4i 0x10` disassembles four instructions at 0x10."
+    },
+    CommandInfo {
+        name: "disas", alias: None, usage: "disas <addr> [count]",
+        summary: "disassemble count instructions starting at the given address",
+        details: "Disassembles count (default 10) instructions starting at the given address. \
+                  The next instruction to be executed is marked with an arrow."
+    },
+    CommandInfo {
+        name: "mem", alias: None, usage: "mem <addr> [count]",
+        summary: "print a hexdump of count values starting at the given address",
+        details: "Prints count (default 32) values starting at the given address, eight per row, \
+                  followed by their lowest bytes as ascii characters."
+    },
+    CommandInfo {
+        name: "write", alias: None, usage: "write <addr> <val>",
+        summary: "write value to the given address",
+        details: "Writes the given value to memory. Values may be decimal, negative decimal or hex (0x prefix)."
+    },
+    CommandInfo {
+        name: "display", alias: None, usage: "display [<addr> [fmt]]",
+        summary: "print the value at the given address whenever execution stops",
+        details: "Adds a display expression that is printed every time execution stops, \
+                  using the optional format fmt (see `help x`). Without arguments, lists all display expressions."
+    },
+    CommandInfo {
+        name: "undisplay", alias: None, usage: "undisplay [n]",
+        summary: "remove display expression n, or all of them",
+        details: "Removes the display expression with the number shown by `display`, \
+                  or all display expressions if n is not given."
+    },
+    CommandInfo {
+        name: "set", alias: None, usage: "set accu <val> | set iar <addr>",
+        summary: "write a value to the accumulator or instruction address register",
+        details: "`set accu <val>` writes a value to the accumulator.\n\
+                  `set iar <addr>` sets the address of the next instruction without continuing."
+    },
+    CommandInfo {
+        name: "jump", alias: None, usage: "jump <addr>",
+        summary: "continue execution at the given address",
+        details: "Sets the instruction address register to the given address and continues execution."
+    },
+    CommandInfo {
+        name: "interrupts", alias: None, usage: "interrupts [raise <timer|device>]",
+        summary: "show the interrupt controller or request an interrupt",
+        details: "Without arguments, shows whether interrupts are enabled, the vector and save addresses, \
+                  the timer countdown and all pending interrupts.\n\
+                  `interrupts raise timer` and `interrupts raise <n>` request an interrupt from the timer \
+                  or from the n-th attached device. It is serviced once interrupts are enabled."
+    },
+    CommandInfo {
+        name: "restart", alias: None, usage: "restart",
+        summary: "reload the original program and start over",
+        details: "Resets registers and memory to the state the program was loaded with. \
+                  Breakpoints and display expressions are kept."
+    },
+    CommandInfo {
+        name: "dump", alias: None, usage: "dump <file> [format] [start..end]",
+        summary: "dump the machine's memory to the specified file",
+        details: "Writes the memory in use to the given file in the given format: raw (the binary format \
+                  of programs, the default), ihex, hexdump, csv or json. \
+                  With a range, only the addresses from start up to (excluding) end are written. \
+                  Raw dumps have no addresses, so their range must start at 0."
+    },
+    CommandInfo {
+        name: "save", alias: None, usage: "save <file>",
+        summary: "save a snapshot of the whole machine to the specified file",
+        details: "Writes registers, memory, interrupt and device state to the given file. \
+                  The snapshot can be restored with `load` or with `mima run --resume <file>`."
+    },
+    CommandInfo {
+        name: "load", alias: None, usage: "load <file>",
+        summary: "restore a snapshot created with `save`",
+        details: "Replaces the state of the machine with the snapshot in the given file. \
+                  Breakpoints and display expressions are kept, `restart` goes back to the snapshot's program."
+    },
+    CommandInfo {
+        name: "halt", alias: None, usage: "halt",
+        summary: "stop execution",
+        details: "Halts the machine and leaves the debugger."
+    },
+    CommandInfo {
+        name: "help", alias: Some("?"), usage: "help [command]",
+        summary: "display this help message or detailed help for a command",
+        details: "Without arguments, lists all commands. With a command name, shows its usage in detail."
+    }
+];
+
+const HISTORY_FILE: &str = ".mima_history";
+
+// where the debugger writes its messages: the terminal, or a buffer that is read by the TUI
+#[derive(Default)]
+pub struct Output {
+    buffer: Option<RefCell<Vec<OutputLine>>>
+}
+
+pub struct OutputLine {
+    pub text: String,
+    pub error: bool
+}
+
+impl Output {
+    pub fn buffered() -> Self {
+        Output { buffer: Some(RefCell::new(Vec::new())) }
+    }
+
+    pub fn line<S: Into<String>>(&self, text: S) {
+        self.write(text.into(), false);
+    }
+
+    pub fn error<S: Into<String>>(&self, text: S) {
+        self.write(text.into(), true);
+    }
+
+    fn write(&self, text: String, error: bool) {
+        match &self.buffer {
+            Some(buffer) => buffer.borrow_mut().push(OutputLine { text, error }),
+            None if error => eprintln!("{}", text),
+            None => println!("{}", text)
+        }
+    }
+
+    // removes and returns all buffered lines
+    pub fn take(&self) -> Vec<OutputLine> {
+        self.buffer.as_ref().map_or(Vec::new(), |buffer| buffer.replace(Vec::new()))
+    }
+}
 
 pub struct Debugger<'a> {
     runtime: &'a mut Runtime,
-    editor: Editor<()>,
+    image: Vec<MimaValue>,
+    editor: Editor<DebuggerHelper>,
+    symbols: SymbolTable,
+    displays: Vec<DisplayEntry>,
+    displays_file: Option<PathBuf>,
     breakpoints: HashSet<MimaAddress>,
     break_next: bool,
-    break_state: bool
+    break_state: bool,
+    output: Output,
+    // creates the writers device output goes to, if it does not go to stdout
+    device_output: Option<Box<dyn Fn() -> Box<dyn Write>>>
 }
 
 impl<'a> From<&'a mut Runtime> for Debugger<'a> {
     fn from(runtime: &'a mut Runtime) -> Self {
-        let mut editor = Editor::<()>::new();
+        let mut editor = Editor::<DebuggerHelper>::new();
         editor.set_auto_add_history(true);
+        editor.set_helper(Some(DebuggerHelper::default()));
+        if let Some(path) = history_path() {
+            // a missing history file is expected on first use
+            let _ = editor.load_history(&path);
+        }
+        let image = runtime.mem_iter().copied().collect();
         Debugger {
             runtime,
+            image,
             editor,
+            symbols: SymbolTable::default(),
+            displays: Vec::new(),
+            displays_file: None,
             breakpoints: HashSet::new(),
             break_next: true,
-            break_state: false
+            break_state: false,
+            output: Output::default(),
+            device_output: None
         }
     }
 }
 
 impl Debugger<'_> {
 
+    // sets the program `restart` goes back to, by default the memory the debugger was started with
+    pub fn with_image(mut self, image: Vec<MimaValue>) -> Self {
+        self.image = image;
+        self
+    }
+
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    // sends the output of devices to writers created by `output`, also after a snapshot was loaded
+    pub fn set_device_output<F: Fn() -> Box<dyn Write> + 'static>(&mut self, output: F) {
+        self.runtime.set_device_output(&output);
+        self.device_output = Some(Box::new(output));
+    }
+
+    pub fn with_symbols(mut self, symbols: SymbolTable) -> Self {
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.labels = symbols.names().map(|s| s.to_owned()).collect();
+        }
+        self.symbols = symbols;
+        self
+    }
+
+    // loads display expressions from the given file, if it exists, and saves all changes to it
+    pub fn with_displays(mut self, file: Option<PathBuf>) -> Result<Self, String> {
+        if let Some(path) = &file {
+            if path.exists() {
+                let content = fs::read_to_string(path)
+                    .map_err(|e| format!("Could not read displays file: {}", e))?;
+                for line in content.lines().filter(|l| !l.trim().is_empty()) {
+                    let entry = DisplayEntry::parse(line.split_whitespace().collect())
+                        .ok_or(format!("Invalid display expression in displays file: {}", line))?;
+                    self.displays.push(entry);
+                }
+            }
+        }
+        self.displays_file = file;
+        Ok(self)
+    }
+
     pub fn run(&mut self) -> Result<(), String> {
+        let result = self.run_loop();
+        if let Some(path) = history_path() {
+            if let Err(error) = self.editor.save_history(&path) {
+                self.output.error(format!("Could not save debugger history: {}", error));
+            }
+        }
+        result
+    }
+
+    fn run_loop(&mut self) -> Result<(), String> {
         while !self.runtime.halt {
-            let instr_addr = self.runtime.read_iar();
-            if self.break_next || self.breakpoints.contains(&instr_addr) {
-                self.break_next = false;
+            if self.should_break() {
+                self.enter_break();
                 self.print_state();
-                self.break_state = true;
                 while self.break_state {
                     let input = self.editor.readline(">")
                         .map_err(|kind| kind.to_string())?;
-                    let args: Vec<&str> = input.split(' ').collect();
-                    match args.as_slice() {
-                        ["state"] => self.print_state(),
-                        [""] => self.step(),
-                        ["continue"] => self.continue_run(),
-                        ["break", addr] => self.toggle_breakpoint(addr),
-                        ["read", addr] => self.print_mem(addr),
-                        ["write", addr, val] => self.write_mem(addr, val),
-                        ["dump", path] => self.make_dump(path),
-                        ["halt"] => self.stop(),
-                        ["?"] => println!("{}", HELP_MESSAGE),
-                        _ => {
-                            eprintln!("Unknown command. Type '?' for help");
-                        }
-                    }
+                    self.execute(input.trim_end_matches(&['\r', '\n'][..]));
                 }
             }
             if !self.runtime.halt {
-                self.runtime.step()?;
+                self.step_checked()?;
             }
         }
         Ok(())
     }
 
+    // whether execution has to stop before the next instruction
+    pub fn should_break(&self) -> bool {
+        self.break_next || self.breakpoints.contains(&self.runtime.read_iar())
+    }
+
+    // enters the break state in which commands are read
+    pub fn enter_break(&mut self) {
+        self.break_next = false;
+        self.break_state = true;
+    }
+
+    // makes execution stop before the next instruction
+    pub fn pause(&mut self) {
+        self.break_next = true;
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.break_state
+    }
+
+    // executes one instruction and reports assertions that failed in it
+    pub fn step_checked(&mut self) -> Result<(), String> {
+        let failures = self.runtime.assertion_failures().len();
+        self.runtime.step()?;
+        // stop after a failed assertion so the state can be inspected
+        for failure in &self.runtime.assertion_failures()[failures..] {
+            self.output.error(failure.clone());
+            self.break_next = true;
+        }
+        Ok(())
+    }
+
+    pub fn runtime(&self) -> &Runtime {
+        self.runtime
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn breakpoints(&self) -> &HashSet<MimaAddress> {
+        &self.breakpoints
+    }
+
+    pub fn output(&self) -> &Output {
+        &self.output
+    }
+
+    pub fn execute(&mut self, input: &str) {
+        let mut args: Vec<&str> = input.split(' ').collect();
+        if let Some(command) = COMMANDS.iter().find(|c| c.alias == Some(args[0])) {
+            args[0] = command.name;
+        }
+        match args.as_slice() {
+            ["state"] => {
+                self.print_state();
+                self.print_internal_registers();
+                if self.runtime.interrupts().is_some() {
+                    self.print_interrupts();
+                }
+            }
+            [""] | ["step"] => self.step(),
+            ["ustep"] => self.micro_step(),
+            ["continue"] => self.continue_run(),
+            ["break", addr] => self.toggle_breakpoint(addr),
+            ["read", addr] => self.print_mem(addr),
+            [spec, addr] if spec.starts_with("x/") => self.examine(&spec[2..], addr),
+            ["disas", addr] => self.disassemble(addr, "10"),
+            ["disas", addr, count] => self.disassemble(addr, count),
+            ["mem", addr] => self.hexdump(addr, "32"),
+            ["mem", addr, count] => self.hexdump(addr, count),
+            ["write", addr, val] => self.write_mem(addr, val),
+            ["display"] => self.print_displays(),
+            ["display", expr @ ..] => self.add_display(expr),
+            ["undisplay"] => self.remove_display(None),
+            ["undisplay", n] => self.remove_display(Some(n)),
+            ["set", "accu", val] => self.set_accu(val),
+            ["set", "iar", addr] => self.set_iar(addr),
+            ["jump", addr] => self.jump(addr),
+            ["interrupts"] => self.print_interrupts(),
+            ["interrupts", "raise", source] => self.raise_interrupt(source),
+            ["restart"] => self.restart(),
+            ["dump", path] => self.make_dump(path, "raw", None),
+            ["dump", path, format] => self.make_dump(path, format, None),
+            ["dump", path, format, range] => self.make_dump(path, format, Some(range)),
+            ["save", path] => self.save_snapshot(path),
+            ["load", path] => self.load_snapshot(path),
+            ["halt"] => self.stop(),
+            ["help"] => print_help(&self.output),
+            ["help", command] => print_command_help(&self.output, command),
+            [command, ..] => match find_command(command) {
+                Some(info) => self.output.error(format!("Usage: {}. Type 'help {}' for details", info.usage, info.name)),
+                None => self.output.error("Unknown command. Type 'help' for help")
+            },
+            [] => {}
+        }
+    }
+
     fn print_state(&self) {
         let instr_addr = self.runtime.read_iar();
-        println!("Accumulator: {val} {val:#07x} {val:#022b}", val = self.runtime.read_accu());
+        let accu = self.runtime.read_accu();
+        let flags = self.runtime.read_flags()
+            .map_or(String::new(), |f| format!(" {}", f));
+        self.output.line(format!("Accumulator: {} ({}) {:#08x} {:#026b}{}",
+                 accu, self.runtime.isa().to_signed(accu), accu, accu, flags));
+        let ir = self.runtime.read_ir();
+        self.output.line(format!("IAR: {:#07x} IR: {:#08x} ({})", instr_addr, ir, Format::Instruction.apply(ir, self.runtime.isa())));
 
         if instr_addr > 0 {
-            println!("   {}", stringify_instr(self.runtime, instr_addr - 1));
+            self.output.line(format!("   {}", stringify_instr(self.runtime, instr_addr - 1)));
+        }
+        self.output.line(format!("-> {}", stringify_instr(self.runtime, instr_addr)));
+        if instr_addr < self.runtime.isa().max_address() {
+            self.output.line(format!("   {}", stringify_instr(self.runtime, instr_addr + 1)));
         }
-        println!("-> {}", stringify_instr(self.runtime, instr_addr));
-        if instr_addr < ADDRESS_SPACE - 1 {
-            println!("   {}", stringify_instr(self.runtime, instr_addr + 1));
+        self.print_displays();
+    }
+
+    fn print_internal_registers(&self) {
+        let runtime = &self.runtime;
+        self.output.line(format!("SAR: {:#07x} SDR: {:#08x} X: {:#08x} Y: {:#08x} Z: {:#08x}",
+                 runtime.read_sar(), runtime.read_sdr(),
+                 runtime.read_x(), runtime.read_y(), runtime.read_z()));
+        if runtime.isa().supports(Opcode::CALL) || runtime.isa().supports(Opcode::LDSP) {
+            self.output.line(format!("SP: {:#07x}", runtime.read_sp()));
+        }
+    }
+
+    fn print_interrupts(&self) {
+        let controller = match self.runtime.interrupts() {
+            Some(controller) => controller,
+            None => {
+                self.output.line("Interrupts are not configured");
+                return;
+            }
+        };
+        self.output.line(format!("Interrupts: {} vector: {:#07x} save: {:#07x}",
+                 if controller.enabled { "enabled" } else { "disabled" },
+                 controller.vector, controller.save_addr));
+        if let (Some(interval), Some(remaining)) = (controller.timer_interval(), controller.timer_remaining()) {
+            self.output.line(format!("Timer: every {} instructions, next in {}", interval, remaining));
+        }
+        let pending: Vec<String> = controller.pending().map(|source| source.to_string()).collect();
+        self.output.line(format!("Pending: {}", if pending.is_empty() { "-".to_owned() } else { pending.join(", ") }));
+        for (i, (base, device)) in self.runtime.devices().enumerate() {
+            self.output.line(format!("Device {}: {} at {:#07x}", i, device.name(), base));
         }
     }
 
+    fn raise_interrupt(&mut self, source: &str) {
+        let source = if source == "timer" {
+            InterruptSource::Timer
+        } else {
+            match source.parse::<usize>() {
+                Ok(i) if i < self.runtime.devices().count() => InterruptSource::Device(i),
+                _ => {
+                    self.output.error(format!("Unknown interrupt source '{}'", source));
+                    return;
+                }
+            }
+        };
+        if self.runtime.interrupts().is_none() {
+            self.output.error("Interrupts are not configured");
+            return;
+        }
+        self.runtime.raise_interrupt(source);
+        self.output.line(format!("Raised {} interrupt", source));
+    }
+
+    fn print_displays(&self) {
+        for line in self.display_values() {
+            self.output.line(line);
+        }
+    }
+
+    // the current values of all display expressions
+    pub fn display_values(&self) -> Vec<String> {
+        self.displays.iter().enumerate().map(|(i, entry)| match self.parse_addr(&entry.expr) {
+            Ok(addr) => {
+                let val = self.runtime.read_mem(addr);
+                let formatted = match entry.format {
+                    Some(format) => format.apply(val, self.runtime.isa()),
+                    None => format!("{} ({}) {:#08x}", val, self.runtime.isa().to_signed(val), val)
+                };
+                format!("{}: {} = {}", i + 1, entry.expr, formatted)
+            }
+            Err(_) => format!("{}: {} = <unknown address>", i + 1, entry.expr)
+        }).collect()
+    }
+
+    fn add_display(&mut self, args: &[&str]) {
+        match DisplayEntry::parse(args.to_vec()) {
+            Some(entry) if self.parse_addr(&entry.expr).is_ok() => {
+                self.displays.push(entry);
+                self.save_displays();
+                self.print_displays();
+            }
+            Some(entry) => self.output.error(format!("Invalid address {}", entry.expr)),
+            None => self.output.error("Usage: display <addr> [x|d|u|b|c|i]")
+        }
+    }
+
+    fn remove_display(&mut self, n: Option<&str>) {
+        match n.map(|n| n.parse::<usize>()) {
+            None => {
+                self.displays.clear();
+                self.output.line("Removed all display expressions");
+            }
+            Some(Ok(n)) if n >= 1 && n <= self.displays.len() => {
+                let entry = self.displays.remove(n - 1);
+                self.output.line(format!("Removed display expression {}", entry.expr));
+            }
+            Some(_) => {
+                self.output.error(format!("No display expression {}", n.unwrap()));
+                return;
+            }
+        }
+        self.save_displays();
+    }
+
+    fn save_displays(&self) {
+        if let Some(path) = &self.displays_file {
+            let content: String = self.displays.iter()
+                .map(|entry| format!("{}\n", entry))
+                .collect();
+            if let Err(error) = fs::write(path, content) {
+                self.output.error(format!("Could not save display expressions: {}", error));
+            }
+        }
+    }
+
+    // parses a numeric address or resolves a label
+    pub fn parse_addr(&self, s: &str) -> Result<MimaAddress, ParseIntError> {
+        self.symbols.parse_addr(s, self.runtime.isa())
+    }
+
+    fn parse_value(&self, s: &str) -> Result<MimaValue, ParseIntError> {
+        self.runtime.isa().parse_value(s)
+    }
+
     fn step(&mut self) {
         self.break_state = false;
         self.break_next = true;
     }
 
+    fn micro_step(&mut self) {
+        match self.runtime.micro_step() {
+            Ok(step) => {
+                self.output.line(step.to_string());
+                self.print_internal_registers();
+                if self.runtime.halt {
+                    self.break_state = false;
+                } else if self.runtime.read_cycle() == 0 {
+                    self.print_state();
+                }
+            }
+            Err(error) => self.output.error(error)
+        }
+    }
+
     fn continue_run(&mut self) {
         self.break_state = false;
     }
 
     fn toggle_breakpoint(&mut self, addr: &str) {
-        if let Ok(addr) = parse_mima_addr(addr) {
+        if let Ok(addr) = self.parse_addr(addr) {
             if self.breakpoints.contains(&addr) {
                 self.breakpoints.remove(&addr);
-                println!("Breakpoint at address {:#x} removed", addr);
+                self.output.line(format!("Breakpoint at address {:#x} removed", addr));
             } else {
                 self.breakpoints.insert(addr);
-                println!("Breakpoint set at address {:#x}", addr);
+                self.output.line(format!("Breakpoint set at address {:#x}", addr));
             }
         } else {
-            eprintln!("Invalid address {}", addr);
+            self.output.error(format!("Invalid address {}", addr));
         }
     }
 
     fn print_mem(&self, addr: &str) {
-        if let Ok(addr) = parse_mima_addr(addr) {
-            println!("{:#07x}: {val} {val:#07x} {val:#022b}",
-                     addr, val = self.runtime.read_mem(addr));
+        if let Ok(addr) = self.parse_addr(addr) {
+            let val = self.runtime.read_mem(addr);
+            self.output.line(format!("{:#07x}: {} ({}) {:#08x} {:#026b}",
+                     addr, val, self.runtime.isa().to_signed(val), val, val));
+        } else {
+            self.output.error(format!("Invalid address {}", addr));
+        }
+    }
+
+    fn examine(&self, spec: &str, addr: &str) {
+        match (parse_examine(spec), self.parse_addr(addr)) {
+            (Some((count, format)), Ok(addr)) => {
+                for addr in address_range(addr, count, self.runtime.isa()) {
+                    self.output.line(format!("{:#07x}: {}", addr, format.apply(self.runtime.read_mem(addr), self.runtime.isa())));
+                }
+            }
+            (None, _) => self.output.error(format!("Invalid format x/{}", spec)),
+            (_, Err(_)) => self.output.error(format!("Invalid address {}", addr))
+        }
+    }
+
+    fn disassemble(&self, addr: &str, count: &str) {
+        if let [Ok(addr), Ok(count)] = [self.parse_addr(addr), count.parse::<u32>()] {
+            for addr in address_range(addr, count, self.runtime.isa()) {
+                let marker = if addr == self.runtime.read_iar() { "->" } else { "  " };
+                self.output.line(format!("{} {}", marker, stringify_instr(self.runtime, addr)));
+            }
+        } else {
+            self.output.error(format!("Invalid address {} or count {}", addr, count));
+        }
+    }
+
+    fn hexdump(&self, addr: &str, count: &str) {
+        if let [Ok(addr), Ok(count)] = [self.parse_addr(addr), count.parse::<u32>()] {
+            let values: Vec<(MimaAddress, MimaValue)> = address_range(addr, count, self.runtime.isa())
+                .map(|addr| (addr, self.runtime.read_mem(addr)))
+                .collect();
+            for row in hexdump_rows(&values, self.runtime.isa()) {
+                self.output.line(row);
+            }
         } else {
-            eprintln!("Invalid address {}", addr);
+            self.output.error(format!("Invalid address {} or count {}", addr, count));
         }
     }
 
     fn write_mem(&mut self, addr: &str, val: &str) {
-        if let [Ok(addr), Ok(val)] = [parse_mima_addr(addr), parse_mima_value(val)] {
+        if let [Ok(addr), Ok(val)] = [self.parse_addr(addr), self.parse_value(val)] {
             self.runtime.write_mem(addr, val);
-            println!("Wrote {} to address {}", addr, val);
+            self.output.line(format!("Wrote {} to address {:#x}", val, addr));
+        } else {
+            self.output.error(format!("Invalid address {} or value {}", addr, val));
+        }
+    }
+
+    fn set_accu(&mut self, val: &str) {
+        if let Ok(val) = self.parse_value(val) {
+            self.runtime.write_accu(val);
+            self.output.line(format!("Accumulator set to {}", val));
+        } else {
+            self.output.error(format!("Invalid value {}", val));
+        }
+    }
+
+    fn set_iar(&mut self, addr: &str) {
+        if let Ok(addr) = self.parse_addr(addr) {
+            self.runtime.write_iar(addr);
+            self.output.line(format!("Next instruction address set to {:#x}", addr));
+        } else {
+            self.output.error(format!("Invalid address {}", addr));
+        }
+    }
+
+    fn jump(&mut self, addr: &str) {
+        if let Ok(addr) = self.parse_addr(addr) {
+            self.runtime.write_iar(addr);
+            self.break_state = false;
         } else {
-            eprintln!("Invalid address {} or value {}", addr, val);
+            self.output.error(format!("Invalid address {}", addr));
         }
     }
 
-    fn make_dump(&self, path: &str) {
+    fn restart(&mut self) {
+        self.runtime.reset(self.image.clone());
+        self.output.line("Restarted program");
+        self.print_state();
+    }
+
+    fn make_dump(&self, path: &str, format: &str, range: Option<&str>) {
+        let format = match DumpFormat::from_str(format) {
+            Ok(format) => format,
+            Err(_) => {
+                self.output.error(format!("Unknown dump format '{}'. Expected one of {}", format, DumpFormat::VARIANTS.join(", ")));
+                return;
+            }
+        };
+        let range = match range.map(AddressRange::from_str).transpose() {
+            Ok(range) => range,
+            Err(error) => {
+                self.output.error(error);
+                return;
+            }
+        };
         match PathBuf::from_str(path) {
             Ok(buf) => {
-                if let Err(error) = create_memdump(&buf, self.runtime) {
-                    eprintln!("Unable to create memory dump at {}: {}",
-                              path, error.to_string());
+                if let Err(error) = create_memdump(&buf, self.runtime, format, range) {
+                    self.output.error(format!("Unable to create memory dump at {}: {}",
+                              path, error));
                 } else {
-                    println!("Created memory dump at {}", path);
+                    self.output.line(format!("Created memory dump at {}", path));
                 }
             }
             Err(error) => {
-                eprintln!("Could not read path: {}", error.to_string());
+                self.output.error(format!("Could not read path: {}", error));
+            }
+        }
+    }
+
+    fn save_snapshot(&self, path: &str) {
+        let result = File::create(path)
+            .map_err(|e| format!("Could not open snapshot file: {}", e))
+            .and_then(|file| self.runtime.snapshot(&self.image).write(BufWriter::new(file)));
+        match result {
+            Ok(()) => self.output.line(format!("Saved snapshot to {}", path)),
+            Err(error) => self.output.error(format!("Unable to save snapshot to {}: {}", path, error))
+        }
+    }
+
+    fn load_snapshot(&mut self, path: &str) {
+        let result = File::open(path)
+            .map_err(|e| format!("Could not open snapshot file: {}", e))
+            .and_then(|file| Snapshot::read(BufReader::new(file)))
+            .and_then(|mut snapshot| {
+                let program = std::mem::take(&mut snapshot.program);
+                snapshot.restore().map(|runtime| (runtime, program))
+            });
+        match result {
+            Ok((runtime, program)) => {
+                // assertions are not part of snapshots, they come with the program being debugged
+                *self.runtime = runtime.with_assertions(self.runtime.assertions().to_vec());
+                if let Some(output) = &self.device_output {
+                    self.runtime.set_device_output(output);
+                }
+                self.image = program;
+                self.output.line(format!("Loaded snapshot from {}", path));
+                self.print_state();
             }
+            Err(error) => self.output.error(format!("Unable to load snapshot from {}: {}", path, error))
         }
     }
 
@@ -155,8 +746,298 @@ impl Debugger<'_> {
 
 }
 
+// the count and format of `x/<n><fmt>`, both of which may be left out
+fn parse_examine(spec: &str) -> Option<(u32, Format)> {
+    let digits_end = spec.find(|c: char| !c.is_ascii_digit()).unwrap_or(spec.len());
+    let (count, format) = spec.split_at(digits_end);
+    let count = if count.is_empty() { 1 } else { count.parse::<u32>().ok()? };
+    let format = match format {
+        "" => Format::Hex,
+        f if f.len() == 1 => Format::from_char(f.chars().next()?)?,
+        _ => return None
+    };
+    Some((count, format))
+}
+
+fn find_command(name: &str) -> Option<&'static CommandInfo> {
+    let name = name.split('/').next().unwrap_or(name);
+    COMMANDS.iter().find(|c| c.name == name || c.alias == Some(name))
+}
+
+fn print_help(output: &Output) {
+    for command in COMMANDS {
+        let alias = command.alias.map_or(String::new(), |a| format!(" ({})", a));
+        output.line(format!("`{}`{} - {}", command.usage, alias, command.summary));
+    }
+    output.line("<ENTER> - same as `step`");
+    output.line("Addresses may be given as numbers or as labels from the symbol file");
+}
+
+fn print_command_help(output: &Output, name: &str) {
+    match find_command(name) {
+        Some(command) => {
+            output.line(format!("Usage: {}", command.usage));
+            if let Some(alias) = command.alias {
+                output.line(format!("Alias: {}", alias));
+            }
+            output.line(command.details);
+        }
+        None => output.error(format!("Unknown command {}", name))
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    dirs_next::home_dir().map(|home| home.join(HISTORY_FILE))
+}
+
+// an address expression printed whenever execution stops
+struct DisplayEntry {
+    expr: String,
+    format: Option<Format>
+}
+
+impl DisplayEntry {
+    fn parse(args: Vec<&str>) -> Option<Self> {
+        match args.as_slice() {
+            [expr] => Some(DisplayEntry { expr: (*expr).to_owned(), format: None }),
+            [expr, format] if format.len() == 1 => Format::from_char(format.chars().next().unwrap())
+                .map(|format| DisplayEntry { expr: (*expr).to_owned(), format: Some(format) }),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for DisplayEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.format {
+            Some(format) => write!(f, "{} {}", self.expr, format.to_char()),
+            None => write!(f, "{}", self.expr)
+        }
+    }
+}
+
+// output formats for examining memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Hex,
+    Signed,
+    Unsigned,
+    Binary,
+    Char,
+    Instruction
+}
+
+impl Format {
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            'x' => Some(Self::Hex),
+            'd' => Some(Self::Signed),
+            'u' => Some(Self::Unsigned),
+            'b' => Some(Self::Binary),
+            'c' => Some(Self::Char),
+            'i' => Some(Self::Instruction),
+            _ => None
+        }
+    }
+
+    fn to_char(self) -> char {
+        match self {
+            Self::Hex => 'x',
+            Self::Signed => 'd',
+            Self::Unsigned => 'u',
+            Self::Binary => 'b',
+            Self::Char => 'c',
+            Self::Instruction => 'i'
+        }
+    }
+
+    fn apply(self, val: MimaValue, isa: &InstructionSet) -> String {
+        match self {
+            Self::Hex => format!("{:#08x}", val),
+            Self::Signed => isa.to_signed(val).to_string(),
+            Self::Unsigned => val.to_string(),
+            Self::Binary => format!("{:#026b}", val),
+            Self::Char => format!("'{}'", as_char(val)),
+            Self::Instruction => isa.decode(val)
+                .map_or("???".to_owned(), |i| isa.format(&i))
+        }
+    }
+}
+
+// the addresses from start to start + count - 1 that are within the address space
+fn address_range(start: MimaAddress, count: u32, isa: &InstructionSet) -> impl Iterator<Item = MimaAddress> {
+    start..start.saturating_add(count).min(isa.address_space())
+}
+
 fn stringify_instr(runtime: &Runtime, instr_addr: MimaAddress) -> String {
-    let instr_str = Instruction::try_from(runtime.read_mem(instr_addr))
-        .map_or("???".to_owned(), |i| i.to_string());
+    let isa = runtime.isa();
+    let instr_str = isa.decode(runtime.read_mem(instr_addr))
+        .map_or("???".to_owned(), |i| isa.format(&i));
     format!("{:#07x}: {}", instr_addr, instr_str)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mima_common::instructions::Instruction;
+
+    // LDC 5, STV 0x10, HALT followed by the data
+    fn runtime(data: &[MimaValue]) -> Runtime {
+        let isa = InstructionSet::default();
+        let mut values: Vec<MimaValue> = [(Opcode::LDC, 5), (Opcode::STV, 0x10), (Opcode::HALT, 0)].iter()
+            .map(|(opcode, arg)| isa.encode(&Instruction { opcode: *opcode, arg: *arg }).unwrap())
+            .collect();
+        values.extend(data);
+        Runtime::with_instructions(&values)
+    }
+
+    fn debugger(runtime: &mut Runtime) -> Debugger<'_> {
+        let symbols = SymbolTable::from(std::collections::HashMap::from([("x".to_owned(), 0x10)]));
+        Debugger::from(runtime).with_output(Output::buffered()).with_symbols(symbols)
+    }
+
+    // the lines a command prints, errors prefixed with `error: `
+    fn execute(debugger: &mut Debugger, input: &str) -> Vec<String> {
+        debugger.execute(input);
+        debugger.output().take().into_iter()
+            .map(|line| if line.error { format!("error: {}", line.text) } else { line.text })
+            .collect()
+    }
+
+    #[test]
+    fn parses_examine_specs() {
+        assert_eq!(parse_examine(""), Some((1, Format::Hex)));
+        assert_eq!(parse_examine("4"), Some((4, Format::Hex)));
+        assert_eq!(parse_examine("d"), Some((1, Format::Signed)));
+        assert_eq!(parse_examine("12i"), Some((12, Format::Instruction)));
+        assert_eq!(parse_examine("4q"), None);
+        assert_eq!(parse_examine("4xd"), None);
+        assert_eq!(parse_examine("99999999999x"), None);
+    }
+
+    #[test]
+    fn examines_memory_in_all_formats() {
+        let mut runtime = runtime(&[0xffffff, 0x41]);
+        let mut debugger = debugger(&mut runtime);
+        assert_eq!(execute(&mut debugger, "x/2 3"), ["0x00003: 0xffffff", "0x00004: 0x000041"]);
+        assert_eq!(execute(&mut debugger, "x/2d 3"), ["0x00003: -1", "0x00004: 65"]);
+        assert_eq!(execute(&mut debugger, "x/u 3"), ["0x00003: 16777215"]);
+        assert_eq!(execute(&mut debugger, "x/b 4"), ["0x00004: 0b000000000000000001000001"]);
+        assert_eq!(execute(&mut debugger, "x/c 4"), ["0x00004: 'A'"]);
+        assert_eq!(execute(&mut debugger, "x/2i 0"), ["0x00000: LDC  0x5", "0x00001: STV  0x10"]);
+        assert_eq!(execute(&mut debugger, "x/4x 0xffffe").len(), 2);
+        assert_eq!(execute(&mut debugger, "x/4q 0"), ["error: Invalid format x/4q"]);
+        assert_eq!(execute(&mut debugger, "x/4 y"), ["error: Invalid address y"]);
+    }
+
+    #[test]
+    fn disassembles_around_the_iar() {
+        let mut runtime = runtime(&[]);
+        let mut debugger = debugger(&mut runtime);
+        assert_eq!(execute(&mut debugger, "disas 0 3"), ["-> 0x00000: LDC  0x5", "   0x00001: STV  0x10", "   0x00002: HALT"]);
+        assert_eq!(execute(&mut debugger, "disas x").len(), 10);
+        assert_eq!(execute(&mut debugger, "disas 0 many"), ["error: Invalid address 0 or count many"]);
+    }
+
+    #[test]
+    fn sets_registers() {
+        let mut runtime = runtime(&[]);
+        let mut debugger = debugger(&mut runtime);
+        assert_eq!(execute(&mut debugger, "set accu -1"), ["Accumulator set to 16777215"]);
+        assert_eq!(execute(&mut debugger, "set iar x"), ["Next instruction address set to 0x10"]);
+        assert_eq!((debugger.runtime().read_accu(), debugger.runtime().read_iar()), (0xffffff, 0x10));
+        assert_eq!(execute(&mut debugger, "set accu five"), ["error: Invalid value five"]);
+        assert_eq!(execute(&mut debugger, "set iar y"), ["error: Invalid address y"]);
+        assert_eq!(execute(&mut debugger, "set sp 1"), ["error: Usage: set accu <val> | set iar <addr>. Type 'help set' for details"]);
+    }
+
+    #[test]
+    fn jumps_continue_at_the_address() {
+        let mut runtime = runtime(&[]);
+        let mut debugger = debugger(&mut runtime);
+        debugger.enter_break();
+        assert_eq!(execute(&mut debugger, "jump y"), ["error: Invalid address y"]);
+        assert!(debugger.is_stopped());
+        assert!(execute(&mut debugger, "jump 2").is_empty());
+        assert!(!debugger.is_stopped());
+        assert_eq!(debugger.runtime().read_iar(), 2);
+    }
+
+    #[test]
+    fn restarts_keep_breakpoints() {
+        let mut runtime = runtime(&[]);
+        let mut debugger = debugger(&mut runtime);
+        execute(&mut debugger, "break 2");
+        debugger.step_checked().unwrap();
+        debugger.step_checked().unwrap();
+        execute(&mut debugger, "write 0 7");
+        assert_eq!(execute(&mut debugger, "restart")[0], "Restarted program");
+        let runtime = debugger.runtime();
+        assert_eq!((runtime.read_iar(), runtime.read_accu(), runtime.read_mem(0x10)), (0, 0, 0));
+        assert_eq!(runtime.read_mem(0), runtime.isa().encode(&Instruction { opcode: Opcode::LDC, arg: 5 }).unwrap());
+        assert!(debugger.breakpoints().contains(&2));
+    }
+
+    #[test]
+    fn displays_are_shown_on_every_stop() {
+        let mut runtime = runtime(&[]);
+        let mut debugger = debugger(&mut runtime);
+        assert_eq!(execute(&mut debugger, "display x d"), ["1: x = 0"]);
+        assert_eq!(execute(&mut debugger, "display 0 i"), ["1: x = 0", "2: 0 = LDC  0x5"]);
+        assert_eq!(execute(&mut debugger, "display y"), ["error: Invalid address y"]);
+        assert_eq!(execute(&mut debugger, "display x q"), ["error: Usage: display <addr> [x|d|u|b|c|i]"]);
+        execute(&mut debugger, "write x -2");
+        assert_eq!(execute(&mut debugger, "state")[4..6], ["1: x = -2", "2: 0 = LDC  0x5"]);
+        assert_eq!(execute(&mut debugger, "undisplay 3"), ["error: No display expression 3"]);
+        assert_eq!(execute(&mut debugger, "undisplay 1"), ["Removed display expression x"]);
+        assert_eq!(debugger.display_values(), ["1: 0 = LDC  0x5"]);
+        assert_eq!(execute(&mut debugger, "undisplay"), ["Removed all display expressions"]);
+        assert!(debugger.display_values().is_empty());
+    }
+
+    #[test]
+    fn displays_persist_in_their_file() {
+        let path = std::env::temp_dir().join(format!("mima-displays-{}", std::process::id()));
+        let mut runtime = runtime(&[]);
+        let mut debugger = debugger(&mut runtime).with_displays(Some(path.clone())).unwrap();
+        execute(&mut debugger, "display x d");
+        execute(&mut debugger, "display 1");
+        execute(&mut debugger, "undisplay 2");
+        execute(&mut debugger, "display 2 i");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "x d\n2 i\n");
+
+        let mut runtime = self::runtime(&[]);
+        let debugger = self::debugger(&mut runtime).with_displays(Some(path.clone())).unwrap();
+        assert_eq!(debugger.display_values(), ["1: x = 0", "2: 2 = HALT"]);
+        fs::write(&path, "x q\n").unwrap();
+        let mut runtime = self::runtime(&[]);
+        let error = self::debugger(&mut runtime).with_displays(Some(path.clone())).err().unwrap();
+        assert_eq!(error, "Invalid display expression in displays file: x q");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn explains_commands() {
+        let mut runtime = runtime(&[]);
+        let mut debugger = debugger(&mut runtime);
+        let help = execute(&mut debugger, "help");
+        assert_eq!(help.len(), COMMANDS.len() + 2);
+        assert!(help.contains(&"`step` (s) - run the next instruction and immediately break again".to_owned()));
+        assert_eq!(execute(&mut debugger, "? s"),
+                   ["Usage: step", "Alias: s", "Executes exactly one instruction. Entering an empty line does the same."]);
+        assert_eq!(execute(&mut debugger, "help x/4d")[0], "Usage: x/<n><fmt> <addr>");
+        assert_eq!(execute(&mut debugger, "help frobnicate"), ["error: Unknown command frobnicate"]);
+        assert_eq!(execute(&mut debugger, "frobnicate"), ["error: Unknown command. Type 'help' for help"]);
+        assert_eq!(execute(&mut debugger, "disas"), ["error: Usage: disas <addr> [count]. Type 'help disas' for details"]);
+    }
+
+    #[test]
+    fn expands_aliases() {
+        let mut runtime = runtime(&[]);
+        let mut debugger = debugger(&mut runtime);
+        assert_eq!(execute(&mut debugger, "b x"), ["Breakpoint set at address 0x10"]);
+        debugger.enter_break();
+        execute(&mut debugger, "c");
+        assert!(!debugger.is_stopped());
+    }
+}
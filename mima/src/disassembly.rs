@@ -1,12 +1,11 @@
 use mima_common::types::MimaValue;
-use mima_common::instructions::Instruction;
-use std::convert::TryFrom;
+use mima_common::isa::InstructionSet;
 
-pub fn disassemble(instructions: Vec<MimaValue>) -> Result<String, String> {
+pub fn disassemble(instructions: Vec<MimaValue>, isa: &InstructionSet) -> Result<String, String> {
     let mut output = String::new();
     for instr_value in instructions {
-        let instr = Instruction::try_from(instr_value)?;
-        output.push_str(&*instr.to_string());
+        let instr = isa.decode(instr_value)?;
+        output.push_str(&isa.format(&instr));
         output.push('\n');
     }
     Ok(output)
@@ -0,0 +1,149 @@
+use crate::source::{scan, SourceLine};
+use std::fs;
+use std::path::PathBuf;
+
+const INDENT: &str = "    ";
+// spaces between the longest code in a block and its comments
+const COMMENT_GAP: usize = 2;
+
+// formats the given files in place, or only reports the ones that are not formatted when checking
+pub fn format_files(files: &[PathBuf], check: bool) -> Result<(), String> {
+    let mut unformatted = 0;
+    for path in files {
+        let source = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let formatted = format_source(&source);
+        if formatted == source {
+            continue;
+        }
+        if check {
+            let line = source.lines().zip(formatted.lines())
+                .position(|(original, formatted)| original != formatted)
+                .unwrap_or_else(|| source.lines().count().min(formatted.lines().count()));
+            println!("{}: not formatted, starting at line {}", path.display(), line + 1);
+            unformatted += 1;
+        } else {
+            fs::write(path, formatted)
+                .map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+        }
+    }
+    if unformatted > 0 {
+        Err(format!("{} of {} files are not formatted", unformatted, files.len()))
+    } else {
+        Ok(())
+    }
+}
+
+// labels start at the beginning of a line, instructions and directives are indented with
+// their operands in one column, and comments after code are aligned within blocks of lines
+// that are separated by blank lines
+pub fn format_source(source: &str) -> String {
+    let lines = scan(source);
+    let mnemonic_width = lines.iter()
+        .filter_map(|line| line.mnemonic())
+        .map(|token| token.text.chars().count())
+        .max()
+        .unwrap_or(0);
+    let code: Vec<String> = lines.iter().map(|line| format_code(line, mnemonic_width)).collect();
+
+    let mut output: Vec<String> = Vec::new();
+    let mut block_start = 0;
+    while block_start < lines.len() {
+        if is_blank(&lines[block_start]) {
+            if output.last().is_some_and(|line| !line.is_empty()) {
+                output.push(String::new());
+            }
+            block_start += 1;
+            continue;
+        }
+        let block_end = (block_start..lines.len())
+            .find(|i| is_blank(&lines[*i]))
+            .unwrap_or(lines.len());
+        let comment_column = (block_start..block_end)
+            .filter(|i| lines[*i].comment.is_some() && !code[*i].is_empty())
+            .map(|i| code[i].chars().count() + COMMENT_GAP)
+            .max()
+            .unwrap_or(0);
+        for i in block_start..block_end {
+            output.push(match (&lines[i].comment, code[i].is_empty()) {
+                (Some(comment), true) => format!("{}{}", comment_indent(&lines[i + 1..]), comment.text),
+                (Some(comment), false) => format!("{:width$}{}", code[i], comment.text, width = comment_column),
+                (None, _) => code[i].clone()
+            });
+        }
+        block_start = block_end;
+    }
+    while output.last().is_some_and(|line| line.is_empty()) {
+        output.pop();
+    }
+    output.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+fn is_blank(line: &SourceLine) -> bool {
+    line.tokens.is_empty() && line.comment.is_none()
+}
+
+fn format_code(line: &SourceLine, mnemonic_width: usize) -> String {
+    let rest = |skip: usize| line.tokens.iter()
+        .skip(skip)
+        .map(|token| token.text.as_str())
+        .collect::<Vec<&str>>()
+        .join(" ");
+    if line.label().is_some() {
+        rest(0)
+    } else if line.directive().is_some() {
+        format!("{}{}", INDENT, rest(0))
+    } else if let Some(mnemonic) = line.mnemonic() {
+        let mnemonic = mnemonic.text.to_uppercase();
+        if line.tokens.len() > 1 {
+            format!("{}{:width$} {}", INDENT, mnemonic, rest(1), width = mnemonic_width)
+        } else {
+            format!("{}{}", INDENT, mnemonic)
+        }
+    } else {
+        String::new()
+    }
+}
+
+// comments on their own line are indented like the code that follows them
+fn comment_indent(following: &[SourceLine]) -> &'static str {
+    match following.iter().find(|line| !line.tokens.is_empty()) {
+        Some(line) if line.label().is_none() => INDENT,
+        _ => ""
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNFORMATTED: &str = "; counts down\nstart:\n  ldc 5 ; five\n\n\n  stv   x ;store\n  .assert_at   end   accu 5\nloop:\n; done\nhalt\n\n";
+
+    #[test]
+    fn lays_out_labels_instructions_and_directives() {
+        assert_eq!(format_source(UNFORMATTED), "; counts down\n\
+            start:\n\
+            \x20   LDC  5  ; five\n\
+            \n\
+            \x20   STV  x  ;store\n\
+            \x20   .assert_at end accu 5\n\
+            loop:\n\
+            \x20   ; done\n\
+            \x20   HALT\n");
+    }
+
+    #[test]
+    fn aligns_comments_within_blocks() {
+        let formatted = format_source("add one ; a\nstv counter ; b\n\nhalt ; c\n");
+        assert_eq!(formatted, "    ADD  one      ; a\n    STV  counter  ; b\n\n    HALT  ; c\n");
+    }
+
+    #[test]
+    fn is_idempotent() {
+        let formatted = format_source(UNFORMATTED);
+        assert_eq!(format_source(&formatted), formatted);
+        let empty = format_source("\n\n");
+        assert_eq!(empty, "");
+        assert_eq!(format_source(&empty), empty);
+    }
+}
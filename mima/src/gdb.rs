@@ -0,0 +1,413 @@
+use mima_common::runtime::Runtime;
+use mima_common::types::{MimaAddress, MimaValue};
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+// instructions executed between two checks for an interrupt from GDB while continuing
+const STEPS_PER_CHECK: u32 = 10_000;
+const INTERRUPT: u8 = 0x03;
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+// most bytes read or written by one packet, so that replies fit into the advertised packet size
+const MAX_TRANSFER: usize = 0x1000;
+
+// waits for GDB to connect on the given port and serves the remote serial protocol
+pub fn serve_tcp(runtime: &mut Runtime, port: u16) -> Result<(), String> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .map_err(|e| format!("Could not listen on port {}: {}", port, e))?;
+    eprintln!("Waiting for GDB to connect on port {}", port);
+    let (stream, _) = listener.accept()
+        .map_err(|e| format!("Could not accept GDB connection: {}", e))?;
+    let reader = stream.try_clone().map_err(|e| e.to_string())?;
+    GdbStub::new(runtime, stream).serve(spawn_reader(reader))
+}
+
+// serves the remote serial protocol on stdin and stdout, for `target remote | mima run --gdb-stdio ...`
+pub fn serve_stdio(runtime: &mut Runtime) -> Result<(), String> {
+    GdbStub::new(runtime, io::stdout()).serve(spawn_reader(io::stdin()))
+}
+
+// bytes are read on a separate thread so that GDB can interrupt a running program
+fn spawn_reader<R: Read + Send + 'static>(mut input: R) -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = [0; 1024];
+        while let Ok(count) = input.read(&mut buffer) {
+            if count == 0 || buffer[..count].iter().any(|b| sender.send(*b).is_err()) {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+enum Stop {
+    Signal(u8),
+    Exited
+}
+
+// GDB addresses bytes, so every word of memory occupies value_bytes bytes, stored big-endian
+// like in binary files. The IAR register holds the byte address of the next instruction.
+struct GdbStub<'a, W: Write> {
+    runtime: &'a mut Runtime,
+    output: W,
+    breakpoints: HashSet<MimaAddress>,
+    ack: bool
+}
+
+impl<'a, W: Write> GdbStub<'a, W> {
+    fn new(runtime: &'a mut Runtime, output: W) -> Self {
+        GdbStub { runtime, output, breakpoints: HashSet::new(), ack: true }
+    }
+
+    fn serve(mut self, input: Receiver<u8>) -> Result<(), String> {
+        while let Some(packet) = self.read_packet(&input)? {
+            let reply = match packet.as_str() {
+                "?" => self.stop_reply(Stop::Signal(SIGTRAP)),
+                "g" => format!("{}{}", self.accu_hex(), self.iar_hex()),
+                "s" => {
+                    let stop = self.step();
+                    self.stop_reply(stop)
+                }
+                "c" => {
+                    let stop = self.continue_run(&input);
+                    self.stop_reply(stop)
+                }
+                "qAttached" => "1".to_owned(),
+                "qC" => "QC1".to_owned(),
+                "qfThreadInfo" => "m1".to_owned(),
+                "qsThreadInfo" => "l".to_owned(),
+                "QStartNoAckMode" => {
+                    self.send("OK")?;
+                    self.ack = false;
+                    continue;
+                }
+                "D" | "k" => {
+                    self.send("OK")?;
+                    return Ok(());
+                }
+                _ if packet.starts_with("qSupported") =>
+                    "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+;swbreak+".to_owned(),
+                _ if packet.starts_with('H') => "OK".to_owned(),
+                _ => self.handle_with_args(&packet).unwrap_or_else(|| "E01".to_owned())
+            };
+            self.send(&reply)?;
+        }
+        Ok(())
+    }
+
+    // packets with arguments. None is a malformed packet, unsupported packets get an empty reply
+    fn handle_with_args(&mut self, packet: &str) -> Option<String> {
+        if !packet.is_char_boundary(1) {
+            return Some(String::new());
+        }
+        let (command, args) = packet.split_at(1);
+        match command {
+            "G" => {
+                let accu_len = self.value_bytes() * 2;
+                let accu = u64::from_str_radix(args.get(..accu_len)?, 16).ok()?;
+                let iar = u64::from_str_radix(args.get(accu_len..accu_len + 8)?, 16).ok()?;
+                self.runtime.write_accu(accu as MimaValue);
+                self.runtime.write_iar(self.word_address(iar as MimaAddress));
+                Some("OK".to_owned())
+            }
+            "p" => match u8::from_str_radix(args, 16).ok()? {
+                0 => Some(self.accu_hex()),
+                1 => Some(self.iar_hex()),
+                _ => None
+            },
+            "P" => {
+                let (register, value) = args.split_once('=')?;
+                let value = u64::from_str_radix(value, 16).ok()?;
+                match u8::from_str_radix(register, 16).ok()? {
+                    0 => self.runtime.write_accu(value as MimaValue),
+                    1 => self.runtime.write_iar(self.word_address(value as MimaAddress)),
+                    _ => return None
+                }
+                Some("OK".to_owned())
+            }
+            "m" => {
+                let (addr, len) = args.split_once(',')?;
+                let addr = usize::from_str_radix(addr, 16).ok()?;
+                // GDB reads the rest with further packets
+                let len = usize::from_str_radix(len, 16).ok()?.min(MAX_TRANSFER);
+                let end = self.checked_end(addr, len)?;
+                Some((addr..end).map(|byte| format!("{:02x}", self.read_byte(byte))).collect())
+            }
+            "M" => {
+                let (location, data) = args.split_once(':')?;
+                let (addr, len) = location.split_once(',')?;
+                let addr = usize::from_str_radix(addr, 16).ok()?;
+                let len = usize::from_str_radix(len, 16).ok()?;
+                if len > MAX_TRANSFER || data.len() != len * 2 {
+                    return None;
+                }
+                self.checked_end(addr, len)?;
+                for i in 0..len {
+                    let byte = u8::from_str_radix(data.get(i * 2..i * 2 + 2)?, 16).ok()?;
+                    self.write_byte(addr + i, byte);
+                }
+                Some("OK".to_owned())
+            }
+            "Z" | "z" => {
+                let mut parts = args.split(',');
+                let kind = parts.next()?;
+                if kind != "0" && kind != "1" {
+                    return Some(String::new());
+                }
+                let addr = self.word_address(MimaAddress::from_str_radix(parts.next()?, 16).ok()?);
+                if command == "Z" {
+                    self.breakpoints.insert(addr);
+                } else {
+                    self.breakpoints.remove(&addr);
+                }
+                Some("OK".to_owned())
+            }
+            "q" => {
+                let annex = match args.strip_prefix("Xfer:features:read:target.xml:") {
+                    Some(annex) => annex,
+                    None => return Some(String::new())
+                };
+                let (offset, len) = annex.split_once(',')?;
+                let offset = usize::from_str_radix(offset, 16).ok()?;
+                let len = usize::from_str_radix(len, 16).ok()?;
+                let xml = self.target_description();
+                let chunk = xml.get(offset..).unwrap_or_default();
+                Some(if chunk.len() > len {
+                    format!("m{}", &chunk[..len])
+                } else {
+                    format!("l{}", chunk)
+                })
+            }
+            _ => Some(String::new())
+        }
+    }
+
+    fn step(&mut self) -> Stop {
+        if self.runtime.halt {
+            return Stop::Exited;
+        }
+        let failures = self.runtime.assertion_failures().len();
+        if let Err(error) = self.runtime.step() {
+            self.console(&format!("{}\n", error));
+            return Stop::Signal(SIGILL);
+        }
+        let new_failures = self.runtime.assertion_failures()[failures..].to_vec();
+        for failure in &new_failures {
+            self.console(&format!("{}\n", failure));
+        }
+        if self.runtime.halt {
+            Stop::Exited
+        } else {
+            Stop::Signal(SIGTRAP)
+        }
+    }
+
+    // runs until a breakpoint, a failed assertion, the end of the program or an interrupt from GDB
+    fn continue_run(&mut self, input: &Receiver<u8>) -> Stop {
+        let mut first = true;
+        loop {
+            for _ in 0..STEPS_PER_CHECK {
+                if !first && self.breakpoints.contains(&self.runtime.read_iar()) {
+                    return Stop::Signal(SIGTRAP);
+                }
+                first = false;
+                let failures = self.runtime.assertion_failures().len();
+                match self.step() {
+                    Stop::Signal(SIGTRAP) if self.runtime.assertion_failures().len() == failures => {}
+                    stop => return stop
+                }
+            }
+            match input.try_recv() {
+                Ok(INTERRUPT) => return Stop::Signal(SIGINT),
+                Ok(_) | Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Stop::Signal(SIGINT)
+            }
+        }
+    }
+
+    fn stop_reply(&self, stop: Stop) -> String {
+        match stop {
+            Stop::Signal(signal) => format!("S{:02x}", signal),
+            Stop::Exited => format!("W{:02x}", !self.runtime.assertion_failures().is_empty() as u8)
+        }
+    }
+
+    // reads the next packet, acknowledging it unless no-ack mode is active. Returns None when GDB disconnects
+    fn read_packet(&mut self, input: &Receiver<u8>) -> Result<Option<String>, String> {
+        loop {
+            // acknowledgements and interrupts outside of a running program are ignored
+            match input.recv() {
+                Ok(b'$') => {}
+                Ok(_) => continue,
+                Err(_) => return Ok(None)
+            }
+            let mut data = Vec::new();
+            loop {
+                match input.recv() {
+                    Ok(b'#') => break,
+                    Ok(byte) => data.push(byte),
+                    Err(_) => return Ok(None)
+                }
+            }
+            let checksum = match (input.recv(), input.recv()) {
+                (Ok(high), Ok(low)) => u8::from_str_radix(&String::from_utf8_lossy(&[high, low]), 16).ok(),
+                _ => return Ok(None)
+            };
+            let valid = checksum == Some(data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)));
+            if self.ack {
+                self.write(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> Result<(), String> {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        self.write(format!("${}#{:02x}", data, checksum).as_bytes())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.output.write_all(bytes)
+            .and_then(|_| self.output.flush())
+            .map_err(|e| format!("Could not write to GDB: {}", e))
+    }
+
+    // sends text to the GDB console
+    fn console(&mut self, text: &str) {
+        let hex: String = text.bytes().map(|b| format!("{:02x}", b)).collect();
+        // the program keeps running if GDB cannot be reached, the next reply will fail as well
+        let _ = self.send(&format!("O{}", hex));
+    }
+
+    fn value_bytes(&self) -> usize {
+        self.runtime.isa().value_bytes() as usize
+    }
+
+    fn word_address(&self, byte_addr: MimaAddress) -> MimaAddress {
+        byte_addr / self.value_bytes() as MimaAddress
+    }
+
+    fn accu_hex(&self) -> String {
+        format!("{:0digits$x}", self.runtime.read_accu(), digits = self.value_bytes() * 2)
+    }
+
+    fn iar_hex(&self) -> String {
+        format!("{:08x}", self.runtime.read_iar() * self.value_bytes() as MimaAddress)
+    }
+
+    // the end of a byte range, if the whole range is within memory
+    fn checked_end(&self, addr: usize, len: usize) -> Option<usize> {
+        let memory_bytes = self.runtime.isa().address_space() as usize * self.value_bytes();
+        addr.checked_add(len).filter(|end| *end <= memory_bytes)
+    }
+
+    fn read_byte(&self, byte_addr: usize) -> u8 {
+        let value_bytes = self.value_bytes();
+        let word = self.runtime.read_mem((byte_addr / value_bytes) as MimaAddress);
+        let shift = (value_bytes - 1 - byte_addr % value_bytes) * 8;
+        (word >> shift) as u8
+    }
+
+    fn write_byte(&mut self, byte_addr: usize, byte: u8) {
+        let value_bytes = self.value_bytes();
+        let addr = (byte_addr / value_bytes) as MimaAddress;
+        let shift = (value_bytes - 1 - byte_addr % value_bytes) * 8;
+        let word: MimaValue = self.runtime.read_mem(addr) & !(0xff << shift) | (byte as MimaValue) << shift;
+        self.runtime.write_mem(addr, word);
+    }
+
+    fn target_description(&self) -> String {
+        format!(concat!(
+            "<?xml version=\"1.0\"?>\n",
+            "<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n",
+            "<target version=\"1.0\">\n",
+            "  <feature name=\"org.mima.core\">\n",
+            "    <reg name=\"accu\" bitsize=\"{}\" type=\"int\" regnum=\"0\"/>\n",
+            "    <reg name=\"iar\" bitsize=\"32\" type=\"code_ptr\" regnum=\"1\"/>\n",
+            "  </feature>\n",
+            "</target>\n"), self.value_bytes() * 8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mima_common::instructions::{Instruction, Opcode};
+    use mima_common::isa::InstructionSet;
+
+    fn packet(data: &str) -> String {
+        format!("${}#{:02x}", data, data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b)))
+    }
+
+    // serves the given bytes until they run out, returning everything the stub wrote
+    fn serve_raw(runtime: &mut Runtime, input: &str) -> String {
+        let (sender, receiver) = mpsc::channel();
+        input.bytes().for_each(|b| sender.send(b).unwrap());
+        drop(sender);
+        let mut output = Vec::new();
+        GdbStub::new(runtime, &mut output).serve(receiver).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    // the replies to the packets, with their framing and checksums checked
+    fn replies(runtime: &mut Runtime, packets: &[&str]) -> Vec<String> {
+        let input: String = packets.iter().map(|data| packet(data)).collect();
+        let output = serve_raw(runtime, &input);
+        let replies: Vec<String> = output.split('$').skip(1)
+            .map(|framed| {
+                let (data, checksum) = framed.split_once('#').unwrap();
+                assert_eq!(packet(data), format!("${}#{}", data, checksum.trim_end_matches('+')));
+                data.to_owned()
+            })
+            .collect();
+        assert_eq!(output.matches('+').count(), packets.len());
+        replies
+    }
+
+    fn program(instructions: &[(Opcode, MimaAddress)]) -> Runtime {
+        let isa = InstructionSet::default();
+        let values: Vec<MimaValue> = instructions.iter()
+            .map(|(opcode, arg)| isa.encode(&Instruction { opcode: *opcode, arg: *arg }).unwrap())
+            .collect();
+        Runtime::with_instructions(&values)
+    }
+
+    #[test]
+    fn acknowledges_valid_packets_only() {
+        let mut runtime = Runtime::new();
+        assert_eq!(serve_raw(&mut runtime, "+$?#00$?#3f"), "-+$S05#b8");
+        assert_eq!(serve_raw(&mut runtime, &format!("{}{}", packet("QStartNoAckMode"), packet("?"))), "+$OK#9a$S05#b8");
+    }
+
+    #[test]
+    fn memory_packets_address_the_bytes_of_words() {
+        let mut runtime = Runtime::with_memory(vec![0x123456, 0xabcdef]);
+        assert_eq!(replies(&mut runtime, &["m0,6", "m1,2", "M4,2:0102", "m3,3"]),
+                   ["123456abcdef", "3456", "OK", "ab0102"]);
+        assert_eq!(runtime.read_mem(1), 0xab0102);
+    }
+
+    #[test]
+    fn memory_packets_stay_within_memory() {
+        let mut runtime = Runtime::new();
+        assert_eq!(replies(&mut runtime, &["m2ffffd,3", "m2ffffe,3", "mffffffffffffffff,2", "M2ffffe,3:000000",
+                                           "Mffffffffffffffff,1:00", "M0,2:00", "M0,1001:00"]),
+                   ["000000", "E01", "E01", "E01", "E01", "E01", "E01"]);
+        // reads are shortened to the transfer limit
+        assert_eq!(replies(&mut runtime, &["m0,2000"])[0].len(), MAX_TRANSFER * 2);
+    }
+
+    #[test]
+    fn continues_to_breakpoints() {
+        let mut runtime = program(&[(Opcode::LDC, 1), (Opcode::LDC, 2), (Opcode::LDC, 3), (Opcode::HALT, 0)]);
+        assert_eq!(replies(&mut runtime, &["Z0,6,4", "c", "g", "z0,6,4", "c", "g"]),
+                   ["OK", "S05", "00000200000006", "OK", "W00", "0000030000000c"]);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::assembly::assemble;
+use crate::source::scan;
+use mima_common::instructions::{Instruction, Opcode};
+use mima_common::isa::InstructionSet;
+use mima_common::types::MimaAddress;
+use std::collections::HashSet;
+use std::path::Path;
+
+pub struct Warning {
+    pub line: usize,
+    pub message: String
+}
+
+// lints a source file and prints the warnings, failing if there are any
+pub fn lint_file(path: &Path, source: &str, absolute: bool, isa: &InstructionSet) -> Result<(), String> {
+    let warnings = lint(source, absolute, isa)?;
+    for warning in &warnings {
+        println!("{}:{}: warning: {}", path.display(), warning.line, warning.message);
+    }
+    if warnings.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{} warning(s)", warnings.len()))
+    }
+}
+
+// assembles the source and looks for mistakes the assembler accepts, using the control flow
+// from the first instruction. CALL is assumed to return to the following instruction
+pub fn lint(source: &str, absolute: bool, isa: &InstructionSet) -> Result<Vec<Warning>, String> {
+    let program = assemble(source.to_owned(), absolute, isa)?;
+    let lines = scan(source);
+    let instructions = &program.instructions;
+    let count = instructions.len();
+    let labels: HashSet<&str> = lines.iter().filter_map(|line| line.label()).map(|label| label.name()).collect();
+    // the name used as operand of every instruction, if any
+    let operands: Vec<Option<&str>> = program.lines.iter()
+        .map(|line| lines[line - 1].name_references().first().map(|token| token.name()))
+        .collect();
+    let mut warnings = Vec::new();
+    let mut warn = |index: usize, message: String| warnings.push(Warning { line: program.lines[index], message });
+
+    let reachable = reachable(instructions);
+    for (i, instr) in instructions.iter().enumerate() {
+        let is_variable = operands[i].is_some_and(|name| !labels.contains(name));
+        match instr.opcode {
+            Opcode::LDC if is_variable => warn(i, format!(
+                "LDC loads the address of variable `{}`, LDV loads its value", operands[i].unwrap_or_default())),
+            Opcode::JMP | Opcode::JMN | Opcode::CALL if is_variable => warn(i, format!(
+                "{} jumps to variable `{}`, which is not a label", isa.mnemonic(instr.opcode), operands[i].unwrap_or_default())),
+            Opcode::JMP | Opcode::JMN | Opcode::CALL if instr.arg as usize >= count => warn(i, format!(
+                "{} jumps to {:#x}, which is outside of the program", isa.mnemonic(instr.opcode), instr.arg)),
+            opcode if accesses_data(opcode) && (instr.arg as usize) < count && operands[i].is_some_and(|name| labels.contains(name)) =>
+                warn(i, format!("{} uses label `{}` as data, but it marks code",
+                                isa.mnemonic(opcode), operands[i].unwrap_or_default())),
+            _ => {}
+        }
+        if !reachable[i] {
+            // one warning for every run of unreachable instructions
+            if i == 0 || reachable[i - 1] {
+                let after = i.checked_sub(1)
+                    .map(|prev| instructions[prev].opcode)
+                    .filter(|opcode| matches!(opcode, Opcode::JMP | Opcode::HALT | Opcode::RET | Opcode::RETI))
+                    .map_or(String::new(), |opcode| format!(" after {}", isa.mnemonic(opcode)));
+                warn(i, format!("Unreachable code{}", after));
+            }
+        } else if i + 1 == count && successors(instr.opcode, instr.arg, i).contains(&count) {
+            warn(i, "Execution continues after the last instruction into data".to_owned());
+        }
+    }
+    let halts = instructions.iter().zip(&reachable).any(|(instr, reachable)| *reachable && instr.opcode == Opcode::HALT);
+    if count > 0 && !halts {
+        warn(0, "No HALT instruction can be reached".to_owned());
+    }
+    warnings.sort_by_key(|warning| warning.line);
+    Ok(warnings)
+}
+
+// instructions reachable from the first one
+fn reachable(instructions: &[Instruction]) -> Vec<bool> {
+    let mut reachable = vec![false; instructions.len()];
+    let mut pending = if instructions.is_empty() { Vec::new() } else { vec![0] };
+    while let Some(i) = pending.pop() {
+        if i >= instructions.len() || reachable[i] {
+            continue;
+        }
+        reachable[i] = true;
+        pending.extend(successors(instructions[i].opcode, instructions[i].arg, i));
+    }
+    reachable
+}
+
+fn successors(opcode: Opcode, arg: MimaAddress, index: usize) -> Vec<usize> {
+    match opcode {
+        Opcode::HALT | Opcode::RET | Opcode::RETI => Vec::new(),
+        Opcode::JMP => vec![arg as usize],
+        Opcode::JMN | Opcode::CALL => vec![arg as usize, index + 1],
+        _ => vec![index + 1]
+    }
+}
+
+fn accesses_data(opcode: Opcode) -> bool {
+    matches!(opcode, Opcode::LDV | Opcode::STV | Opcode::ADD | Opcode::AND | Opcode::OR | Opcode::XOR
+        | Opcode::EQL | Opcode::LDIV | Opcode::STIV | Opcode::SUB | Opcode::MUL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(source: &str) -> Vec<(usize, String)> {
+        lint(source, true, &InstructionSet::default()).unwrap().into_iter()
+            .map(|warning| (warning.line, warning.message))
+            .collect()
+    }
+
+    #[test]
+    fn accepts_clean_programs() {
+        assert!(messages("LDC 3\nSTV counter\nloop:\nLDV counter\nJMN end\nJMP loop\nend:\nHALT\n").is_empty());
+    }
+
+    #[test]
+    fn warns_about_addresses_used_as_values() {
+        assert_eq!(messages("LDC x\nHALT\n"),
+                   vec![(1, "LDC loads the address of variable `x`, LDV loads its value".to_owned())]);
+        assert_eq!(messages("start:\nLDV start\nHALT\n"),
+                   vec![(2, "LDV uses label `start` as data, but it marks code".to_owned())]);
+    }
+
+    #[test]
+    fn warns_about_jump_targets() {
+        assert_eq!(messages("JMN x\nHALT\n"),
+                   vec![(1, "JMN jumps to variable `x`, which is not a label".to_owned())]);
+        assert_eq!(messages("JMN 0x100\nHALT\n"),
+                   vec![(1, "JMN jumps to 0x100, which is outside of the program".to_owned())]);
+    }
+
+    #[test]
+    fn warns_about_control_flow() {
+        assert_eq!(messages("JMP end\nLDC 1\nLDC 2\nend:\nHALT\n"),
+                   vec![(2, "Unreachable code after JMP".to_owned())]);
+        assert_eq!(messages("LDC 1\n"), vec![
+            (1, "Execution continues after the last instruction into data".to_owned()),
+            (1, "No HALT instruction can be reached".to_owned())
+        ]);
+        assert_eq!(messages("loop:\nJMP loop\nHALT\n"), vec![
+            (2, "No HALT instruction can be reached".to_owned()),
+            (3, "Unreachable code after JMP".to_owned())
+        ]);
+    }
+}
@@ -0,0 +1,444 @@
+use crate::assembly::assemble;
+use crate::messages::{read_message, write_message};
+use crate::source::{scan, SourceLine, Token};
+use crate::symbols::SymbolTable;
+use mima_common::instructions::Isa;
+use mima_common::isa::InstructionSet;
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+const ERROR_PARSE: i64 = -32700;
+const ERROR_METHOD_NOT_FOUND: i64 = -32601;
+const ERROR_INVALID_PARAMS: i64 = -32602;
+// kinds defined by the protocol
+const COMPLETION_FUNCTION: u8 = 3;
+const COMPLETION_VARIABLE: u8 = 6;
+const COMPLETION_KEYWORD: u8 = 14;
+const SYMBOL_FUNCTION: u8 = 12;
+const SYMBOL_VARIABLE: u8 = 13;
+const SEVERITY_ERROR: u8 = 1;
+
+const DIRECTIVES: &[(&str, &str)] = &[
+    (".expect", "Checks `<target> <comparison> <value>` when the program halts"),
+    (".assert_at", "Checks `<target> <comparison> <value>` whenever execution reaches the location")
+];
+
+// serves the Language Server Protocol for assembly sources on stdin and stdout
+pub fn serve(isa: InstructionSet) -> Result<(), String> {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut server = LanguageServer { output: io::stdout(), isa, absolute: false, documents: HashMap::new() };
+    while let Some(message) = read_message(&mut input)? {
+        let message = match message {
+            Ok(message) => message,
+            Err(error) => {
+                write_message(&mut server.output, &json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": { "code": ERROR_PARSE, "message": error }
+                }))?;
+                continue;
+            }
+        };
+        if message["method"] == "exit" {
+            break;
+        }
+        server.handle(message)?;
+    }
+    Ok(())
+}
+
+struct LanguageServer<W: Write> {
+    output: W,
+    isa: InstructionSet,
+    absolute: bool,
+    // the content of all open documents by URI
+    documents: HashMap<String, String>
+}
+
+impl<W: Write> LanguageServer<W> {
+    fn handle(&mut self, message: Value) -> Result<(), String> {
+        let method = message["method"].as_str().unwrap_or_default().to_owned();
+        let params = &message["params"];
+        if message.get("id").is_none() {
+            return self.notification(&method, params);
+        }
+        let result = match method.as_str() {
+            "initialize" => self.initialize(params),
+            "shutdown" => Ok(Value::Null),
+            "textDocument/definition" => self.definition(params),
+            "textDocument/references" => self.references(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/completion" => self.completion(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            _ => Err((ERROR_METHOD_NOT_FOUND, format!("Unsupported method '{}'", method)))
+        };
+        let mut response = json!({ "jsonrpc": "2.0", "id": message["id"] });
+        match result {
+            Ok(result) => response["result"] = result,
+            Err((code, message)) => response["error"] = json!({ "code": code, "message": message })
+        }
+        write_message(&mut self.output, &response)
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Result<(), String> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_owned();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default().to_owned();
+                self.documents.insert(uri.clone(), text);
+                self.publish_diagnostics(&uri)
+            }
+            "textDocument/didChange" => {
+                // the whole document is sent on every change
+                if let Some(text) = params["contentChanges"].as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str()) {
+                    self.documents.insert(uri, text.to_owned());
+                }
+                Ok(())
+            }
+            "textDocument/didSave" => {
+                if let Some(text) = params["text"].as_str() {
+                    self.documents.insert(uri.clone(), text.to_owned());
+                }
+                self.publish_diagnostics(&uri)
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.send_notification("textDocument/publishDiagnostics", json!({ "uri": uri, "diagnostics": [] }))
+            }
+            _ => Ok(())
+        }
+    }
+
+    fn send_notification(&mut self, method: &str, params: Value) -> Result<(), String> {
+        write_message(&mut self.output, &json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    // initialization options: isa, isaFile and absolute, as for `mima asm`
+    fn initialize(&mut self, params: &Value) -> Result<Value, (i64, String)> {
+        let options = &params["initializationOptions"];
+        if let Some(path) = options["isaFile"].as_str() {
+            self.isa = InstructionSet::read_from(Path::new(path)).map_err(|e| (ERROR_INVALID_PARAMS, e))?;
+        } else if let Some(name) = options["isa"].as_str() {
+            self.isa = Isa::from_str(name)
+                .map(InstructionSet::from)
+                .map_err(|_e| (ERROR_INVALID_PARAMS, format!("Unknown instruction set '{}'", name)))?;
+        }
+        self.absolute = options["absolute"].as_bool().unwrap_or(false);
+        Ok(json!({
+            "capabilities": {
+                "textDocumentSync": { "openClose": true, "change": 1, "save": { "includeText": true } },
+                "definitionProvider": true,
+                "referencesProvider": true,
+                "hoverProvider": true,
+                "completionProvider": {},
+                "documentSymbolProvider": true
+            },
+            "serverInfo": { "name": "mima" }
+        }))
+    }
+
+    // assembler errors are reported for the line they name
+    fn publish_diagnostics(&mut self, uri: &str) -> Result<(), String> {
+        let diagnostics = match self.documents.get(uri).map(|text| assemble(text.clone(), self.absolute, &self.isa)) {
+            Some(Err(error)) => {
+                let (line, message) = error.strip_prefix("Line ")
+                    .and_then(|rest| rest.split_once(": "))
+                    .and_then(|(line, message)| line.parse::<usize>().ok().map(|line| (line, message.to_owned())))
+                    .unwrap_or((1, error.clone()));
+                let text = &self.documents[uri];
+                let length = text.lines().nth(line - 1).map_or(0, |l| l.chars().count());
+                vec![json!({
+                    "range": range(text, line, 0, length),
+                    "severity": SEVERITY_ERROR,
+                    "source": "mima",
+                    "message": message
+                })]
+            }
+            _ => Vec::new()
+        };
+        self.send_notification("textDocument/publishDiagnostics", json!({ "uri": uri, "diagnostics": diagnostics }))
+    }
+
+    // the uri, text and scanned lines of the document a request is about
+    fn document<'a>(&'a self, params: &'a Value) -> Result<(&'a str, &'a str, Vec<SourceLine>), (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        self.documents.get(uri)
+            .map(|text| (uri, text.as_str(), scan(text)))
+            .ok_or((ERROR_INVALID_PARAMS, format!("Unknown document '{}'", uri)))
+    }
+
+    fn definition(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, text, lines) = self.document(params)?;
+        let result = name_at(text, &lines, &params["position"])
+            .and_then(|name| definition(&lines, &name))
+            .map_or(Value::Null, |(line, token)| location(uri, text, line, token));
+        Ok(result)
+    }
+
+    fn references(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, text, lines) = self.document(params)?;
+        let include_declaration = params["context"]["includeDeclaration"].as_bool().unwrap_or(true);
+        let locations: Vec<Value> = match name_at(text, &lines, &params["position"]) {
+            Some(name) => occurrences(&lines, &name).into_iter()
+                .filter(|(line, token)| include_declaration || lines[line - 1].label() != Some(token))
+                .map(|(line, token)| location(uri, text, line, token))
+                .collect(),
+            None => Vec::new()
+        };
+        Ok(json!(locations))
+    }
+
+    fn hover(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, source, lines) = self.document(params)?;
+        let (line, token) = match token_at(source, &lines, &params["position"]) {
+            Some(found) => found,
+            None => return Ok(Value::Null)
+        };
+        let source_line = &lines[line - 1];
+        let text = if source_line.mnemonic() == Some(token) {
+            match self.isa.lookup(&token.text) {
+                Some(opcode) => {
+                    let spec = self.isa.spec(opcode).expect("looked up operations are part of the instruction set");
+                    format!("**{}**{}\n\n{}\n\nOpcode `{:#x}` ({} bits)",
+                            spec.mnemonic, if opcode.has_arg() { " a" } else { "" },
+                            opcode.description(), spec.code, spec.bits)
+                }
+                None => return Ok(Value::Null)
+            }
+        } else if let Some((_, description)) = DIRECTIVES.iter().find(|(name, _)| source_line.directive() == Some(token) && *name == token.text) {
+            (*description).to_owned()
+        } else if source_line.label() == Some(token) || source_line.name_references().contains(&token) {
+            let name = token.name();
+            let kind = if lines.iter().any(|l| l.label().is_some_and(|label| label.name() == name)) {
+                "label"
+            } else {
+                "variable"
+            };
+            match self.symbols(uri).and_then(|symbols| symbols.resolve(name)) {
+                Some(addr) => format!("{} `{}` at address `{:#x}`", kind, name, addr),
+                None => format!("{} `{}` (the program does not assemble)", kind, name)
+            }
+        } else {
+            return Ok(Value::Null);
+        };
+        Ok(json!({
+            "contents": { "kind": "markdown", "value": text },
+            "range": range(source, line, token.column, token.end())
+        }))
+    }
+
+    // mnemonics and directives at the start of a line, labels and variables after a mnemonic
+    fn completion(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (_, text, lines) = self.document(params)?;
+        let line = params["position"]["line"].as_u64().unwrap_or(0) as usize;
+        let character = char_column(text, line, params["position"]["character"].as_u64().unwrap_or(0) as usize);
+        let preceding = lines.get(line).map_or(0, |l| l.tokens.iter().filter(|token| token.end() < character).count());
+        let items: Vec<Value> = if preceding == 0 {
+            self.isa.opcodes().iter()
+                .map(|spec| json!({ "label": spec.mnemonic, "kind": COMPLETION_KEYWORD, "detail": spec.opcode.description() }))
+                .chain(DIRECTIVES.iter()
+                    .map(|(name, description)| json!({ "label": name, "kind": COMPLETION_KEYWORD, "detail": description })))
+                .collect()
+        } else {
+            let labels: BTreeSet<&str> = lines.iter().filter_map(|l| l.label()).map(|token| token.name()).collect();
+            let names: BTreeSet<&str> = lines.iter()
+                .flat_map(|l| l.name_references())
+                .map(|token| token.name())
+                .chain(labels.iter().copied())
+                .collect();
+            names.into_iter()
+                .map(|name| json!({
+                    "label": name,
+                    "kind": if labels.contains(name) { COMPLETION_FUNCTION } else { COMPLETION_VARIABLE }
+                }))
+                .collect()
+        };
+        Ok(json!(items))
+    }
+
+    // labels and template variables, the latter at their first use
+    fn document_symbols(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, text, lines) = self.document(params)?;
+        let symbols = self.symbols(uri);
+        let mut seen = BTreeSet::new();
+        let mut result = Vec::new();
+        for line in &lines {
+            let declared = line.label().map(|token| (token, SYMBOL_FUNCTION));
+            let used = line.name_references().into_iter().map(|token| (token, SYMBOL_VARIABLE));
+            for (token, kind) in declared.into_iter().chain(used) {
+                let name = token.name();
+                let is_label = lines.iter().any(|l| l.label().is_some_and(|label| label.name() == name));
+                if seen.contains(name) || (kind == SYMBOL_VARIABLE && is_label) {
+                    continue;
+                }
+                seen.insert(name.to_owned());
+                let mut symbol = json!({
+                    "name": name,
+                    "kind": kind,
+                    "range": range(text, line.number, token.column, token.end()),
+                    "selectionRange": range(text, line.number, token.column, token.column + name.chars().count())
+                });
+                if let Some(addr) = symbols.as_ref().and_then(|symbols| symbols.resolve(name)) {
+                    symbol["detail"] = json!(format!("{:#x}", addr));
+                }
+                result.push(symbol);
+            }
+        }
+        Ok(json!(result))
+    }
+
+    // the addresses assigned by the assembler, if the document assembles
+    fn symbols(&self, uri: &str) -> Option<SymbolTable> {
+        let text = self.documents.get(uri)?;
+        assemble(text.clone(), self.absolute, &self.isa).ok().map(|program| program.symbols)
+    }
+}
+
+fn token_at<'a>(text: &str, lines: &'a [SourceLine], position: &Value) -> Option<(usize, &'a Token)> {
+    let line = lines.get(position["line"].as_u64()? as usize)?;
+    let column = char_column(text, line.number - 1, position["character"].as_u64()? as usize);
+    line.token_at(column).map(|token| (line.number, token))
+}
+
+// the label or variable at the given position
+fn name_at(text: &str, lines: &[SourceLine], position: &Value) -> Option<String> {
+    let (line, token) = token_at(text, lines, position)?;
+    let source_line = &lines[line - 1];
+    if source_line.label() == Some(token) || source_line.name_references().contains(&token) {
+        Some(token.name().to_owned())
+    } else {
+        None
+    }
+}
+
+// the declaration of a label, or the first use of a template variable
+fn definition<'a>(lines: &'a [SourceLine], name: &str) -> Option<(usize, &'a Token)> {
+    lines.iter()
+        .find_map(|line| line.label().filter(|label| label.name() == name).map(|label| (line.number, label)))
+        .or_else(|| occurrences(lines, name).into_iter().next())
+}
+
+fn occurrences<'a>(lines: &'a [SourceLine], name: &str) -> Vec<(usize, &'a Token)> {
+    lines.iter()
+        .flat_map(|line| line.label().into_iter().chain(line.name_references()).map(move |token| (line.number, token)))
+        .filter(|(_, token)| token.name() == name)
+        .collect()
+}
+
+// protocol positions count lines from zero and characters in UTF-16 code units, while
+// the scanner counts lines from one and columns in chars
+fn range(text: &str, line: usize, start: usize, end: usize) -> Value {
+    let source_line = text.lines().nth(line - 1).unwrap_or_default();
+    json!({
+        "start": { "line": line - 1, "character": utf16_column(source_line, start) },
+        "end": { "line": line - 1, "character": utf16_column(source_line, end) }
+    })
+}
+
+fn location(uri: &str, text: &str, line: usize, token: &Token) -> Value {
+    json!({ "uri": uri, "range": range(text, line, token.column, token.column + token.name().chars().count()) })
+}
+
+fn utf16_column(source_line: &str, column: usize) -> usize {
+    source_line.chars().take(column).map(char::len_utf16).sum()
+}
+
+// the column of the char a protocol position on a zero based line points at
+fn char_column(text: &str, line: usize, character: usize) -> usize {
+    let source_line = text.lines().nth(line).unwrap_or_default();
+    let mut units = 0;
+    source_line.chars()
+        .take_while(|c| {
+            units += c.len_utf16();
+            units <= character
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///test.asm";
+
+    fn server(text: &str) -> LanguageServer<Vec<u8>> {
+        let mut server = LanguageServer {
+            output: Vec::new(),
+            isa: InstructionSet::default(),
+            absolute: false,
+            documents: HashMap::new()
+        };
+        server.handle(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": URI, "text": text } }
+        })).unwrap();
+        server
+    }
+
+    // the last message the server sent
+    fn last_message(server: &mut LanguageServer<Vec<u8>>) -> Value {
+        let output = std::mem::take(&mut server.output);
+        let mut input = output.as_slice();
+        let mut last = Value::Null;
+        while let Some(message) = read_message(&mut input).unwrap() {
+            last = message.unwrap();
+        }
+        last
+    }
+
+    fn request(server: &mut LanguageServer<Vec<u8>>, method: &str, line: usize, character: usize) -> Value {
+        server.handle(json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": { "textDocument": { "uri": URI }, "position": { "line": line, "character": character } }
+        })).unwrap();
+        last_message(server)["result"].take()
+    }
+
+    fn span(line: usize, start: usize, end: usize) -> Value {
+        json!({ "start": { "line": line, "character": start }, "end": { "line": line, "character": end } })
+    }
+
+    #[test]
+    fn reports_errors_at_their_line() {
+        let mut server = server("LDC 1\nFOO 2\n");
+        let diagnostics = &last_message(&mut server)["params"]["diagnostics"];
+        assert_eq!(diagnostics[0]["range"], span(1, 0, 5));
+        assert_eq!(diagnostics[0]["severity"], SEVERITY_ERROR);
+    }
+
+    // the emoji take two UTF-16 code units each
+    #[test]
+    fn positions_count_utf16_code_units() {
+        let mut server = server("😀😀:\nx:\n.assert_at 😀😀 x == 1\n");
+        assert_eq!(request(&mut server, "textDocument/definition", 2, 12),
+                   json!({ "uri": URI, "range": span(0, 0, 4) }));
+        assert_eq!(request(&mut server, "textDocument/references", 2, 16), json!([
+            { "uri": URI, "range": span(1, 0, 1) },
+            { "uri": URI, "range": span(2, 16, 17) }
+        ]));
+        assert_eq!(request(&mut server, "textDocument/hover", 2, 16)["range"], span(2, 16, 17));
+        assert_eq!(request(&mut server, "textDocument/definition", 2, 17), json!({ "uri": URI, "range": span(1, 0, 1) }));
+        assert_eq!(request(&mut server, "textDocument/definition", 2, 18), Value::Null);
+    }
+
+    #[test]
+    fn completes_mnemonics_and_names() {
+        let mut server = server("loop:\nLDV x\nJMP \n");
+        let mnemonics = request(&mut server, "textDocument/completion", 2, 0);
+        assert!(mnemonics.as_array().unwrap().iter().any(|item| item["label"] == "JMP"));
+        let names = request(&mut server, "textDocument/completion", 2, 4);
+        assert_eq!(names, json!([
+            { "label": "loop", "kind": COMPLETION_FUNCTION },
+            { "label": "x", "kind": COMPLETION_VARIABLE }
+        ]));
+    }
+}
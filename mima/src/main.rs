@@ -2,28 +2,84 @@ mod debugger;
 mod disassembly;
 mod assembly;
 mod cli;
+mod symbols;
+mod completion;
+mod memdump;
+mod report;
+mod testing;
+mod repl;
+mod tui;
+mod dap;
+mod gdb;
+mod messages;
+mod lsp;
+mod source;
+mod formatting;
+mod lint;
+mod profile;
+mod cfg;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use clap::Clap;
-use mima_common::types::{WriteMimaExt, ReadMimaExt, MimaValue};
+use mima_common::types::{WriteMimaExt, ReadMimaExt, MimaValue, MimaAddress};
 use std::fs::File;
 use std::io;
-use mima_common::runtime::Runtime;
-use crate::debugger::Debugger;
+use mima_common::runtime::{Runtime, Snapshot};
+use crate::debugger::{Debugger, Output};
+use crate::tui::Tui;
 use std::io::{Write, Read};
 use crate::disassembly::disassemble;
 use crate::assembly::assemble;
-use crate::cli::{MainOpts, SubCommand, AsmOpts, RunOpts};
+use crate::cli::{MainOpts, SubCommand, AsmOpts, RunOpts, CfgOpts, OutputFormat};
+use crate::report::RunReport;
+use crate::repl::Repl;
+use crate::symbols::SymbolTable;
+use crate::profile::{Profile, run_profiled, write_profile, read_profile};
+use crate::testing::DEFAULT_MAX_STEPS;
+use crate::cfg::render_cfg;
+use crate::memdump::{DumpFormat, AddressRange, read_memdump, write_memdump, check_range};
+use mima_common::instructions::{Isa, Opcode};
+use mima_common::isa::InstructionSet;
+use mima_common::interrupts::InterruptController;
+use mima_common::devices::{ConsoleDevice, stdin_input};
 
 
 fn main() -> Result<(), String> {
     let opts: MainOpts = MainOpts::parse();
-    let input = File::open(&opts.file())
-        .map_err(|e| format!("Could not open input file: {}", e.to_string()))?;
+    if let SubCommand::Repl(repl_opts) = &opts.cmd {
+        let runtime = Runtime::new()
+            .with_isa(load_isa(repl_opts.isa, &repl_opts.isa_file)?)
+            .with_status_flags(repl_opts.flags);
+        return Repl::new(runtime).run();
+    }
+    if let SubCommand::Dap = &opts.cmd {
+        return dap::serve();
+    }
+    if let SubCommand::Lsp(lsp_opts) = &opts.cmd {
+        return lsp::serve(load_isa(lsp_opts.isa, &lsp_opts.isa_file)?);
+    }
+    if let SubCommand::Fmt(fmt_opts) = &opts.cmd {
+        return formatting::format_files(&fmt_opts.files, fmt_opts.check);
+    }
+    let path = opts.file().expect("all other subcommands read an input file");
+    let mut input = File::open(path)
+        .map_err(|e| format!("Could not open input file: {}", e))?;
 
     match &opts.cmd {
         SubCommand::Asm(asm_opts) => run_asm(input, asm_opts),
-        SubCommand::Run(run_opts) => run_run(input, run_opts)
+        SubCommand::Run(run_opts) => run_run(input, run_opts),
+        SubCommand::Test(test_opts) => {
+            let mut content = String::new();
+            input.read_to_string(&mut content).map_err(|e| e.to_string())?;
+            testing::run_tests(path, &content, test_opts.format)
+        }
+        SubCommand::Lint(lint_opts) => {
+            let mut content = String::new();
+            input.read_to_string(&mut content).map_err(|e| e.to_string())?;
+            lint::lint_file(path, &content, lint_opts.absolute, &load_isa(lint_opts.isa, &lint_opts.isa_file)?)
+        }
+        SubCommand::Cfg(cfg_opts) => run_cfg(input, path, cfg_opts),
+        SubCommand::Repl(_) | SubCommand::Dap | SubCommand::Lsp(_) | SubCommand::Fmt(_) => Ok(())
     }?;
     Ok(())
 }
@@ -32,68 +88,263 @@ fn run_asm(mut input: File, opts: &AsmOpts) -> Result<(), String> {
     let output = || {
         if let Some(path) = &opts.output {
             File::create(path)
-                .map_err(|e| format!("Could not open output file: {}", e.to_string()))
-                .map(|f| Some(f))
+                .map_err(|e| format!("Could not open output file: {}", e))
+                .map(Some)
         } else {
             Ok(None)
         }
     };
+    let isa = load_isa(opts.isa, &opts.isa_file)?;
     if opts.disassemble {
-        let values = read_mima_file(&mut input)?;
-        let asm = disassemble(values)?;
+        let values = read_mima_file(&mut input, &isa)?;
+        let asm = disassemble(values, &isa)?;
         write_to_output(output()?.as_mut(), |w| w.write_all(asm.as_bytes()))?;
     } else {
         let mut content = String::new();
         input.read_to_string(&mut content).map_err(|e| e.to_string())?;
-        let instructions: Vec<MimaValue> = assemble(content, opts.absolute)?
+        let program = assemble(content, opts.absolute, &isa)?;
+        let instructions = program.instructions
             .iter()
-            .map(|i| MimaValue::from(i))
-            .collect();
-        write_mima_file(&mut output()?.unwrap(), instructions.as_slice())?;
+            .map(|i| isa.encode(i))
+            .collect::<Result<Vec<MimaValue>, String>>()?;
+        write_mima_file(&mut output()?.unwrap(), instructions.as_slice(), &isa)?;
+        if let Some(path) = &opts.symbols {
+            program.symbols.write_to(path)?;
+        }
     }
     Ok(())
 }
 
 fn run_run(mut input: File, opts: &RunOpts) -> Result<(), String> {
-    let instructions = read_mima_file(&mut input)?;
-    let mut runtime = Runtime::with_instructions(&instructions);
-    if opts.debug {
-        Debugger::from(&mut runtime).run()
+    // fails before running rather than when dumping
+    check_range(opts.memdump_format, opts.memdump_range)?;
+    let (mut runtime, instructions) = if opts.resume.is_some() {
+        let mut snapshot = Snapshot::read(io::BufReader::new(input))?;
+        let program = std::mem::take(&mut snapshot.program);
+        let mut runtime = snapshot.restore()?;
+        // restored devices are not connected to stdin yet, the debugger and GDB read their commands from it
+        if !opts.debug && !opts.gdb_stdio {
+            runtime.set_device_input(stdin_input);
+        }
+        (runtime, program)
+    } else {
+        let isa = load_isa(opts.isa, &opts.isa_file)?;
+        let instructions = read_memdump(&mut input, opts.input_format, &isa)?;
+        let runtime = Runtime::with_instructions(&instructions)
+            .with_isa(isa)
+            .with_status_flags(opts.flags);
+        (attach_devices(runtime, opts)?, instructions)
+    };
+    let symbols = match &opts.symbols {
+        Some(path) => SymbolTable::read_from(path, runtime.isa())?,
+        None => SymbolTable::default()
+    };
+    initialise_memory(&mut runtime, opts, &symbols)?;
+    runtime = runtime.with_assertions(symbols.assertions().to_vec());
+    let named_addresses = resolve_names(&opts.print, &symbols, runtime.isa())?;
+    let result = if opts.debug {
+        let mut debugger = Debugger::from(&mut runtime)
+            .with_image(instructions.clone())
+            .with_symbols(symbols)
+            .with_displays(opts.displays.clone())?;
+        if opts.tui {
+            Tui::new(debugger.with_output(Output::buffered())).run()
+        } else {
+            debugger.run()
+        }
+    } else if let Some(port) = opts.gdb_port {
+        gdb::serve_tcp(&mut runtime, port)
+    } else if opts.gdb_stdio {
+        gdb::serve_stdio(&mut runtime)
+    } else if opts.trace {
+        run_traced(&mut runtime)
+    } else if let Some(path) = &opts.profile {
+        let mut profile = Profile::new();
+        let result = run_profiled(&mut runtime, &mut profile, opts.max_steps.unwrap_or(DEFAULT_MAX_STEPS));
+        write_profile(path, &profile)?;
+        result
     } else {
         runtime.run()
-    }?;
-
-    let mut addresses = opts.abs_output.clone()
-        .unwrap_or_else(|| Vec::new());
-    addresses.extend(opts.rel_output.clone().unwrap_or_else(|| Vec::new()).iter()
-        .map(|addr| *addr + instructions.len() as u32 + 1));
-    addresses.iter()
-        .map(|addr| runtime.read_mem(*addr))
-        .for_each(|v| println!("{}", v));
+    };
 
+    let mut addresses: Vec<(Option<String>, MimaAddress)> = opts.abs_output.clone()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|addr| (None, addr))
+        .collect();
+    addresses.extend(opts.rel_output.clone().unwrap_or_default().iter()
+        .map(|addr| (None, *addr + instructions.len() as u32 + 1)));
+    addresses.extend(named_addresses);
+    match opts.output_format {
+        OutputFormat::Text => {
+            result?;
+            for (name, addr) in &addresses {
+                let val = runtime.read_mem(*addr);
+                match name {
+                    Some(name) => println!("{} = {}", name, val),
+                    None => println!("{}", val)
+                }
+            }
+            // the debugger reports failures as they happen
+            if !opts.debug {
+                runtime.assertion_failures().iter().for_each(|failure| eprintln!("{}", failure));
+            }
+        }
+        OutputFormat::Json => {
+            println!("{}", RunReport::new(&runtime, &addresses, &result).to_json());
+            result?;
+        }
+    }
 
     if let Some(ref path) = &opts.memdump {
-        create_memdump(path, &runtime)?;
+        create_memdump(path, &runtime, opts.memdump_format, opts.memdump_range)?;
+    }
+
+    match runtime.assertion_failures().len() {
+        0 => Ok(()),
+        failures => Err(format!("{} assertion(s) failed", failures))
+    }
+}
+
+// sets up the interrupt controller and memory mapped devices requested on the command line
+fn attach_devices(mut runtime: Runtime, opts: &RunOpts) -> Result<Runtime, String> {
+    if let Some(vector) = opts.interrupt_vector {
+        if !runtime.isa().supports(Opcode::EI) {
+            return Err("Interrupts can only be enabled with the EI instruction, \
+                        which requires the extended instruction set (--isa extended)".to_owned());
+        }
+        let max_address = runtime.isa().max_address();
+        let save_addr = opts.interrupt_save.unwrap_or(max_address - 2);
+        // IAR, accumulator and cause are saved to three consecutive addresses
+        if save_addr.checked_add(2).is_none_or(|last| last > max_address) {
+            return Err(format!("The interrupt save area at {:#x} does not fit into memory, \
+                                it must start at {:#x} or below", save_addr, max_address - 2));
+        }
+        let mut controller = InterruptController::new(runtime.isa().coerce_address(vector), save_addr);
+        if let Some(interval) = opts.timer {
+            if interval == 0 {
+                return Err("Timer interval must be at least 1".to_owned());
+            }
+            controller = controller.with_timer(interval);
+        }
+        runtime = runtime.with_interrupts(controller);
     }
+    if let Some(addr) = opts.console {
+        let mut console = ConsoleDevice::default();
+        // the debugger and GDB read their commands from stdin
+        if !opts.debug && !opts.gdb_stdio {
+            console = console.with_input(stdin_input());
+        }
+        runtime = runtime.with_device(addr, Box::new(console));
+    }
+    Ok(runtime)
+}
+
+// applies the memory assignments and file loads given on the command line
+fn initialise_memory(runtime: &mut Runtime, opts: &RunOpts, symbols: &SymbolTable) -> Result<(), String> {
+    for assignment in &opts.set {
+        let (target, value) = assignment.split_once('=')
+            .ok_or(format!("Invalid assignment '{}', expected ADDR=VALUE", assignment))?;
+        let addr = symbols.parse_addr(target, runtime.isa())
+            .map_err(|_e| format!("Unknown address or symbol '{}'", target))?;
+        let value = runtime.isa().parse_value(value)
+            .map_err(|_e| format!("Invalid value '{}' for {}", value, target))?;
+        runtime.write_mem(addr, value);
+    }
+    for load in &opts.load {
+        let (path, target) = load.rsplit_once('@')
+            .ok_or(format!("Invalid load '{}', expected FILE@ADDR", load))?;
+        let start = symbols.parse_addr(target, runtime.isa())
+            .map_err(|_e| format!("Unknown address or symbol '{}'", target))?;
+        let mut file = File::open(path)
+            .map_err(|e| format!("Could not open {}: {}", path, e))?;
+        let values = read_mima_file(&mut file, runtime.isa())?;
+        if start as usize + values.len() > runtime.isa().address_space() as usize {
+            return Err(format!("{} does not fit into memory at {:#x}", path, start));
+        }
+        for (i, value) in values.into_iter().enumerate() {
+            runtime.write_mem(start + i as u32, value);
+        }
+    }
+    Ok(())
+}
+
+// the addresses of the names given with --print
+fn resolve_names(names: &[String], symbols: &SymbolTable, isa: &InstructionSet)
+    -> Result<Vec<(Option<String>, MimaAddress)>, String> {
+    names.iter()
+        .map(|name| symbols.parse_addr(name, isa)
+            .map(|addr| (Some(name.clone()), addr))
+            .map_err(|_e| format!("Unknown symbol '{}'. The assembler's symbol file can be given with -s", name)))
+        .collect()
+}
+
+// assembly sources are assembled first, binaries are named with the symbol file if there is one
+fn run_cfg(mut input: File, path: &Path, opts: &CfgOpts) -> Result<(), String> {
+    let isa = load_isa(opts.isa, &opts.isa_file)?;
+    let (program, symbols) = if path.extension().is_some_and(|ext| ext == "asm") {
+        let mut content = String::new();
+        input.read_to_string(&mut content).map_err(|e| e.to_string())?;
+        let program = assemble(content, opts.absolute, &isa)?;
+        let values = program.instructions
+            .iter()
+            .map(|i| isa.encode(i))
+            .collect::<Result<Vec<MimaValue>, String>>()?;
+        (values, program.symbols)
+    } else {
+        let symbols = match &opts.symbols {
+            Some(path) => SymbolTable::read_from(path, &isa)?,
+            None => SymbolTable::default()
+        };
+        (read_memdump(&mut input, opts.input_format, &isa)?, symbols)
+    };
+    let profile = opts.profile.as_deref().map(|path| read_profile(path, &isa)).transpose()?;
+    print!("{}", render_cfg(&program, &isa, &symbols, profile.as_ref(), opts.format));
+    Ok(())
+}
 
+// runs the program, printing each instruction and its effect on the accumulator
+fn run_traced(runtime: &mut Runtime) -> Result<(), String> {
+    while !runtime.halt {
+        let addr = runtime.read_iar();
+        runtime.step()?;
+        let isa = runtime.isa();
+        let instr = isa.decode(runtime.read_ir())
+            .map_or("???".to_owned(), |i| isa.format(&i));
+        let accu = runtime.read_accu();
+        let flags = runtime.read_flags()
+            .map_or(String::new(), |f| format!(" {}", f));
+        eprintln!("{:#07x}: {:14} accu={} ({}){}", addr, instr, accu, isa.to_signed(accu), flags);
+    }
     Ok(())
+}
 
+// uses the instruction set file if given, otherwise the built-in profile
+fn load_isa(isa: Isa, file: &Option<PathBuf>) -> Result<InstructionSet, String> {
+    match file {
+        Some(path) => InstructionSet::read_from(path),
+        None => Ok(InstructionSet::from(isa))
+    }
 }
 
-fn read_mima_file(file: &mut File) -> Result<Vec<MimaValue>, String> {
-    file.read_all_mima_vals()
-        .map_err(|e| format!("Failed to parse mima file: {}", e.to_string()))
+fn read_mima_file(file: &mut File, isa: &InstructionSet) -> Result<Vec<MimaValue>, String> {
+    file.read_all_mima_vals_sized(isa.value_bytes())
+        .map_err(|e| format!("Failed to parse mima file: {}", e))
 }
 
-fn create_memdump(path: &PathBuf, runtime: &Runtime) -> Result<(), String> {
-    File::create(path)
-        .map_err(|e| format!("Could not open memdump file: {}", e.to_string()))
-        .and_then(|mut file| write_mima_file(&mut file, runtime.mem_iter().as_slice()))
+fn create_memdump(path: &PathBuf, runtime: &Runtime, format: DumpFormat, range: Option<AddressRange>)
+    -> Result<(), String> {
+    check_range(format, range)?;
+    let mut file = File::create(path)
+        .map(io::BufWriter::new)
+        .map_err(|e| format!("Could not open memdump file: {}", e))?;
+    write_memdump(&mut file, runtime, format, range)?;
+    file.flush().map_err(|e| format!("Could not write memory dump: {}", e))
 }
 
-fn write_mima_file(file: &mut File, vals: &[MimaValue]) -> Result<(), String> {
-    file.write_all_mima_vals(vals)
-        .map_err(|e| format!("Could not write mima file: {}", e.to_string()))
+fn write_mima_file(file: &mut File, vals: &[MimaValue], isa: &InstructionSet) -> Result<(), String> {
+    file.write_all_mima_vals_sized(vals, isa.value_bytes())
+        .map_err(|e| format!("Could not write mima file: {}", e))
 }
 
 fn write_to_output<O: FnOnce(&mut dyn Write) -> io::Result<()>>(file: Option<&mut File>, op: O)
@@ -102,5 +353,72 @@ fn write_to_output<O: FnOnce(&mut dyn Write) -> io::Result<()>>(file: Option<&mu
         op(file)
     } else {
         op(&mut io::stdout())
-    }).map_err(|e| format!("Could not write to output: {}", e.to_string()))
+    }).map_err(|e| format!("Could not write to output: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+
+    fn symbols() -> SymbolTable {
+        SymbolTable::from(HashMap::from([("x".to_owned(), 0x10), ("top".to_owned(), 0xfffff)]))
+    }
+
+    fn initialise(args: &[&str]) -> Result<Runtime, String> {
+        let opts = RunOpts::try_parse_from(["run"].iter().chain(args).chain(&["program.bin"])).unwrap();
+        let mut runtime = Runtime::new();
+        initialise_memory(&mut runtime, &opts, &symbols()).map(|_| runtime)
+    }
+
+    // a binary with the values 1 and 2 that is removed when dropped
+    struct TempBinary(PathBuf);
+
+    impl TempBinary {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("mima-{}-{}.bin", name, std::process::id()));
+            fs::write(&path, [0, 0, 1, 0, 0, 2]).unwrap();
+            TempBinary(path)
+        }
+
+        fn load_at(&self, target: &str) -> String {
+            format!("{}@{}", self.0.display(), target)
+        }
+    }
+
+    impl Drop for TempBinary {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn sets_values_at_labels_and_addresses() {
+        let runtime = initialise(&["--set", "x=5", "--set", "0x20=-1"]).unwrap();
+        assert_eq!((runtime.read_mem(0x10), runtime.read_mem(0x20)), (5, 0xffffff));
+        assert_eq!(initialise(&["--set", "y=5"]).err().unwrap(), "Unknown address or symbol 'y'");
+        assert_eq!(initialise(&["--set", "x"]).err().unwrap(), "Invalid assignment 'x', expected ADDR=VALUE");
+        assert_eq!(initialise(&["--set", "x=five"]).err().unwrap(), "Invalid value 'five' for x");
+    }
+
+    #[test]
+    fn loads_files_that_fit_into_memory() {
+        let binary = TempBinary::new("load");
+        let runtime = initialise(&["--load", &binary.load_at("x")]).unwrap();
+        assert_eq!((runtime.read_mem(0x10), runtime.read_mem(0x11)), (1, 2));
+        let runtime = initialise(&["--load", &binary.load_at("0xffffe")]).unwrap();
+        assert_eq!(runtime.read_mem(0xfffff), 2);
+        let error = initialise(&["--load", &binary.load_at("top")]).err().unwrap();
+        assert!(error.ends_with("does not fit into memory at 0xfffff"), "{}", error);
+    }
+
+    #[test]
+    fn resolves_printed_names() {
+        let names = vec!["x".to_owned(), "0x5".to_owned()];
+        assert_eq!(resolve_names(&names, &symbols(), &InstructionSet::default()),
+                   Ok(vec![(Some("x".to_owned()), 0x10), (Some("0x5".to_owned()), 5)]));
+        let error = resolve_names(&["missing".to_owned()], &symbols(), &InstructionSet::default());
+        assert_eq!(error.err().unwrap(), "Unknown symbol 'missing'. The assembler's symbol file can be given with -s");
+    }
 }
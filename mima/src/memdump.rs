@@ -0,0 +1,355 @@
+use mima_common::types::{MimaAddress, MimaValue, ReadMimaExt, WriteMimaExt, parse_mima_number};
+use mima_common::runtime::Runtime;
+use mima_common::isa::InstructionSet;
+use strum_macros::{EnumString, EnumVariantNames};
+use serde::{Serialize, Deserialize};
+use std::io::{Read, Write, BufRead, BufReader};
+use std::str::FromStr;
+
+pub const HEXDUMP_ROW_LENGTH: usize = 8;
+// number of values per data record in Intel HEX-style dumps
+const IHEX_RECORD_LENGTH: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, EnumVariantNames)]
+#[strum(serialize_all = "lowercase")]
+pub enum DumpFormat {
+    // values in the binary format used for programs
+    Raw,
+    // Intel HEX records. Record addresses count values, not bytes
+    Ihex,
+    // rows of hex values followed by their characters
+    Hexdump,
+    // one line per value: address, unsigned, signed, instruction
+    Csv,
+    Json
+}
+
+// addresses from start up to (excluding) end, written as START..END
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressRange {
+    pub start: MimaAddress,
+    pub end: MimaAddress
+}
+
+impl FromStr for AddressRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s.split_once("..")
+            .ok_or(format!("Invalid range '{}', expected START..END", s))?;
+        let parse = |num: &str| parse_mima_number(num)
+            .map_err(|_e| format!("Invalid address '{}' in range", num));
+        let range = AddressRange { start: parse(start)?, end: parse(end)? };
+        if range.start > range.end {
+            return Err(format!("Range start {:#x} is after its end {:#x}", range.start, range.end));
+        }
+        Ok(range)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonDump {
+    #[serde(default)]
+    isa: String,
+    values: Vec<JsonEntry>
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonEntry {
+    address: MimaAddress,
+    unsigned: MimaValue,
+    #[serde(default)]
+    signed: i64,
+    #[serde(default)]
+    instruction: Option<String>
+}
+
+// raw dumps have no addresses and are loaded at address 0, so they must start there to be read back
+pub fn check_range(format: DumpFormat, range: Option<AddressRange>) -> Result<(), String> {
+    match range {
+        Some(range) if format == DumpFormat::Raw && range.start != 0 =>
+            Err(format!("Raw memory dumps cannot start at {:#x}, use a format with addresses for this range", range.start)),
+        _ => Ok(())
+    }
+}
+
+// writes the values in the given range (by default all memory in use) in the given format
+pub fn write_memdump(
+    mut output: &mut dyn Write,
+    runtime: &Runtime,
+    format: DumpFormat,
+    range: Option<AddressRange>
+) -> Result<(), String> {
+    let isa = runtime.isa();
+    let range = range.unwrap_or(AddressRange { start: 0, end: runtime.mem_iter().len() as MimaAddress });
+    let values: Vec<(MimaAddress, MimaValue)> = (range.start..range.end.min(isa.address_space()))
+        .map(|addr| (addr, runtime.read_mem(addr)))
+        .collect();
+    let to_err = |e: std::io::Error| format!("Could not write memory dump: {}", e);
+    match format {
+        DumpFormat::Raw => {
+            let raw: Vec<MimaValue> = values.iter().map(|(_, val)| *val).collect();
+            output.write_all_mima_vals_sized(&raw, isa.value_bytes()).map_err(to_err)
+        }
+        DumpFormat::Ihex => write_ihex(output, &values, isa).map_err(to_err),
+        DumpFormat::Hexdump => hexdump_rows(&values, isa).iter()
+            .try_for_each(|row| writeln!(output, "{}", row))
+            .map_err(to_err),
+        DumpFormat::Csv => {
+            writeln!(output, "address,unsigned,signed,instruction").map_err(to_err)?;
+            values.iter()
+                .try_for_each(|(addr, val)| writeln!(output, "{:#07x},{},{},{}", addr, val, isa.to_signed(*val),
+                                                     isa.decode(*val).map_or(String::new(), |i| isa.format(&i))))
+                .map_err(to_err)
+        }
+        DumpFormat::Json => {
+            let dump = JsonDump {
+                isa: isa.name.clone(),
+                values: values.iter()
+                    .map(|(addr, val)| JsonEntry {
+                        address: *addr,
+                        unsigned: *val,
+                        signed: isa.to_signed(*val),
+                        instruction: isa.decode(*val).ok().map(|i| isa.format(&i))
+                    })
+                    .collect()
+            };
+            serde_json::to_writer_pretty(output, &dump)
+                .map_err(|e| format!("Could not write memory dump: {}", e))
+        }
+    }
+}
+
+// reads a memory dump and returns the memory contents starting at address 0
+pub fn read_memdump(mut input: &mut dyn Read, format: DumpFormat, isa: &InstructionSet) -> Result<Vec<MimaValue>, String> {
+    if format == DumpFormat::Raw {
+        return input.read_all_mima_vals_sized(isa.value_bytes())
+            .map_err(|e| format!("Failed to parse mima file: {}", e));
+    }
+    let entries = match format {
+        DumpFormat::Ihex => read_ihex(input, isa),
+        DumpFormat::Hexdump => read_hexdump(input),
+        DumpFormat::Csv => read_csv(input),
+        _ => serde_json::from_reader(input)
+            .map(|dump: JsonDump| dump.values.iter().map(|e| (e.address, e.unsigned)).collect())
+            .map_err(|e| e.to_string())
+    }.map_err(|e| format!("Failed to parse memory dump: {}", e))?;
+
+    let mut memory = Vec::new();
+    for (addr, val) in entries {
+        if addr >= isa.address_space() {
+            return Err(format!("Address {:#x} in memory dump is out of range", addr));
+        }
+        let index = addr as usize;
+        if index >= memory.len() {
+            memory.resize(index + 1, 0);
+        }
+        memory[index] = isa.coerce_value(val);
+    }
+    Ok(memory)
+}
+
+// formats values as rows of hex numbers and their characters
+pub fn hexdump_rows(values: &[(MimaAddress, MimaValue)], isa: &InstructionSet) -> Vec<String> {
+    let digits = isa.value_bytes() as usize * 2;
+    values.chunks(HEXDUMP_ROW_LENGTH)
+        .map(|row| {
+            let hex: Vec<String> = row.iter()
+                .map(|(_, val)| format!("{:0digits$x}", val, digits = digits))
+                .collect();
+            let chars: String = row.iter()
+                .map(|(_, val)| as_char(*val))
+                .collect();
+            format!("{:#07x}: {:width$} |{}|", row[0].0, hex.join(" "), chars,
+                    width = HEXDUMP_ROW_LENGTH * (digits + 1) - 1)
+        })
+        .collect()
+}
+
+pub fn as_char(val: MimaValue) -> char {
+    let c = (val & 0xff) as u8 as char;
+    if c.is_ascii_graphic() || c == ' ' { c } else { '.' }
+}
+
+fn write_ihex(output: &mut dyn Write, values: &[(MimaAddress, MimaValue)], isa: &InstructionSet) -> std::io::Result<()> {
+    let mut segment = 0;
+    let mut records = Vec::<Vec<(MimaAddress, MimaValue)>>::new();
+    for (addr, val) in values {
+        match records.last_mut() {
+            Some(record) if record[0].0 / IHEX_RECORD_LENGTH == addr / IHEX_RECORD_LENGTH => record.push((*addr, *val)),
+            _ => records.push(vec![(*addr, *val)])
+        }
+    }
+    for record in records {
+        let addr = record[0].0;
+        if addr >> 16 != segment {
+            segment = addr >> 16;
+            writeln!(output, "{}", ihex_record(0, 4, &[(segment >> 8) as u8, segment as u8]))?;
+        }
+        let data: Vec<u8> = record.iter()
+            .flat_map(|(_, val)| val.to_be_bytes()[4 - isa.value_bytes() as usize..].to_vec())
+            .collect();
+        writeln!(output, "{}", ihex_record(addr as u16, 0, &data))?;
+    }
+    writeln!(output, "{}", ihex_record(0, 1, &[]))
+}
+
+fn ihex_record(addr: u16, kind: u8, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8, (addr >> 8) as u8, addr as u8, kind];
+    bytes.extend_from_slice(data);
+    let checksum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg();
+    bytes.push(checksum);
+    let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!(":{}", hex)
+}
+
+fn read_ihex(input: &mut dyn Read, isa: &InstructionSet) -> Result<Vec<(MimaAddress, MimaValue)>, String> {
+    let value_bytes = isa.value_bytes() as usize;
+    let mut segment = 0;
+    let mut entries = Vec::new();
+    for (line_num, line) in BufReader::new(input).lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let err = |msg: &str| format!("Line {}: {}", line_num + 1, msg);
+        let hex = line.strip_prefix(':').ok_or_else(|| err("Record must start with ':'"))?;
+        let bytes = (0..hex.len()).step_by(2)
+            .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| err("Invalid hex digits"))?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(err("Invalid record length"));
+        }
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(err("Checksum mismatch"));
+        }
+        let addr = ((bytes[1] as u32) << 8) | bytes[2] as u32;
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            0 => {
+                if data.len() % value_bytes != 0 {
+                    return Err(err("Data does not consist of whole values"));
+                }
+                for (i, chunk) in data.chunks(value_bytes).enumerate() {
+                    let val = chunk.iter().fold(0, |val, b| (val << 8) | *b as MimaValue);
+                    let addr = (segment << 16 | addr).checked_add(i as u32)
+                        .ok_or_else(|| err("Address is out of range"))?;
+                    entries.push((addr, val));
+                }
+            }
+            1 => break,
+            4 if data.len() == 2 => segment = ((data[0] as u32) << 8) | data[1] as u32,
+            _ => return Err(err("Unsupported record type"))
+        }
+    }
+    Ok(entries)
+}
+
+fn read_hexdump(input: &mut dyn Read) -> Result<Vec<(MimaAddress, MimaValue)>, String> {
+    let mut entries = Vec::new();
+    for (line_num, line) in BufReader::new(input).lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let err = || format!("Line {}: Expected '<address>: <values> |<chars>|'", line_num + 1);
+        let (addr, rest) = line.split_once(':').ok_or_else(err)?;
+        let start = parse_mima_number(addr.trim()).map_err(|_e| err())?;
+        let hex = rest.split('|').next().unwrap_or("");
+        for (i, val) in hex.split_whitespace().enumerate() {
+            let val = MimaValue::from_str_radix(val, 16).map_err(|_e| err())?;
+            entries.push((start.checked_add(i as u32).ok_or_else(err)?, val));
+        }
+    }
+    Ok(entries)
+}
+
+fn read_csv(input: &mut dyn Read) -> Result<Vec<(MimaAddress, MimaValue)>, String> {
+    let mut entries = Vec::new();
+    for (line_num, line) in BufReader::new(input).lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() || line.starts_with("address") {
+            continue;
+        }
+        let err = || format!("Line {}: Expected '<address>,<value>,...'", line_num + 1);
+        let mut columns = line.split(',');
+        let addr = columns.next().and_then(|a| parse_mima_number(a.trim()).ok()).ok_or_else(err)?;
+        let val = columns.next().and_then(|v| parse_mima_number(v.trim()).ok()).ok_or_else(err)?;
+        entries.push((addr, val));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATS: [DumpFormat; 5] = [DumpFormat::Raw, DumpFormat::Ihex, DumpFormat::Hexdump, DumpFormat::Csv, DumpFormat::Json];
+
+    fn dump(runtime: &Runtime, format: DumpFormat, range: Option<AddressRange>) -> Vec<u8> {
+        let mut output = Vec::new();
+        write_memdump(&mut output, runtime, format, range).unwrap();
+        output
+    }
+
+    #[test]
+    fn round_trips_every_format() {
+        let memory = vec![0x100005, 0xffffff, 0x123456, 0, 42, 0x41, 0xf00000, 7, 9, 0x800000];
+        let runtime = Runtime::with_memory(memory.clone());
+        for format in FORMATS {
+            let output = dump(&runtime, format, None);
+            let read = read_memdump(&mut output.as_slice(), format, runtime.isa()).unwrap();
+            assert_eq!(read, memory, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn keeps_addresses_of_ranges() {
+        let mut runtime = Runtime::new();
+        runtime.write_mem(0x1fffe, 1);
+        runtime.write_mem(0x20001, 2);
+        let range = AddressRange { start: 0x1fffe, end: 0x20002 };
+        for format in &FORMATS[1..] {
+            let output = dump(&runtime, *format, Some(range));
+            let read = read_memdump(&mut output.as_slice(), *format, runtime.isa()).unwrap();
+            assert_eq!(read.len(), 0x20002, "{:?}", format);
+            assert_eq!((read[0x1fffe], read[0x1ffff], read[0x20001]), (1, 0, 2), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn rejects_raw_ranges_not_starting_at_zero() {
+        let range = |start| Some(AddressRange { start, end: 10 });
+        assert!(check_range(DumpFormat::Raw, range(2)).is_err());
+        assert!(check_range(DumpFormat::Raw, range(0)).is_ok());
+        assert!(check_range(DumpFormat::Ihex, range(2)).is_ok());
+        assert!(check_range(DumpFormat::Raw, None).is_ok());
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!("0x10..0x20".parse(), Ok(AddressRange { start: 0x10, end: 0x20 }));
+        assert!("5..3".parse::<AddressRange>().is_err());
+        assert!("5".parse::<AddressRange>().is_err());
+    }
+
+    #[test]
+    fn rejects_corrupt_ihex() {
+        let isa = InstructionSet::default();
+        let mut checksum = ":03000000000001FD\n".as_bytes();
+        assert!(read_memdump(&mut checksum, DumpFormat::Ihex, &isa).unwrap_err().contains("Checksum"));
+        let overflow = ":02000004FFFFFC\n:06FFFF00000001000002F9\n:00000001FF\n";
+        assert!(read_memdump(&mut overflow.as_bytes(), DumpFormat::Ihex, &isa).unwrap_err().contains("out of range"));
+    }
+
+    #[test]
+    fn rejects_hexdump_addresses_out_of_range() {
+        let isa = InstructionSet::default();
+        let mut dump = "0xffffffff: 000001 000002 |..|\n".as_bytes();
+        assert!(read_memdump(&mut dump, DumpFormat::Hexdump, &isa).is_err());
+        let mut dump = "0xfffff: 000001 000002 |..|\n".as_bytes();
+        assert!(read_memdump(&mut dump, DumpFormat::Hexdump, &isa).unwrap_err().contains("out of range"));
+    }
+}
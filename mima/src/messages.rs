@@ -0,0 +1,65 @@
+use serde_json::Value;
+use std::io::{BufRead, Write};
+
+// larger messages are rejected instead of allocating whatever the header asks for
+const MAX_CONTENT_LENGTH: usize = 1 << 24;
+
+// JSON messages preceded by a `Content-Length` header, as used by the debug adapter and language server protocols.
+// the outer error means the stream can not be read any further, the inner one that the body is not valid JSON
+pub fn read_message(input: &mut impl BufRead) -> Result<Option<Result<Value, String>>, String> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).map_err(|e| e.to_string())? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>().map_err(|e| e.to_string())?);
+        }
+    }
+    let length = length.ok_or("Message without Content-Length header")?;
+    if length > MAX_CONTENT_LENGTH {
+        return Err(format!("Message of {} bytes exceeds the limit of {} bytes", length, MAX_CONTENT_LENGTH));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).map_err(|e| e.to_string())?;
+    Ok(Some(serde_json::from_slice(&body).map_err(|e| format!("Invalid message: {}", e))))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Value) -> Result<(), String> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+        .and_then(|_| output.flush())
+        .map_err(|e| format!("Could not write message: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(input: &str) -> Result<Option<Result<Value, String>>, String> {
+        read_message(&mut input.as_bytes())
+    }
+
+    #[test]
+    fn reads_written_messages() {
+        let mut output = Vec::new();
+        write_message(&mut output, &serde_json::json!({ "seq": 1 })).unwrap();
+        assert_eq!(String::from_utf8(output.clone()).unwrap(), "Content-Length: 9\r\n\r\n{\"seq\":1}");
+        assert_eq!(read_message(&mut output.as_slice()), Ok(Some(Ok(serde_json::json!({ "seq": 1 })))));
+        assert_eq!(read(""), Ok(None));
+    }
+
+    #[test]
+    fn rejects_invalid_messages() {
+        assert_eq!(read("Content-Length: 3\r\n\r\n{}}").unwrap().unwrap().err().unwrap(),
+                   "Invalid message: trailing characters at line 1 column 3");
+        assert_eq!(read("\r\n{}").err().unwrap(), "Message without Content-Length header");
+        assert_eq!(read("Content-Length: 99999999999\r\n\r\n").err().unwrap(),
+                   "Message of 99999999999 bytes exceeds the limit of 16777216 bytes");
+    }
+}
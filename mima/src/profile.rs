@@ -0,0 +1,75 @@
+use mima_common::runtime::Runtime;
+use mima_common::types::{MimaAddress, parse_mima_number};
+use mima_common::isa::InstructionSet;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+// how often the instruction at each address was executed
+pub type Profile = BTreeMap<MimaAddress, u64>;
+
+pub fn run_profiled(runtime: &mut Runtime, profile: &mut Profile, max_steps: u64) -> Result<(), String> {
+    while !runtime.halt {
+        if runtime.read_steps() >= max_steps {
+            return Err(format!("Program did not halt within {} steps", max_steps));
+        }
+        *profile.entry(runtime.read_iar()).or_insert(0) += 1;
+        runtime.step()?;
+    }
+    Ok(())
+}
+
+// profiles are CSV files with an `address,count` line for every executed instruction
+pub fn write_profile(path: &Path, profile: &Profile) -> Result<(), String> {
+    let mut content = "address,count\n".to_owned();
+    for (addr, count) in profile {
+        content.push_str(&format!("{:#07x},{}\n", addr, count));
+    }
+    fs::write(path, content).map_err(|e| format!("Could not write profile: {}", e))
+}
+
+pub fn read_profile(path: &Path, isa: &InstructionSet) -> Result<Profile, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read profile: {}", e))?;
+    parse_profile(&content, isa)
+}
+
+// addresses outside of the address space of the instruction set are rejected
+fn parse_profile(content: &str, isa: &InstructionSet) -> Result<Profile, String> {
+    let mut profile = Profile::new();
+    for (line_num, line) in content.lines().enumerate().skip(1) {
+        let entry = line.split_once(',')
+            .and_then(|(addr, count)| Some((parse_mima_number(addr.trim()).ok()?, count.trim().parse::<u64>().ok()?)))
+            .filter(|(addr, _)| *addr <= isa.max_address())
+            .ok_or(format!("Profile line {}: Expected '<address>,<count>'", line_num + 1))?;
+        profile.insert(entry.0, entry.1);
+    }
+    Ok(profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mima_common::instructions::{Instruction, Opcode};
+
+    #[test]
+    fn counts_executed_instructions_up_to_the_limit() {
+        let isa = InstructionSet::default();
+        let program = [Instruction { opcode: Opcode::LDC, arg: 1 }, Instruction { opcode: Opcode::JMP, arg: 0 }];
+        let memory = program.iter().map(|instr| isa.encode(instr).unwrap()).collect();
+        let mut runtime = Runtime::with_memory(memory);
+        let mut profile = Profile::new();
+        assert_eq!(run_profiled(&mut runtime, &mut profile, 5), Err("Program did not halt within 5 steps".to_owned()));
+        assert_eq!(profile, Profile::from([(0, 3), (1, 2)]));
+    }
+
+    #[test]
+    fn rejects_addresses_outside_of_memory() {
+        let isa = InstructionSet::default();
+        assert_eq!(parse_profile("address,count\n0x00005,3\n", &isa), Ok(Profile::from([(5, 3)])));
+        assert!(parse_profile("address,count\n0x100005,3\n", &isa).is_err());
+        let mut wide = InstructionSet::default();
+        wide.address_bits = 24;
+        assert_eq!(parse_profile("address,count\n0x100005,3\n", &wide), Ok(Profile::from([(0x100005, 3)])));
+    }
+}
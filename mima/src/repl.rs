@@ -0,0 +1,298 @@
+use mima_common::runtime::Runtime;
+use mima_common::types::{MimaAddress, MimaValue, parse_mima_number};
+use mima_common::instructions::{Instruction, Opcode};
+use mima_common::isa::InstructionSet;
+use crate::assembly::assemble;
+use crate::memdump::hexdump_rows;
+use rustyline::Editor;
+use rustyline::config::Configurer;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+// first address used for variables, leaving room for the instructions entered before
+const VARIABLES_START: MimaAddress = 0x100;
+
+const HELP: &str = "\
+Enter an instruction to write it to the current IAR and execute it, e.g. `LDC 5` or `STV x`.
+Names used as arguments are variables that are assigned an address after the program
+(starting at 0x100), `name:` binds a name to the current IAR. Jumps only accept bound names.
+:state              show accumulator and IAR
+:vars               list all names and their values
+:mem <addr> [count] hexdump of count values (default 16)
+:set <name> <value> write a value to a variable or address
+:reset              clear memory, registers and names
+:help               show this message
+:quit               leave the repl";
+
+// assembles and executes one instruction at a time
+pub struct Repl {
+    runtime: Runtime,
+    editor: Editor<()>,
+    names: BTreeMap<String, MimaAddress>,
+    variables: BTreeSet<MimaAddress>,
+    // the address after the highest instruction entered so far
+    program_end: MimaAddress
+}
+
+impl Repl {
+    pub fn new(runtime: Runtime) -> Self {
+        let mut editor = Editor::<()>::new();
+        editor.set_auto_add_history(true);
+        Repl { runtime, editor, names: BTreeMap::new(), variables: BTreeSet::new(), program_end: 0 }
+    }
+
+    pub fn run(&mut self) -> Result<(), String> {
+        println!("mima repl ({}). Type :help for help", self.runtime.isa().name);
+        loop {
+            let prompt = format!("{:#07x}> ", self.runtime.read_iar());
+            let line = match self.editor.readline(&prompt) {
+                Ok(line) => line,
+                Err(rustyline::error::ReadlineError::Eof) => return Ok(()),
+                Err(rustyline::error::ReadlineError::Interrupted) => continue,
+                Err(error) => return Err(error.to_string())
+            };
+            let line = line.split(';').next().unwrap_or("").trim().to_owned();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(command) = line.strip_prefix(':') {
+                if !self.execute_command(command) {
+                    return Ok(());
+                }
+            } else {
+                match self.execute_line(&line) {
+                    Ok(effects) => println!("{}", effects),
+                    Err(error) => eprintln!("{}", error)
+                }
+            }
+        }
+    }
+
+    // returns false if the repl should be left
+    fn execute_command(&mut self, command: &str) -> bool {
+        let args: Vec<&str> = command.split_whitespace().collect();
+        match args.as_slice() {
+            ["state"] => self.print_state(),
+            ["vars"] => self.print_vars(),
+            ["mem", addr] => self.print_mem(addr, "16"),
+            ["mem", addr, count] => self.print_mem(addr, count),
+            ["set", name, value] => self.set(name, value),
+            ["reset"] => {
+                self.runtime.reset(Vec::new());
+                self.names.clear();
+                self.variables.clear();
+                self.program_end = 0;
+                println!("Machine reset");
+            }
+            ["help"] => println!("{}", HELP),
+            ["quit"] | ["q"] => return false,
+            _ => eprintln!("Unknown command. Type :help for help")
+        }
+        true
+    }
+
+    // assembles an instruction or label definition and executes it at the current IAR,
+    // returning a description of its effects
+    fn execute_line(&mut self, line: &str) -> Result<String, String> {
+        if let Some(label) = line.strip_suffix(':') {
+            let iar = self.runtime.read_iar();
+            self.names.insert(label.trim().to_owned(), iar);
+            return Ok(format!("{} = {:#07x}", label.trim(), iar));
+        }
+        if self.runtime.halt {
+            return Err("The machine is halted. Type :reset to start over".to_owned());
+        }
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let resolved = match tokens.as_slice() {
+            [opcode] => (*opcode).to_owned(),
+            [opcode, arg] => {
+                // jump targets have to be labels, a new variable would be jumped into
+                let jump = matches!(Opcode::from_str(&opcode.to_uppercase()), Ok(Opcode::JMP | Opcode::JMN | Opcode::CALL));
+                format!("{} {:#x}", opcode, self.address_of(arg, !jump)?)
+            }
+            _ => return Err("Expected a single instruction".to_owned())
+        };
+        let program = assemble(resolved, true, self.runtime.isa())?;
+        let instr = program.instructions[0];
+        let encoded = self.runtime.isa().encode(&instr)?;
+
+        let addr = self.runtime.read_iar();
+        if self.variables.contains(&addr) {
+            return Err(format!("The program has reached the variable at {:#x}. Type :reset to start over", addr));
+        }
+        self.runtime.write_mem(addr, encoded);
+        self.program_end = self.program_end.max(addr + 1);
+        let accu = self.runtime.read_accu();
+        let written = self.written_address(&instr).map(|addr| (addr, self.runtime.read_mem(addr)));
+        self.runtime.step()?;
+        Ok(self.effects(&self.runtime.isa().format(&instr), accu, written))
+    }
+
+    // resolves a constant, numeric address or name, allocating a new variable after the program
+    // for unknown names if allowed
+    fn address_of(&mut self, arg: &str, allocate: bool) -> Result<MimaAddress, String> {
+        let isa = self.runtime.isa();
+        if let Ok(addr) = parse_mima_number(arg) {
+            return Ok(isa.coerce_address(addr));
+        }
+        if arg.starts_with('-') {
+            return match arg.parse::<i64>() {
+                Ok(num) if num >= -(1i64 << (isa.address_bits - 1)) => Ok(isa.coerce_address(num as MimaAddress)),
+                _ => Err(format!("Constant {} does not fit into {} bits", arg, isa.address_bits))
+            };
+        }
+        if let Some(addr) = self.names.get(arg) {
+            return Ok(*addr);
+        }
+        if !allocate {
+            return Err(format!("Unknown label '{}'. Define it with `{}:` first", arg, arg));
+        }
+        // the instruction being entered is not part of program_end yet
+        let addr = self.variables.last().map_or(VARIABLES_START, |last| last + 1)
+            .max(self.program_end)
+            .max(self.runtime.read_iar() + 1);
+        if addr > isa.max_address() {
+            return Err("No more space for variables".to_owned());
+        }
+        self.names.insert(arg.to_owned(), addr);
+        self.variables.insert(addr);
+        Ok(addr)
+    }
+
+    // the address an instruction stores a value to, if any
+    fn written_address(&self, instr: &Instruction) -> Option<MimaAddress> {
+        let isa = self.runtime.isa();
+        match instr.opcode {
+            Opcode::STV => Some(instr.arg),
+            Opcode::STIV => Some(isa.coerce_address(self.runtime.read_mem(instr.arg))),
+            Opcode::CALL => Some(isa.coerce_address(self.runtime.read_sp().wrapping_sub(1))),
+            _ => None
+        }
+    }
+
+    fn effects(&self, instr: &str, old_accu: MimaValue, written: Option<(MimaAddress, MimaValue)>) -> String {
+        let isa = self.runtime.isa();
+        let accu = self.runtime.read_accu();
+        let mut effects = Vec::new();
+        if accu != old_accu {
+            effects.push(format!("accu: {} -> {}", describe(old_accu, isa), describe(accu, isa)));
+        }
+        if let Some((addr, old)) = written {
+            let val = self.runtime.read_mem(addr);
+            if old != val {
+                effects.push(format!("{}: {} -> {}", self.name_of(addr), describe(old, isa), describe(val, isa)));
+            }
+        }
+        if let Some(flags) = self.runtime.read_flags() {
+            effects.push(flags.to_string());
+        }
+        if self.runtime.halt {
+            effects.push("halted".to_owned());
+        }
+        if effects.is_empty() {
+            effects.push("no changes".to_owned());
+        }
+        format!("{:14} {}", instr, effects.join(", "))
+    }
+
+    fn name_of(&self, addr: MimaAddress) -> String {
+        self.names.iter()
+            .find(|(_, a)| **a == addr)
+            .map_or(format!("{:#07x}", addr), |(name, _)| format!("{} ({:#x})", name, addr))
+    }
+
+    fn print_state(&self) {
+        let accu = self.runtime.read_accu();
+        println!("Accumulator: {} IAR: {:#07x}", describe(accu, self.runtime.isa()), self.runtime.read_iar());
+    }
+
+    fn print_vars(&self) {
+        for (name, addr) in &self.names {
+            println!("{} ({:#07x}) = {}", name, addr, describe(self.runtime.read_mem(*addr), self.runtime.isa()));
+        }
+    }
+
+    fn print_mem(&self, addr: &str, count: &str) {
+        let start = self.names.get(addr).copied().or_else(|| parse_mima_number(addr).ok());
+        match (start, count.parse::<u32>()) {
+            (Some(start), Ok(count)) => {
+                let end = start.saturating_add(count).min(self.runtime.isa().address_space());
+                let values: Vec<(MimaAddress, MimaValue)> = (start..end)
+                    .map(|addr| (addr, self.runtime.read_mem(addr)))
+                    .collect();
+                hexdump_rows(&values, self.runtime.isa()).iter().for_each(|row| println!("{}", row));
+            }
+            _ => eprintln!("Invalid address {} or count {}", addr, count)
+        }
+    }
+
+    fn set(&mut self, name: &str, value: &str) {
+        let value = match self.runtime.isa().parse_value(value) {
+            Ok(value) => value,
+            Err(_) => {
+                eprintln!("Invalid value {}", value);
+                return;
+            }
+        };
+        match self.address_of(name, true) {
+            Ok(addr) => {
+                self.runtime.write_mem(addr, value);
+                println!("{} = {}", self.name_of(addr), describe(value, self.runtime.isa()));
+            }
+            Err(error) => eprintln!("{}", error)
+        }
+    }
+}
+
+fn describe(val: MimaValue, isa: &InstructionSet) -> String {
+    if isa.is_negative(val) {
+        format!("{} ({:#x})", isa.to_signed(val), val)
+    } else {
+        val.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repl() -> Repl {
+        Repl::new(Runtime::new())
+    }
+
+    #[test]
+    fn reports_changes_of_the_accumulator_and_memory() {
+        let mut repl = repl();
+        assert!(repl.execute_line("LDC 5").unwrap().ends_with("accu: 0 -> 5"));
+        assert!(repl.execute_line("STV x").unwrap().ends_with("x (0x100): 0 -> 5"));
+        assert!(repl.execute_line("STV x").unwrap().ends_with("no changes"));
+        assert!(repl.execute_line("NOT").unwrap().ends_with("accu: 5 -> -6 (0xfffffa)"));
+        assert!(repl.execute_line("HALT").unwrap().ends_with("halted"));
+        assert!(repl.execute_line("LDC 1").is_err());
+    }
+
+    #[test]
+    fn places_variables_after_the_program() {
+        let mut repl = repl();
+        repl.execute_line("STV a").unwrap();
+        repl.execute_line("STV b").unwrap();
+        assert_eq!((repl.names["a"], repl.names["b"]), (0x100, 0x101));
+        repl.execute_line("JMP 0x1ff").unwrap();
+        repl.execute_line("LDC 7").unwrap();
+        repl.execute_line("STV c").unwrap();
+        assert_eq!(repl.names["c"], 0x201);
+        assert_eq!(repl.runtime.read_mem(0x201), 7);
+        assert_eq!(repl.execute_line("LDC 1").err().unwrap(),
+                   "The program has reached the variable at 0x201. Type :reset to start over");
+    }
+
+    #[test]
+    fn jumps_need_bound_names() {
+        let mut repl = repl();
+        assert_eq!(repl.execute_line("JMP loop").err().unwrap(), "Unknown label 'loop'. Define it with `loop:` first");
+        assert!(!repl.names.contains_key("loop"));
+        assert_eq!(repl.execute_line("loop:").unwrap(), "loop = 0x00000");
+        repl.execute_line("jmp loop").unwrap();
+        assert_eq!(repl.runtime.read_iar(), 0);
+    }
+}
@@ -0,0 +1,112 @@
+use mima_common::types::{MimaAddress, MimaValue};
+use mima_common::isa::InstructionSet;
+use mima_common::runtime::Runtime;
+use serde::Serialize;
+
+// machine readable summary of a finished run
+#[derive(Serialize)]
+pub struct RunReport {
+    termination: Termination,
+    steps: u64,
+    accu: ValueReport,
+    iar: MimaAddress,
+    addresses: Vec<AddressReport>,
+    assertion_failures: Vec<String>
+}
+
+#[derive(Serialize)]
+#[serde(tag = "reason", rename_all = "lowercase")]
+enum Termination {
+    Halt,
+    Error { message: String }
+}
+
+#[derive(Serialize)]
+struct ValueReport {
+    unsigned: MimaValue,
+    signed: i64,
+    hex: String
+}
+
+#[derive(Serialize)]
+struct AddressReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    address: MimaAddress,
+    #[serde(flatten)]
+    value: ValueReport
+}
+
+impl ValueReport {
+    fn new(val: MimaValue, isa: &InstructionSet) -> Self {
+        ValueReport {
+            unsigned: val,
+            signed: isa.to_signed(val),
+            hex: format!("{:#0width$x}", val, width = isa.value_bytes() as usize * 2 + 2)
+        }
+    }
+}
+
+impl RunReport {
+    // addresses are reported in order, with their symbol name if they were requested by name
+    pub fn new(runtime: &Runtime, addresses: &[(Option<String>, MimaAddress)], result: &Result<(), String>) -> Self {
+        let isa = runtime.isa();
+        RunReport {
+            termination: match result {
+                Ok(()) => Termination::Halt,
+                Err(message) => Termination::Error { message: message.clone() }
+            },
+            steps: runtime.read_steps(),
+            accu: ValueReport::new(runtime.read_accu(), isa),
+            iar: runtime.read_iar(),
+            addresses: addresses.iter()
+                .map(|(name, addr)| AddressReport {
+                    name: name.clone(),
+                    address: *addr,
+                    value: ValueReport::new(runtime.read_mem(*addr), isa)
+                })
+                .collect(),
+            assertion_failures: runtime.assertion_failures().to_vec()
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("run reports can always be serialised")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mima_common::instructions::{Instruction, Opcode};
+    use serde_json::{json, Value};
+
+    fn report(result: Result<(), String>) -> Value {
+        let isa = InstructionSet::default();
+        let memory = [(Opcode::LDV, 2), (Opcode::HALT, 0)].iter()
+            .map(|(opcode, arg)| isa.encode(&Instruction { opcode: *opcode, arg: *arg }).unwrap())
+            .chain([0xfffffe])
+            .collect();
+        let mut runtime = Runtime::with_memory(memory);
+        runtime.run().unwrap();
+        let addresses = [(Some("result".to_owned()), 2), (None, 3)];
+        serde_json::from_str(&RunReport::new(&runtime, &addresses, &result).to_json()).unwrap()
+    }
+
+    #[test]
+    fn halted_runs_report_their_values() {
+        let report = report(Ok(()));
+        assert_eq!(report["termination"], json!({ "reason": "halt" }));
+        assert_eq!(report["accu"], json!({ "unsigned": 0xfffffe, "signed": -2, "hex": "0xfffffe" }));
+        assert_eq!(report["addresses"], json!([
+            { "name": "result", "address": 2, "unsigned": 0xfffffe, "signed": -2, "hex": "0xfffffe" },
+            { "address": 3, "unsigned": 0, "signed": 0, "hex": "0x000000" }
+        ]));
+    }
+
+    #[test]
+    fn errors_report_their_message() {
+        let report = report(Err("Exceeded the step limit".to_owned()));
+        assert_eq!(report["termination"], json!({ "reason": "error", "message": "Exceeded the step limit" }));
+    }
+}
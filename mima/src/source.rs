@@ -0,0 +1,96 @@
+use mima_common::types::parse_mima_number;
+
+// a whitespace separated word of an assembly source and the (zero based) column it starts at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub text: String,
+    pub column: usize
+}
+
+// a line of an assembly source split the way the assembler reads it:
+// everything from the first word starting with ';' is a comment
+#[derive(Debug, Clone)]
+pub struct SourceLine {
+    pub number: usize,
+    pub tokens: Vec<Token>,
+    pub comment: Option<Token>
+}
+
+pub fn scan(source: &str) -> Vec<SourceLine> {
+    source.lines().enumerate().map(|(index, line)| {
+        let mut tokens = Vec::new();
+        let mut comment = None;
+        for token in words(line) {
+            if token.text.starts_with(';') {
+                // the comment keeps its spacing
+                let start = line.char_indices().nth(token.column).map_or(line.len(), |(offset, _)| offset);
+                comment = Some(Token { text: line[start..].trim_end().to_owned(), column: token.column });
+                break;
+            }
+            tokens.push(token);
+        }
+        SourceLine { number: index + 1, tokens, comment }
+    }).collect()
+}
+
+fn words(line: &str) -> Vec<Token> {
+    let mut words = Vec::new();
+    let mut current: Option<Token> = None;
+    for (column, c) in line.chars().enumerate() {
+        if c.is_whitespace() {
+            words.extend(current.take());
+        } else {
+            current.get_or_insert(Token { text: String::new(), column }).text.push(c);
+        }
+    }
+    words.extend(current);
+    words
+}
+
+impl SourceLine {
+    // the label declared on this line
+    pub fn label(&self) -> Option<&Token> {
+        self.tokens.first().filter(|token| token.text.ends_with(':'))
+    }
+
+    pub fn directive(&self) -> Option<&Token> {
+        self.tokens.first().filter(|token| token.text.starts_with('.'))
+    }
+
+    pub fn mnemonic(&self) -> Option<&Token> {
+        self.tokens.first().filter(|token| !token.text.ends_with(':') && !token.text.starts_with('.'))
+    }
+
+    pub fn operand(&self) -> Option<&Token> {
+        self.mnemonic().and(self.tokens.get(1))
+    }
+
+    // names used as addresses on this line: the operand of an instruction
+    // and the location and target of an assertion
+    pub fn name_references(&self) -> Vec<&Token> {
+        let candidates: Vec<&Token> = match self.directive().map(|token| token.text.as_str()) {
+            Some(".expect") => self.tokens.get(1).into_iter().collect(),
+            Some(".assert_at") => self.tokens.iter().skip(1).take(2).collect(),
+            Some(_) => Vec::new(),
+            None => self.operand().into_iter().collect()
+        };
+        candidates.into_iter()
+            .filter(|token| token.text != "accu" && parse_mima_number(&token.text).is_err())
+            .collect()
+    }
+
+    pub fn token_at(&self, column: usize) -> Option<&Token> {
+        self.tokens.iter().find(|token| column >= token.column && column <= token.column + token.text.chars().count())
+    }
+}
+
+impl Token {
+    // the name a token refers to, without the colon of a label declaration
+    pub fn name(&self) -> &str {
+        self.text.strip_suffix(':').unwrap_or(&self.text)
+    }
+
+    pub fn end(&self) -> usize {
+        self.column + self.text.chars().count()
+    }
+}
@@ -0,0 +1,135 @@
+use mima_common::types::{MimaAddress, parse_mima_number};
+use mima_common::isa::InstructionSet;
+use mima_common::assertions::Assertion;
+use std::num::ParseIntError;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// mapping of label and template variable names to the addresses assigned by the assembler,
+// along with the assertions of the program
+#[derive(Debug, Default, Clone)]
+pub struct SymbolTable {
+    symbols: HashMap<String, MimaAddress>,
+    assertions: Vec<Assertion>
+}
+
+impl From<HashMap<String, MimaAddress>> for SymbolTable {
+    fn from(symbols: HashMap<String, MimaAddress>) -> Self {
+        SymbolTable { symbols, assertions: Vec::new() }
+    }
+}
+
+impl SymbolTable {
+
+    pub fn with_assertions(mut self, assertions: Vec<Assertion>) -> Self {
+        self.assertions = assertions;
+        self
+    }
+
+    pub fn assertions(&self) -> &[Assertion] {
+        &self.assertions
+    }
+
+    pub fn resolve(&self, name: &str) -> Option<MimaAddress> {
+        self.symbols.get(name).copied()
+    }
+
+    // parses a numeric address or resolves a symbol name
+    pub fn parse_addr(&self, s: &str, isa: &InstructionSet) -> Result<MimaAddress, ParseIntError> {
+        parse_mima_number(s)
+            .map(|addr| isa.coerce_address(addr))
+            .or_else(|e| self.resolve(s).ok_or(e))
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.symbols.keys().map(|s| s.as_str())
+    }
+
+    // all symbols ordered by address, then by name
+    pub fn sorted(&self) -> Vec<(&str, MimaAddress)> {
+        let mut entries: Vec<(&str, MimaAddress)> = self.symbols.iter()
+            .map(|(name, addr)| (name.as_str(), *addr))
+            .collect();
+        entries.sort_by(|(n1, a1), (n2, a2)| a1.cmp(a2).then(n1.cmp(n2)));
+        entries
+    }
+
+    // symbol files contain one `<name> <addr>` pair per line, followed by the assertions
+    pub fn read_from(path: &Path, isa: &InstructionSet) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read symbol file: {}", e))?;
+        Self::parse(&content, isa)
+    }
+
+    // addresses outside of the address space of the instruction set are rejected
+    fn parse(content: &str, isa: &InstructionSet) -> Result<Self, String> {
+        let mut symbols = HashMap::new();
+        let mut assertions = Vec::new();
+        for (line_num, line) in content.lines().enumerate() {
+            if line.starts_with('.') {
+                let assertion = line.parse::<Assertion>()
+                    .map_err(|e| format!("Symbol file line {}: {}", line_num + 1, e))?;
+                assertions.push(assertion);
+                continue;
+            }
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            match tokens.as_slice() {
+                [] => continue,
+                [name, addr] => {
+                    let addr = parse_mima_number(addr).ok()
+                        .filter(|addr| *addr <= isa.max_address())
+                        .ok_or(format!("Symbol file line {}: Invalid address '{}'", line_num + 1, addr))?;
+                    symbols.insert((*name).to_owned(), addr);
+                }
+                _ => return Err(format!("Symbol file line {}: Expected '<name> <address>'",
+                                        line_num + 1))
+            }
+        }
+        Ok(SymbolTable { symbols, assertions })
+    }
+
+    pub fn write_to(&self, path: &Path) -> Result<(), String> {
+        let mut content: String = self.sorted().iter()
+            .map(|(name, addr)| format!("{} {:#x}\n", name, addr))
+            .collect();
+        for assertion in &self.assertions {
+            content.push_str(&format!("{}\n", assertion));
+        }
+        fs::write(path, content)
+            .map_err(|e| format!("Could not write symbol file: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // only the widths matter for addresses
+    fn wide_isa() -> InstructionSet {
+        let mut isa = InstructionSet::default();
+        isa.address_bits = 24;
+        isa.value_bits = 32;
+        isa
+    }
+
+    #[test]
+    fn keeps_addresses_of_wide_instruction_sets() {
+        let symbols = SymbolTable::parse("top 0xfffffe\nloop 0x4\n", &wide_isa()).unwrap();
+        assert_eq!(symbols.resolve("top"), Some(0xfffffe));
+        assert_eq!(symbols.resolve("loop"), Some(4));
+    }
+
+    #[test]
+    fn rejects_addresses_outside_of_memory() {
+        let result = SymbolTable::parse("loop 0x4\ntop 0x100000\n", &InstructionSet::default());
+        assert_eq!(result.unwrap_err(), "Symbol file line 2: Invalid address '0x100000'");
+    }
+
+    #[test]
+    fn reads_assertions() {
+        let symbols = SymbolTable::parse("x 0x3\n.expect 0x3 == 0x5 ; x == 5\n", &InstructionSet::default()).unwrap();
+        assert_eq!(symbols.assertions().len(), 1);
+        assert_eq!(symbols.assertions()[0].description, "x == 5");
+    }
+}
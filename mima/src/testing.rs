@@ -0,0 +1,284 @@
+use mima_common::types::{MimaAddress, MimaValue};
+use mima_common::instructions::Isa;
+use mima_common::isa::InstructionSet;
+use mima_common::runtime::Runtime;
+use crate::assembly::assemble;
+use crate::symbols::SymbolTable;
+use crate::read_mima_file;
+use strum_macros::{EnumString, EnumVariantNames};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::Path;
+use std::str::FromStr;
+
+pub const DEFAULT_MAX_STEPS: u64 = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, EnumVariantNames)]
+#[strum(serialize_all = "lowercase")]
+pub enum ReportFormat {
+    Text,
+    Tap,
+    Junit
+}
+
+// a test specification: the program under test and the cases it is run against.
+// Memory locations are given as labels, template variables or numeric addresses.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TestSpec {
+    // assembly source (.asm) or binary, relative to the spec file
+    program: String,
+    // symbol file for binaries, relative to the spec file
+    symbols: Option<String>,
+    isa: Option<String>,
+    isa_file: Option<String>,
+    #[serde(default)]
+    absolute: bool,
+    max_steps: Option<u64>,
+    #[serde(rename = "case")]
+    cases: Vec<TestCase>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TestCase {
+    name: String,
+    max_steps: Option<u64>,
+    #[serde(default)]
+    set: BTreeMap<String, i64>,
+    #[serde(default)]
+    expect: BTreeMap<String, i64>,
+    accu: Option<i64>
+}
+
+enum Outcome {
+    Pass,
+    // the mismatches between expected and actual values
+    Fail(Vec<String>),
+    Error(String)
+}
+
+struct CaseResult {
+    name: String,
+    steps: u64,
+    outcome: Outcome
+}
+
+// runs all cases of a test specification, prints a report and fails if any case did not pass
+pub fn run_tests(spec_path: &Path, content: &str, format: ReportFormat) -> Result<(), String> {
+    let spec: TestSpec = toml::from_str(content)
+        .map_err(|e| format!("Invalid test specification: {}", e))?;
+    let base = spec_path.parent().unwrap_or_else(|| Path::new("."));
+    let isa = match (&spec.isa_file, &spec.isa) {
+        (Some(file), _) => InstructionSet::read_from(&base.join(file))?,
+        (None, Some(name)) => InstructionSet::from(Isa::from_str(name)
+            .map_err(|_e| format!("Unknown instruction set '{}'", name))?),
+        (None, None) => InstructionSet::default()
+    };
+    let (program, symbols) = load_program(&spec, base, &isa)?;
+
+    let results: Vec<CaseResult> = spec.cases.iter()
+        .map(|case| run_case(case, &program, &symbols, &isa,
+                             case.max_steps.or(spec.max_steps).unwrap_or(DEFAULT_MAX_STEPS)))
+        .collect();
+    let report = match format {
+        ReportFormat::Text => text_report(&results),
+        ReportFormat::Tap => tap_report(&results),
+        ReportFormat::Junit => junit_report(&spec_path.display().to_string(), &results)
+    };
+    print!("{}", report);
+
+    let failed = results.iter().filter(|r| !matches!(r.outcome, Outcome::Pass)).count();
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(format!("{} of {} test cases failed", failed, results.len()))
+    }
+}
+
+// assembles the program if it is a source file, otherwise reads it as a binary
+fn load_program(spec: &TestSpec, base: &Path, isa: &InstructionSet) -> Result<(Vec<MimaValue>, SymbolTable), String> {
+    let path = base.join(&spec.program);
+    if path.extension().is_some_and(|ext| ext == "asm") {
+        let source = fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read program {}: {}", path.display(), e))?;
+        let program = assemble(source, spec.absolute, isa)?;
+        let values = program.instructions.iter()
+            .map(|instr| isa.encode(instr))
+            .collect::<Result<Vec<MimaValue>, String>>()?;
+        Ok((values, program.symbols))
+    } else {
+        let mut file = File::open(&path)
+            .map_err(|e| format!("Could not open program {}: {}", path.display(), e))?;
+        let symbols = match &spec.symbols {
+            Some(symbols) => SymbolTable::read_from(&base.join(symbols), isa)?,
+            None => SymbolTable::default()
+        };
+        Ok((read_mima_file(&mut file, isa)?, symbols))
+    }
+}
+
+fn run_case(case: &TestCase, program: &[MimaValue], symbols: &SymbolTable,
+            isa: &InstructionSet, max_steps: u64) -> CaseResult {
+    let mut runtime = Runtime::with_instructions(&program.to_vec())
+        .with_isa(isa.clone())
+        .with_assertions(symbols.assertions().to_vec());
+    let outcome = execute_case(case, &mut runtime, symbols, isa, max_steps)
+        .unwrap_or_else(Outcome::Error);
+    CaseResult { name: case.name.clone(), steps: runtime.read_steps(), outcome }
+}
+
+fn execute_case(case: &TestCase, runtime: &mut Runtime, symbols: &SymbolTable,
+                isa: &InstructionSet, max_steps: u64) -> Result<Outcome, String> {
+    let resolve = |name: &str| symbols.parse_addr(name, isa)
+        .map_err(|_e| format!("Unknown address or symbol '{}'", name));
+    for (name, value) in &case.set {
+        let addr = resolve(name)?;
+        runtime.write_mem(addr, isa.from_signed(*value));
+    }
+    let expected = case.expect.iter()
+        .map(|(name, value)| resolve(name).map(|addr| (name, addr, *value)))
+        .collect::<Result<Vec<(&String, MimaAddress, i64)>, String>>()?;
+
+    while !runtime.halt {
+        if runtime.read_steps() >= max_steps {
+            return Err(format!("Program did not halt within {} steps", max_steps));
+        }
+        runtime.step()?;
+    }
+
+    let describe = |val: MimaValue| format!("{} ({:#x})", isa.to_signed(val), val);
+    let mut mismatches = runtime.assertion_failures().to_vec();
+    if let Some(accu) = case.accu {
+        let (expected, actual) = (isa.from_signed(accu), runtime.read_accu());
+        if expected != actual {
+            mismatches.push(format!("accu: expected {}, got {}", describe(expected), describe(actual)));
+        }
+    }
+    for (name, addr, value) in expected {
+        let (expected, actual) = (isa.from_signed(value), runtime.read_mem(addr));
+        if expected != actual {
+            mismatches.push(format!("{}: expected {}, got {}", name, describe(expected), describe(actual)));
+        }
+    }
+    Ok(if mismatches.is_empty() { Outcome::Pass } else { Outcome::Fail(mismatches) })
+}
+
+fn text_report(results: &[CaseResult]) -> String {
+    let mut report = String::new();
+    for result in results {
+        match &result.outcome {
+            Outcome::Pass => report.push_str(&format!("PASS {} ({} steps)\n", result.name, result.steps)),
+            Outcome::Fail(mismatches) => {
+                report.push_str(&format!("FAIL {} ({} steps)\n", result.name, result.steps));
+                mismatches.iter().for_each(|m| report.push_str(&format!("    {}\n", m)));
+            }
+            Outcome::Error(message) => report.push_str(&format!("ERROR {}: {}\n", result.name, message))
+        }
+    }
+    let passed = results.iter().filter(|r| matches!(r.outcome, Outcome::Pass)).count();
+    report.push_str(&format!("\n{} passed, {} failed\n", passed, results.len() - passed));
+    report
+}
+
+fn tap_report(results: &[CaseResult]) -> String {
+    let mut report = format!("TAP version 13\n1..{}\n", results.len());
+    for (i, result) in results.iter().enumerate() {
+        let messages = match &result.outcome {
+            Outcome::Pass => {
+                report.push_str(&format!("ok {} - {}\n", i + 1, result.name));
+                continue;
+            }
+            Outcome::Fail(mismatches) => mismatches.clone(),
+            Outcome::Error(message) => vec![message.clone()]
+        };
+        report.push_str(&format!("not ok {} - {}\n  ---\n  message: |\n", i + 1, result.name));
+        messages.iter().for_each(|m| report.push_str(&format!("    {}\n", m)));
+        report.push_str("  ...\n");
+    }
+    report
+}
+
+fn junit_report(suite: &str, results: &[CaseResult]) -> String {
+    let failures = results.iter().filter(|r| matches!(r.outcome, Outcome::Fail(_))).count();
+    let errors = results.iter().filter(|r| matches!(r.outcome, Outcome::Error(_))).count();
+    let mut report = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                              <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\">\n",
+                             xml_escape(suite), results.len(), failures, errors);
+    for result in results {
+        report.push_str(&format!("  <testcase name=\"{}\">", xml_escape(&result.name)));
+        match &result.outcome {
+            Outcome::Pass => {}
+            Outcome::Fail(mismatches) => report.push_str(&format!(
+                "\n    <failure message=\"{} values differ\">{}</failure>\n  ",
+                mismatches.len(), xml_escape(&mismatches.join("\n")))),
+            Outcome::Error(message) => report.push_str(&format!(
+                "\n    <error message=\"{}\"/>\n  ", xml_escape(message)))
+        }
+        report.push_str("</testcase>\n");
+    }
+    report.push_str("</testsuite>\n");
+    report
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUM: &str = "LDV a\nADD b\nSTV sum\nHALT\n";
+
+    fn run(source: &str, case: &str, max_steps: u64) -> CaseResult {
+        let isa = InstructionSet::default();
+        let program = assemble(source.to_owned(), false, &isa).unwrap();
+        let values: Vec<MimaValue> = program.instructions.iter().map(|i| isa.encode(i).unwrap()).collect();
+        let case: TestCase = toml::from_str(case).unwrap();
+        run_case(&case, &values, &program.symbols, &isa, max_steps)
+    }
+
+    #[test]
+    fn passes_matching_values() {
+        let result = run(SUM, "name = \"sum\"\nset = { a = 2, b = -5 }\nexpect = { sum = -3 }\naccu = -3\n", 10);
+        assert!(matches!(result.outcome, Outcome::Pass));
+        assert_eq!(result.steps, 4);
+    }
+
+    #[test]
+    fn reports_mismatches() {
+        let result = run(SUM, "name = \"sum\"\nset = { a = 2, b = 2 }\nexpect = { sum = 5 }\n", 10);
+        match result.outcome {
+            Outcome::Fail(mismatches) => assert_eq!(mismatches, ["sum: expected 5 (0x5), got 4 (0x4)"]),
+            _ => panic!("expected a failure")
+        }
+    }
+
+    #[test]
+    fn stops_at_the_step_limit() {
+        let result = run("loop:\nJMP loop\n", "name = \"loop\"\n", 50);
+        match result.outcome {
+            Outcome::Error(message) => assert_eq!(message, "Program did not halt within 50 steps"),
+            _ => panic!("expected an error")
+        }
+    }
+
+    #[test]
+    fn reports_unknown_symbols() {
+        let result = run(SUM, "name = \"sum\"\nexpect = { total = 4 }\n", 10);
+        assert!(matches!(result.outcome, Outcome::Error(message) if message.contains("'total'")));
+    }
+
+    #[test]
+    fn escapes_junit_reports() {
+        let results = [CaseResult { name: "a<b".to_owned(), steps: 1, outcome: Outcome::Error("\"x\" & y".to_owned()) }];
+        let report = junit_report("suite", &results);
+        assert!(report.contains("<testcase name=\"a&lt;b\">"));
+        assert!(report.contains("<error message=\"&quot;x&quot; &amp; y\"/>"));
+    }
+}
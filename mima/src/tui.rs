@@ -0,0 +1,372 @@
+use crate::debugger::{Debugger, OutputLine};
+use crate::memdump::{as_char, HEXDUMP_ROW_LENGTH};
+use mima_common::types::{MimaAddress, MimaValue};
+use mima_common::instructions::Opcode;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+
+// instructions executed between two redraws while the program is running
+const STEPS_PER_FRAME: u32 = 10_000;
+const LOG_LENGTH: usize = 1000;
+
+// full screen front-end of the debugger: the state of the machine is shown in panes
+// and commands are entered in the line at the bottom
+pub struct Tui<'a> {
+    debugger: Debugger<'a>,
+    input: String,
+    history: Vec<String>,
+    history_pos: usize,
+    log: Vec<OutputLine>,
+    memory_start: MimaAddress,
+    // memory and accumulator as they were before the last command, to highlight changes
+    previous_memory: Vec<MimaValue>,
+    previous_accu: MimaValue,
+    // output of the program's devices, shown in the log instead of being printed over the interface
+    console: Receiver<u8>,
+    // whether the last log line is console output that is continued by the next character
+    console_open: bool,
+    // the debugger left the break state, so the instruction it stopped at runs before breaking again
+    resuming: bool,
+    quit: bool
+}
+
+// sends everything written to it to the log of the interface
+struct ConsoleWriter(Sender<u8>);
+
+impl Write for ConsoleWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        buf.iter().for_each(|byte| {
+            let _ = self.0.send(*byte);
+        });
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> Tui<'a> {
+    pub fn new(mut debugger: Debugger<'a>) -> Self {
+        let (sender, console) = mpsc::channel();
+        debugger.set_device_output(move || Box::new(ConsoleWriter(sender.clone())));
+        let previous_memory = debugger.runtime().mem_iter().copied().collect();
+        let previous_accu = debugger.runtime().read_accu();
+        Tui {
+            debugger,
+            input: String::new(),
+            history: Vec::new(),
+            history_pos: 0,
+            log: Vec::new(),
+            memory_start: 0,
+            previous_memory,
+            previous_accu,
+            console,
+            console_open: false,
+            resuming: false,
+            quit: false
+        }
+    }
+
+    pub fn run(mut self) -> Result<(), String> {
+        let mut terminal = ratatui::init();
+        let result = self.event_loop(&mut terminal);
+        ratatui::restore();
+        result
+    }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> Result<(), String> {
+        self.log_line("Enter debugger commands below, `help` lists them. Esc pauses execution, Ctrl-C quits.", false);
+        while !self.quit {
+            let lines = self.debugger.output().take();
+            if !lines.is_empty() {
+                self.console_open = false;
+            }
+            self.log.extend(lines);
+            self.read_console();
+            if self.log.len() > LOG_LENGTH {
+                self.log.drain(..self.log.len() - LOG_LENGTH);
+            }
+            terminal.draw(|frame| self.draw(frame)).map_err(|e| e.to_string())?;
+
+            let running = !self.debugger.is_stopped() && !self.debugger.runtime().halt;
+            if running {
+                self.run_slice();
+                if event::poll(Duration::ZERO).map_err(|e| e.to_string())? {
+                    self.handle_event(event::read().map_err(|e| e.to_string())?);
+                }
+            } else {
+                self.handle_event(event::read().map_err(|e| e.to_string())?);
+            }
+        }
+        Ok(())
+    }
+
+    // executes instructions until the debugger stops or the frame is over
+    fn run_slice(&mut self) {
+        for _ in 0..STEPS_PER_FRAME {
+            if !std::mem::take(&mut self.resuming) && self.debugger.should_break() {
+                self.debugger.enter_break();
+                return;
+            }
+            if let Err(error) = self.debugger.step_checked() {
+                self.read_console();
+                self.log_line(error, true);
+                self.debugger.enter_break();
+                return;
+            }
+            if self.debugger.runtime().halt {
+                self.read_console();
+                self.log_line("Program halted. `restart` runs it again, Ctrl-C quits.", false);
+                return;
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        let key = match event {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => return
+        };
+        match key {
+            KeyEvent { code: KeyCode::Char('c'), modifiers: KeyModifiers::CONTROL, .. } => self.quit = true,
+            KeyEvent { code: KeyCode::Esc, .. } => self.debugger.pause(),
+            KeyEvent { code: KeyCode::Enter, .. } => self.submit(),
+            KeyEvent { code: KeyCode::Backspace, .. } => {
+                self.input.pop();
+            }
+            KeyEvent { code: KeyCode::Up, .. } if self.history_pos > 0 => {
+                self.history_pos -= 1;
+                self.input = self.history[self.history_pos].clone();
+            }
+            KeyEvent { code: KeyCode::Down, .. } if self.history_pos < self.history.len() => {
+                self.history_pos += 1;
+                self.input = self.history.get(self.history_pos).cloned().unwrap_or_default();
+            }
+            KeyEvent { code: KeyCode::PageUp, .. } => {
+                self.memory_start = self.memory_start.saturating_sub(8 * HEXDUMP_ROW_LENGTH as MimaAddress);
+            }
+            KeyEvent { code: KeyCode::PageDown, .. } => {
+                let isa = self.debugger.runtime().isa();
+                self.memory_start = (self.memory_start + 8 * HEXDUMP_ROW_LENGTH as MimaAddress)
+                    .min(isa.max_address() / HEXDUMP_ROW_LENGTH as MimaAddress * HEXDUMP_ROW_LENGTH as MimaAddress);
+            }
+            KeyEvent { code: KeyCode::Char(c), .. } => self.input.push(c),
+            _ => {}
+        }
+    }
+
+    fn submit(&mut self) {
+        let input = std::mem::take(&mut self.input);
+        if !input.is_empty() && self.history.last() != Some(&input) {
+            self.history.push(input.clone());
+        }
+        self.history_pos = self.history.len();
+        self.log_line(format!("> {}", input), false);
+
+        let args: Vec<&str> = input.split_whitespace().collect();
+        match args.as_slice() {
+            // commands that only concern the TUI
+            ["view", addr] => match self.debugger.parse_addr(addr) {
+                Ok(addr) => self.memory_start = addr - addr % HEXDUMP_ROW_LENGTH as MimaAddress,
+                Err(_) => self.log_line(format!("Invalid address: {}", addr), true)
+            },
+            ["quit"] => self.quit = true,
+            _ => {
+                let runtime = self.debugger.runtime();
+                self.previous_memory = runtime.mem_iter().copied().collect();
+                self.previous_accu = runtime.read_accu();
+                let was_stopped = self.debugger.is_stopped();
+                self.debugger.execute(&input);
+                // as on the command line, `step` and `continue` run the current instruction first
+                self.resuming = was_stopped && !self.debugger.is_stopped();
+                // `halt` leaves the debugger, as it does on the command line
+                if args.as_slice() == ["halt"] {
+                    self.quit = true;
+                }
+            }
+        }
+    }
+
+    fn log_line<S: Into<String>>(&mut self, text: S, error: bool) {
+        self.log.push(OutputLine { text: text.into(), error });
+        self.console_open = false;
+    }
+
+    // appends the characters the program printed since the last frame to the log
+    fn read_console(&mut self) {
+        let bytes: Vec<u8> = self.console.try_iter().collect();
+        for byte in bytes {
+            if byte == b'\n' {
+                self.console_open = false;
+                continue;
+            }
+            if !self.console_open {
+                self.log_line(String::new(), false);
+                self.console_open = true;
+            }
+            if let Some(line) = self.log.last_mut() {
+                line.text.push(as_char(byte as MimaValue));
+            }
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, log, input] = Layout::vertical([
+            Constraint::Min(10), Constraint::Length(10), Constraint::Length(1)
+        ]).areas(frame.area());
+        let [left, right] = Layout::horizontal([Constraint::Length(36), Constraint::Min(0)]).areas(main);
+        let [registers, watches] = Layout::vertical([Constraint::Length(15), Constraint::Min(3)]).areas(left);
+        let [disassembly, memory] = Layout::vertical([Constraint::Percentage(55), Constraint::Min(4)]).areas(right);
+
+        frame.render_widget(Paragraph::new(self.register_lines()).block(Block::bordered().title(" Registers ")), registers);
+        frame.render_widget(Paragraph::new(self.watch_lines()).block(Block::bordered().title(" Watch ")), watches);
+        frame.render_widget(Paragraph::new(self.disassembly_lines(disassembly))
+                                .block(Block::bordered().title(" Disassembly ")), disassembly);
+        frame.render_widget(Paragraph::new(self.memory_lines(memory))
+                                .block(Block::bordered().title(" Memory (PgUp/PgDn, view <addr>) ")), memory);
+        frame.render_widget(Paragraph::new(self.log_lines(log)).block(Block::bordered().title(" Output ")), log);
+
+        let prompt = format!("> {}", self.input);
+        frame.set_cursor_position((input.x + prompt.len() as u16, input.y));
+        frame.render_widget(Paragraph::new(prompt), input);
+    }
+
+    fn register_lines(&self) -> Vec<Line<'static>> {
+        let runtime = self.debugger.runtime();
+        let isa = runtime.isa();
+        let accu = runtime.read_accu();
+        let accu_style = if accu != self.previous_accu { changed_style() } else { Style::default() };
+        let ir = runtime.read_ir();
+        let state = if runtime.halt {
+            "halted"
+        } else if self.debugger.is_stopped() {
+            "stopped"
+        } else {
+            "running"
+        };
+        let mut lines = vec![
+            Line::from(vec![label("Accu "), Span::styled(format!("{:#08x} {}", accu, isa.to_signed(accu)), accu_style)]),
+            Line::from(vec![label("IAR  "), Span::raw(format!("{:#07x}", runtime.read_iar()))]),
+            Line::from(vec![label("IR   "), Span::raw(format!("{:#08x} {}", ir, isa.decode(ir)
+                .map_or("???".to_owned(), |i| isa.format(&i))))]),
+            Line::from(vec![label("SAR  "), Span::raw(format!("{:#07x}", runtime.read_sar()))]),
+            Line::from(vec![label("SDR  "), Span::raw(format!("{:#08x}", runtime.read_sdr()))]),
+            Line::from(vec![label("X    "), Span::raw(format!("{:#08x}", runtime.read_x()))]),
+            Line::from(vec![label("Y    "), Span::raw(format!("{:#08x}", runtime.read_y()))]),
+            Line::from(vec![label("Z    "), Span::raw(format!("{:#08x}", runtime.read_z()))]),
+        ];
+        if isa.supports(Opcode::CALL) || isa.supports(Opcode::LDSP) {
+            lines.push(Line::from(vec![label("SP   "), Span::raw(format!("{:#07x}", runtime.read_sp()))]));
+        }
+        if let Some(flags) = runtime.read_flags() {
+            lines.push(Line::from(vec![label("Flags"), Span::raw(format!(" {}", flags))]));
+        }
+        if let Some(controller) = runtime.interrupts() {
+            let pending = controller.pending().count();
+            lines.push(Line::from(vec![label("Intr "), Span::raw(format!("{} ({} pending)",
+                if controller.enabled { "enabled" } else { "disabled" }, pending))]));
+        }
+        lines.push(Line::from(vec![label("Steps"), Span::raw(format!(" {}", runtime.read_steps()))]));
+        lines.push(Line::from(vec![label("State"), Span::raw(format!(" {}", state))]));
+        lines
+    }
+
+    fn watch_lines(&self) -> Vec<Line<'static>> {
+        let values = self.debugger.display_values();
+        if values.is_empty() {
+            return vec![Line::styled("`display <addr>` adds watches", Style::default().fg(Color::DarkGray))];
+        }
+        values.into_iter().map(Line::from).collect()
+    }
+
+    fn disassembly_lines(&self, area: Rect) -> Vec<Line<'static>> {
+        let runtime = self.debugger.runtime();
+        let isa = runtime.isa();
+        let iar = runtime.read_iar();
+        let mut labels: HashMap<MimaAddress, &str> = HashMap::new();
+        for (name, addr) in self.debugger.symbols().sorted() {
+            labels.entry(addr).or_insert(name);
+        }
+        let height = area.height.saturating_sub(2) as MimaAddress;
+        let start = iar.saturating_sub(height / 3);
+        (start..start.saturating_add(height).min(isa.address_space()))
+            .map(|addr| {
+                let val = runtime.read_mem(addr);
+                let instr = isa.decode(val).map_or("???".to_owned(), |i| isa.format(&i));
+                let marker = if self.debugger.breakpoints().contains(&addr) {
+                    Span::styled("●", Style::default().fg(Color::Red))
+                } else {
+                    Span::raw(" ")
+                };
+                let text = format!("{} {:#07x} {:10} {:#08x}  {}",
+                                   if addr == iar { "->" } else { "  " }, addr,
+                                   labels.get(&addr).copied().unwrap_or(""), val, instr);
+                let style = if addr == iar {
+                    Style::default().fg(Color::Black).bg(Color::Cyan)
+                } else {
+                    Style::default()
+                };
+                Line::from(vec![marker, Span::styled(text, style)])
+            })
+            .collect()
+    }
+
+    fn memory_lines(&self, area: Rect) -> Vec<Line<'static>> {
+        let runtime = self.debugger.runtime();
+        let isa = runtime.isa();
+        let digits = isa.value_bytes() as usize * 2;
+        let memory = runtime.mem_iter().as_slice();
+        let rows = area.height.saturating_sub(2) as MimaAddress;
+        (0..rows)
+            .map(|row| self.memory_start.saturating_add(row * HEXDUMP_ROW_LENGTH as MimaAddress))
+            .take_while(|start| *start < isa.address_space())
+            .map(|start| {
+                let mut spans = vec![Span::styled(format!("{:#07x}: ", start), label_style())];
+                let mut chars = String::new();
+                for addr in start..start.saturating_add(HEXDUMP_ROW_LENGTH as MimaAddress).min(isa.address_space()) {
+                    let val = runtime.read_mem(addr);
+                    let previous = self.previous_memory.get(addr as usize).copied().unwrap_or(0);
+                    let current = memory.get(addr as usize).copied().unwrap_or(0);
+                    let style = if previous != current { changed_style() } else { Style::default() };
+                    spans.push(Span::styled(format!("{:0digits$x}", val, digits = digits), style));
+                    spans.push(Span::raw(" "));
+                    chars.push(as_char(val));
+                }
+                spans.push(Span::raw(format!("|{}|", chars)));
+                Line::from(spans)
+            })
+            .collect()
+    }
+
+    fn log_lines(&self, area: Rect) -> Vec<Line<'static>> {
+        let height = area.height.saturating_sub(2) as usize;
+        self.log[self.log.len().saturating_sub(height)..].iter()
+            .map(|line| if line.error {
+                Line::styled(line.text.clone(), Style::default().fg(Color::Red))
+            } else {
+                Line::raw(line.text.clone())
+            })
+            .collect()
+    }
+}
+
+fn label(text: &'static str) -> Span<'static> {
+    Span::styled(text, label_style())
+}
+
+fn label_style() -> Style {
+    Style::default().fg(Color::Yellow)
+}
+
+fn changed_style() -> Style {
+    Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)
+}